@@ -89,8 +89,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     // Simulate replay with timeline effects
     println!("\n=== Timeline Replay ===");
-    for i in 0..terminal_content.len() {
-        let line = &terminal_content[i];
+    for (i, line) in terminal_content.iter().enumerate() {
         let time = i as f32 * 0.1;
         
         // Simulate timeline highlighting
@@ -37,6 +37,7 @@ fn test_pending_writes_counter() {
         ended_at: None,
         parent_session_id: None,
         branch_name: None,
+        ..Default::default()
     };
     
     // Store session (this will increment pending writes)
@@ -67,6 +68,7 @@ fn test_basic_backup() {
         ended_at: None,
         parent_session_id: None,
         branch_name: None,
+        ..Default::default()
     };
     
     storage.store_session(&session).unwrap();
@@ -114,6 +116,7 @@ fn test_concurrency_performance() {
                     ended_at: None,
                     parent_session_id: None,
                     branch_name: None,
+                    ..Default::default()
                 };
                 
                 storage.store_session(&session).unwrap();
@@ -1,6 +1,5 @@
-use timeloop_terminal::{Storage, SessionManager, EventRecorder};
+use timeloop_terminal::Storage;
 use std::thread;
-use std::time::Duration;
 
 /// Demonstration of storage performance and security improvements
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -39,6 +38,7 @@ fn test_pending_writes_counter() {
         ended_at: None,
         parent_session_id: None,
         branch_name: None,
+        ..Default::default()
     };
     
     // Store session (this will increment pending writes)
@@ -70,6 +70,7 @@ fn test_backup_encryption() {
         ended_at: None,
         parent_session_id: None,
         branch_name: None,
+        ..Default::default()
     };
     
     plain_storage.store_session(&session).unwrap();
@@ -95,6 +96,7 @@ fn test_backup_encryption() {
         ended_at: None,
         parent_session_id: None,
         branch_name: None,
+        ..Default::default()
     };
     
     encrypted_storage.store_session(&encrypted_session).unwrap();
@@ -164,6 +166,7 @@ fn test_concurrency_performance() {
                     ended_at: None,
                     parent_session_id: None,
                     branch_name: None,
+                    ..Default::default()
                 };
                 
                 storage.store_session(&session).unwrap();
@@ -218,6 +221,7 @@ mod tests {
             ended_at: None,
             parent_session_id: None,
             branch_name: None,
+            ..Default::default()
         };
         
         storage.store_session(&session).unwrap();
@@ -238,6 +242,7 @@ mod tests {
             ended_at: None,
             parent_session_id: None,
             branch_name: None,
+            ..Default::default()
         };
         
         encrypted_storage.store_session(&session).unwrap();
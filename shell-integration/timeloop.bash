@@ -0,0 +1,55 @@
+# TimeLoop shell integration for bash: marks prompts and commands with
+# OSC 133 and reports the working directory with OSC 7.
+# Replaces any existing DEBUG trap.
+
+__tl_urlencode() {
+    local LC_ALL=C s=$1 c i out=
+    for ((i = 0; i < ${#s}; i++)); do
+        c=${s:i:1}
+        case $c in
+            [a-zA-Z0-9./_~-]) out+=$c ;;
+            *) printf -v c '%%%02X' "'$c"; out+=$c ;;
+        esac
+    done
+    printf '%s' "$out"
+}
+
+__tl_status() {
+    __tl_last_status=$?
+    return $__tl_last_status
+}
+
+__tl_preexec() {
+    [ -n "$__tl_at_prompt" ] && [ -z "$COMP_LINE" ] || return 0
+    __tl_at_prompt=
+    # An empty line runs nothing but PROMPT_COMMAND
+    [ "$1" = __tl_status ] && return 0
+    __tl_ran=1
+    # A line left out of the history (HISTCONTROL, set +o history) doesn't
+    # move HISTCMD, and the terminal's echo of it is used instead
+    if [ "$HISTCMD" != "$__tl_histcmd" ]; then
+        printf '\033]133;C\007'
+        return 0
+    fi
+    local line
+    line=$(HISTTIMEFORMAT= builtin history 1)
+    [[ $line =~ ^\ *[0-9]+\*?\ +(.*)$ ]] && line=${BASH_REMATCH[1]}
+    printf '\033]133;C;cmdline_url=%s\007' "$(__tl_urlencode "$line")"
+}
+
+__tl_precmd() {
+    [ -n "$__tl_ran" ] && printf '\033]133;D;%s\007' "$__tl_last_status"
+    __tl_ran=
+    printf '\033]7;file://%s%s\007' "$HOSTNAME" "$(__tl_urlencode "$PWD")"
+    case $PS1 in
+        *'133;A'*) ;;
+        *) PS1='\[\033]133;A\007\]'$PS1'\[\033]133;B\007\]' ;;
+    esac
+    __tl_histcmd=$HISTCMD
+    __tl_at_prompt=1
+}
+
+# Drop trailing separators, which would make the combined command invalid
+PROMPT_COMMAND=${PROMPT_COMMAND%"${PROMPT_COMMAND##*[![:space:];]}"}
+PROMPT_COMMAND="__tl_status${PROMPT_COMMAND:+; $PROMPT_COMMAND}; __tl_precmd"
+trap '__tl_preexec "$BASH_COMMAND"' DEBUG
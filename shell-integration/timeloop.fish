@@ -0,0 +1,24 @@
+# TimeLoop shell integration for fish: marks prompts and commands with
+# OSC 133 and reports the working directory with OSC 7.
+
+function __tl_preexec --on-event fish_preexec
+    set -g __tl_ran 1
+    printf '\e]133;C;cmdline_url=%s\a' (string escape --style=url -- $argv[1])
+end
+
+function __tl_postexec --on-event fish_postexec
+    set -l s $status
+    set -q __tl_ran; and printf '\e]133;D;%s\a' $s
+    set -e __tl_ran
+end
+
+function __tl_prompt --on-event fish_prompt
+    printf '\e]7;file://%s%s\a' $hostname (string escape --style=url -- $PWD)
+    printf '\e]133;A\a'
+end
+
+functions -q fish_prompt; and functions -c fish_prompt __tl_user_prompt
+function fish_prompt
+    functions -q __tl_user_prompt; and __tl_user_prompt
+    printf '\e]133;B\a'
+end
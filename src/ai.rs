@@ -61,9 +61,41 @@ fn build_timeline(storage: &Storage, session_id: &str, max_items: usize) -> crat
             } => {
                 lines.push(format!("[term] size {}x{}", screen_size.0, screen_size.1));
             }
+            EventType::Interrupt {
+                signal,
+                ref command,
+                ..
+            } => {
+                lines.push(format!(
+                    "[interrupt] {} sent to '{}'",
+                    crate::process::signal_name(signal),
+                    command
+                ));
+            }
             EventType::SessionMetadata { ref name, .. } => {
                 lines.push(format!("[session] {}", name));
             }
+            EventType::Baseline {
+                ref root,
+                ref files,
+                ..
+            } => {
+                lines.push(format!("[baseline] {} files in {}", files.len(), root));
+            }
+            EventType::Undo { ref commands, .. } => {
+                lines.push(format!("[undo] file effects of {}", commands.join("; ")));
+            }
+            EventType::Custom {
+                ref kind,
+                ref payload,
+                ..
+            } => {
+                lines.push(format!(
+                    "[custom:{}] {}",
+                    kind,
+                    crate::custom_event::render(kind, payload)
+                ));
+            }
         }
     }
     Ok(lines.join("\n"))
@@ -589,6 +589,22 @@ impl TimeLoopGui {
                     });
                 });
                 ui.label(format!("Duration: {}s", rs.duration.num_seconds()));
+                if rs.commands_with_stderr > 0 {
+                    ui.colored_label(
+                        egui::Color32::from_rgb(220, 80, 80),
+                        format!("Commands with error output: {}", rs.commands_with_stderr),
+                    );
+                }
+                if !rs.slowest_commands.is_empty() {
+                    ui.label("Slowest commands:");
+                    for (command, duration_ms) in &rs.slowest_commands {
+                        ui.monospace(format!(
+                            "{:>8.2}s  {}",
+                            *duration_ms as f64 / 1000.0,
+                            command
+                        ));
+                    }
+                }
             });
 
             ui.add_space(8.0);
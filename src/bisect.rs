@@ -0,0 +1,260 @@
+use crate::events::sort_by_command;
+use crate::shell_adapter::ShellKind;
+use crate::workspace::{Workspace, WorkspaceState};
+use crate::{Event, EventType, Storage, TimeLoopError};
+use std::path::{Path, PathBuf};
+
+/// One run of the test command
+#[derive(Debug, Clone)]
+pub struct BisectStep {
+    /// The command whose file effects were the last ones checked out, or None
+    /// for the workspace before any of them
+    pub command: Option<String>,
+    pub passed: bool,
+}
+
+/// The command whose file effects first made the test fail
+#[derive(Debug, Clone)]
+pub struct Culprit {
+    pub event_id: String,
+    pub command: String,
+    /// The files it changed
+    pub files: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BisectReport {
+    pub steps: Vec<BisectStep>,
+    pub culprit: Culprit,
+    /// Files left out of the checkouts because their contents weren't kept
+    pub missing: Vec<PathBuf>,
+}
+
+/// Binary-searches a session's workspace history for the command that broke
+/// a test, like `git bisect` over the recorded `FileChange` events. Only
+/// commands that changed files are candidates; the workspace before the first
+/// of them is taken to be good and the one after the last to be bad.
+pub struct Bisect {
+    storage: Storage,
+    session_id: String,
+}
+
+impl Bisect {
+    pub fn new(session_id: &str) -> crate::Result<Self> {
+        Ok(Self::with_storage(session_id, Storage::new()?))
+    }
+
+    pub fn with_storage(session_id: &str, storage: Storage) -> Self {
+        Self {
+            storage,
+            session_id: session_id.to_string(),
+        }
+    }
+
+    /// Run `test` (a shell command line, or a program and its arguments) in a
+    /// fresh checkout for each candidate, calling `on_step` after each run. A
+    /// zero exit code passes.
+    pub fn run(
+        &self,
+        test: &[String],
+        shell: ShellKind,
+        mut on_step: impl FnMut(&BisectStep),
+    ) -> crate::Result<BisectReport> {
+        let mut events = self.storage.get_events_for_session(&self.session_id)?;
+        sort_by_command(&mut events);
+        let candidates = candidates(&events);
+        if candidates.is_empty() {
+            return Err(TimeLoopError::Replay(format!(
+                "No command of session {} changed any files",
+                self.session_id
+            )));
+        }
+        // State `i` has the effects of the first `i` candidates
+        let state = |i: usize| match i {
+            0 => WorkspaceState::replay(&events[..candidates[0].0]),
+            _ => WorkspaceState::replay(&events[..=candidates[i - 1].1]),
+        };
+        let command = |i: usize| match i {
+            0 => None,
+            _ => match &events[candidates[i - 1].1].event_type {
+                EventType::Command { command, .. } => Some(command.clone()),
+                _ => None,
+            },
+        };
+        // The same root for every checkout, so the test runs in the same place
+        let root = state(candidates.len()).common_root();
+        let workspace = Workspace::with_storage(&self.session_id, self.storage.clone());
+
+        let mut steps = Vec::new();
+        let mut missing = Vec::new();
+        let mut test_at = |i: usize| -> crate::Result<bool> {
+            let into = std::env::temp_dir().join(format!("timeloop-bisect-{}", uuid::Uuid::new_v4()));
+            let report = workspace.write_state(&state(i), &into, root.as_deref());
+            let passed = report.and_then(|report| {
+                for path in report.missing {
+                    if !missing.contains(&path) {
+                        missing.push(path);
+                    }
+                }
+                run_test(test, shell, &into)
+            });
+            let _ = std::fs::remove_dir_all(&into);
+            let step = BisectStep {
+                command: command(i),
+                passed: passed?,
+            };
+            on_step(&step);
+            steps.push(step.clone());
+            Ok(step.passed)
+        };
+
+        let (mut good, mut bad) = (0, candidates.len());
+        if test_at(bad)? {
+            return Err(TimeLoopError::Replay(
+                "The test passes at the end of the session; nothing to bisect".to_string(),
+            ));
+        }
+        if !test_at(good)? {
+            return Err(TimeLoopError::Replay(
+                "The test already fails before the session's first change".to_string(),
+            ));
+        }
+        while bad - good > 1 {
+            let mid = (good + bad) / 2;
+            if test_at(mid)? {
+                good = mid;
+            } else {
+                bad = mid;
+            }
+        }
+
+        let (start, end) = candidates[bad - 1];
+        let mut files: Vec<String> = Vec::new();
+        for event in &events[start..end] {
+            if let EventType::FileChange { path, .. } = &event.event_type {
+                if !files.contains(path) {
+                    files.push(path.clone());
+                }
+            }
+        }
+        let culprit = Culprit {
+            event_id: events[end].id.clone(),
+            command: command(bad).unwrap_or_default(),
+            files,
+        };
+        Ok(BisectReport {
+            steps,
+            culprit,
+            missing,
+        })
+    }
+}
+
+/// For each command that changed files, the index of its first file change
+/// and the index of its `Command` event, which comes after them once sorted by
+/// `sort_by_command`
+fn candidates(events: &[Event]) -> Vec<(usize, usize)> {
+    let mut candidates = Vec::new();
+    let mut first_change = None;
+    for (i, event) in events.iter().enumerate() {
+        match event.event_type {
+            EventType::FileChange { .. } => {
+                first_change.get_or_insert(i);
+            }
+            EventType::Command { .. } => {
+                if let Some(start) = first_change.take() {
+                    candidates.push((start, i));
+                }
+            }
+            _ => {}
+        }
+    }
+    candidates
+}
+
+fn run_test(test: &[String], shell: ShellKind, dir: &Path) -> crate::Result<bool> {
+    let adapter = shell.adapter();
+    let (program, args) = match test {
+        [] => return Err(TimeLoopError::CommandExecution("No test command given".to_string())),
+        [command] => adapter.one_shot_command(command),
+        [program, args @ ..] => (program.as_str(), args.to_vec()),
+    };
+    let status = std::process::Command::new(program)
+        .args(&args)
+        .current_dir(dir)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map_err(|e| TimeLoopError::CommandExecution(format!("Failed to run {}: {}", program, e)))?;
+    Ok(status.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CommandRecord, EventRecorder, FileChangeType};
+    use chrono::{DateTime, Utc};
+    use tempfile::TempDir;
+
+    /// A command that started at `started_at` and has just finished
+    fn ran(command: &str, cwd: &str, started_at: DateTime<Utc>) -> CommandRecord {
+        CommandRecord {
+            command: command.to_string(),
+            stdout: String::new(),
+            stderr: String::new(),
+            output_chunks: Vec::new(),
+            exit_code: 0,
+            signal: None,
+            core_dumped: false,
+            resource_usage: None,
+            working_directory: cwd.to_string(),
+            environment: None,
+            started_at,
+            ended_at: Utc::now(),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_bisect_finds_breaking_command() {
+        let tmp_dir = TempDir::new().unwrap();
+        let db_path = tmp_dir.path().join("events_bisect.db");
+        let storage = Storage::with_path(db_path.to_str().unwrap()).unwrap();
+        let mut recorder = EventRecorder::with_storage("bisect-test", storage.clone());
+        let work = tmp_dir.path().join("work");
+        std::fs::create_dir_all(&work).unwrap();
+        let cwd = work.to_str().unwrap().to_string();
+        let status = work.join("status.txt");
+        let notes = work.join("notes.txt");
+        std::fs::write(&status, "ok").unwrap();
+        recorder.record_baseline(&work, std::slice::from_ref(&status)).unwrap();
+
+        let mut change = |path: &Path, content: &str, command: &str| {
+            let started = Utc::now();
+            std::fs::write(path, content).unwrap();
+            recorder
+                .record_file_change(path.to_str().unwrap(), FileChangeType::Modified)
+                .unwrap();
+            recorder.record_command_execution(ran(command, &cwd, started)).unwrap();
+        };
+        change(&notes, "1", "note one");
+        change(&notes, "2", "note two");
+        change(&status, "broken", "break it");
+        change(&notes, "3", "note three");
+        recorder.record_command("ls", "", 0, &cwd).unwrap();
+
+        let bisect = Bisect::with_storage("bisect-test", storage);
+        let test = vec!["grep -qx ok status.txt".to_string()];
+        let mut runs = 0;
+        let report = bisect.run(&test, ShellKind::Sh, |_| runs += 1).unwrap();
+        assert_eq!(report.culprit.command, "break it");
+        assert_eq!(report.culprit.files, vec![status.to_str().unwrap().to_string()]);
+        assert_eq!(runs, report.steps.len());
+        assert!(runs <= 5);
+
+        // Endpoints that don't bracket a failure are reported
+        let always = vec!["true".to_string()];
+        assert!(bisect.run(&always, ShellKind::Sh, |_| {}).is_err());
+    }
+}
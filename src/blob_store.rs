@@ -0,0 +1,237 @@
+use crate::storage::{Storage, KEY_LEN, NONCE_LEN};
+use crate::file_watcher::{parse_ignore_pattern, should_ignore_path};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Which files have their contents kept in the blob store
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlobPolicy {
+    /// Larger files are hashed but not kept
+    pub max_blob_size_bytes: u64,
+    /// Files matching these patterns (same syntax as the file watcher's ignore
+    /// patterns) are hashed but not kept, e.g. keys and `.env` files
+    pub ignore_patterns: Vec<String>,
+}
+
+impl Default for BlobPolicy {
+    fn default() -> Self {
+        Self {
+            max_blob_size_bytes: 10 * 1024 * 1024,
+            ignore_patterns: [".env", "**/.env.*", "**/*.pem", "**/*.key", "**/id_rsa*", "**/id_ed25519*"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+        }
+    }
+}
+
+/// A deduplicated store of file contents keyed by their SHA-256, the same hash
+/// recorded in `EventType::FileChange::content_hash`. Each blob is one file,
+/// written once and never modified.
+///
+/// When the storage is encrypted, blobs are encrypted with the storage key
+/// (nonce followed by ciphertext) and their file names are derived from the
+/// key as well, so the hashes of the contents aren't visible on disk.
+pub(crate) struct BlobStore {
+    dir: PathBuf,
+    key: Option<[u8; KEY_LEN]>,
+}
+
+impl BlobStore {
+    pub(crate) fn new(dir: PathBuf, key: Option<[u8; KEY_LEN]>) -> Self {
+        Self { dir, key }
+    }
+
+    pub(crate) fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Store `content` unless a blob with the same hash exists, and return the hash
+    pub(crate) fn put(&self, content: &[u8]) -> crate::Result<String> {
+        let hash = hex_digest(content);
+        let path = self.blob_path(&hash);
+        if path.exists() {
+            return Ok(hash);
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let bytes = match &self.key {
+            Some(key) => {
+                let (mut nonce, ciphertext) = Storage::encrypt_bytes(key, content)?;
+                nonce.extend_from_slice(&ciphertext);
+                nonce
+            }
+            None => content.to_vec(),
+        };
+        Storage::atomic_write(&path, bytes, true)?;
+        Ok(hash)
+    }
+
+    /// Store the contents of the file at `path` if `policy` allows it. Returns
+    /// None for files that are too large or ignored.
+    pub(crate) fn put_file(&self, path: &Path, policy: &BlobPolicy) -> crate::Result<Option<String>> {
+        let ignore: Vec<_> = policy.ignore_patterns.iter().map(|p| parse_ignore_pattern(p)).collect();
+        if should_ignore_path(path, &ignore) {
+            return Ok(None);
+        }
+        let file = std::fs::File::open(path)?;
+        if file.metadata()?.len() > policy.max_blob_size_bytes {
+            return Ok(None);
+        }
+        // The file may grow while it is read, so the limit is applied again
+        let mut content = Vec::new();
+        file.take(policy.max_blob_size_bytes + 1).read_to_end(&mut content)?;
+        if content.len() as u64 > policy.max_blob_size_bytes {
+            return Ok(None);
+        }
+        self.put(&content).map(Some)
+    }
+
+    /// The contents stored under `hash`, or None if there is no such blob. A
+    /// blob that doesn't match its hash is reported as an error.
+    pub(crate) fn get(&self, hash: &str) -> crate::Result<Option<Vec<u8>>> {
+        if !is_hash(hash) {
+            return Ok(None);
+        }
+        let path = self.blob_path(hash);
+        let bytes = match std::fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let content = match &self.key {
+            Some(key) if bytes.len() >= NONCE_LEN => {
+                let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+                Storage::try_decrypt(key, nonce, ciphertext).map_err(|_| corrupt(hash))?
+            }
+            Some(_) => return Err(corrupt(hash)),
+            None => bytes,
+        };
+        if hex_digest(&content) != hash {
+            return Err(corrupt(hash));
+        }
+        Ok(Some(content))
+    }
+
+    pub(crate) fn contains(&self, hash: &str) -> bool {
+        is_hash(hash) && self.blob_path(hash).exists()
+    }
+
+    /// Re-encrypt every blob for `new_key`, after a passphrase change
+    pub(crate) fn rekey(&self, new_key: &[u8; KEY_LEN]) -> crate::Result<()> {
+        let new_store = BlobStore::new(self.dir.clone(), Some(*new_key));
+        for path in self.blob_files()? {
+            let bytes = std::fs::read(&path)?;
+            let content = match &self.key {
+                Some(key) if bytes.len() >= NONCE_LEN => {
+                    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+                    match Storage::try_decrypt(key, nonce, ciphertext) {
+                        Ok(content) => content,
+                        // Written with another key; leave it alone
+                        Err(()) => continue,
+                    }
+                }
+                Some(_) => continue,
+                None => bytes,
+            };
+            let new_path = new_store.blob_path(&new_store.put(&content)?);
+            if new_path != path {
+                std::fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn blob_files(&self) -> crate::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        let shards = match std::fs::read_dir(&self.dir) {
+            Ok(shards) => shards,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(files),
+            Err(e) => return Err(e.into()),
+        };
+        for shard in shards {
+            let shard = shard?.path();
+            if shard.is_dir() {
+                for entry in std::fs::read_dir(&shard)? {
+                    files.push(entry?.path());
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    /// `<dir>/ab/cdef...`, sharded on the first byte of the name
+    fn blob_path(&self, hash: &str) -> PathBuf {
+        let name = match &self.key {
+            Some(key) => {
+                let mut hasher = Sha256::new();
+                hasher.update(key);
+                hasher.update(hash.as_bytes());
+                format!("{:x}", hasher.finalize())
+            }
+            None => hash.to_ascii_lowercase(),
+        };
+        let (shard, rest) = name.split_at(2.min(name.len()));
+        self.dir.join(shard).join(rest)
+    }
+}
+
+/// Whether `s` looks like a SHA-256 hex digest, which also keeps it from
+/// naming a path outside the store
+fn is_hash(s: &str) -> bool {
+    s.len() == 64 && s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn hex_digest(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+fn corrupt(hash: &str) -> crate::TimeLoopError {
+    crate::TimeLoopError::Storage(format!("Blob {} is corrupt or unreadable", hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_blobs_are_deduplicated_and_verified() {
+        let tmp_dir = TempDir::new().unwrap();
+        let store = BlobStore::new(tmp_dir.path().join("blobs"), None);
+
+        let hash = store.put(b"hello world").unwrap();
+        assert_eq!(hash, "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9");
+        assert_eq!(store.put(b"hello world").unwrap(), hash);
+        assert_eq!(store.blob_files().unwrap().len(), 1);
+        assert_eq!(store.get(&hash).unwrap().unwrap(), b"hello world");
+        assert!(store.get(&hex_digest(b"missing")).unwrap().is_none());
+
+        std::fs::write(store.blob_path(&hash), b"tampered").unwrap();
+        assert!(store.get(&hash).is_err());
+    }
+
+    #[test]
+    fn test_put_file_applies_policy() {
+        let tmp_dir = TempDir::new().unwrap();
+        let store = BlobStore::new(tmp_dir.path().join("blobs"), None);
+        let policy = BlobPolicy {
+            max_blob_size_bytes: 4,
+            ..BlobPolicy::default()
+        };
+
+        let small = tmp_dir.path().join("small.txt");
+        std::fs::write(&small, b"abc").unwrap();
+        assert!(store.put_file(&small, &policy).unwrap().is_some());
+
+        let large = tmp_dir.path().join("large.txt");
+        std::fs::write(&large, b"abcdef").unwrap();
+        assert!(store.put_file(&large, &policy).unwrap().is_none());
+
+        let secret = tmp_dir.path().join(".env");
+        std::fs::write(&secret, b"K=v").unwrap();
+        assert!(store.put_file(&secret, &policy).unwrap().is_none());
+    }
+}
@@ -0,0 +1,203 @@
+use crate::{Storage, TimeLoopError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use zeroize::Zeroize;
+
+/// A note attached to a point in a session
+#[derive(Debug, Clone, Serialize, Deserialize, Zeroize)]
+pub struct Bookmark {
+    pub id: String,
+    pub session_id: String,
+    /// The event the note refers to
+    pub event_id: String,
+    /// Unique within the session, so the bookmark can be referred to by it
+    #[serde(default)]
+    pub name: Option<String>,
+    pub note: String,
+    #[zeroize(skip)]
+    pub created_at: DateTime<Utc>,
+}
+
+impl Bookmark {
+    /// `name: note`, or whichever of the two the bookmark has
+    pub fn label(&self) -> String {
+        match (&self.name, self.note.is_empty()) {
+            (Some(name), true) => name.clone(),
+            (Some(name), false) => format!("{}: {}", name, self.note),
+            (None, _) => self.note.clone(),
+        }
+    }
+}
+
+pub struct BookmarkManager {
+    storage: Storage,
+}
+
+impl BookmarkManager {
+    pub fn new() -> crate::Result<Self> {
+        let storage = Storage::new()?;
+        Ok(Self { storage })
+    }
+
+    pub fn with_storage(storage: Storage) -> Self {
+        Self { storage }
+    }
+
+    /// Bookmark an event of a session. A name must not be taken by another
+    /// bookmark of the session, nor look like an event ID of it.
+    pub fn add(
+        &self,
+        session_id: &str,
+        event_id: &str,
+        name: Option<&str>,
+        note: &str,
+    ) -> crate::Result<Bookmark> {
+        let events = self.storage.get_events_for_session(session_id)?;
+        if !events.iter().any(|e| e.id == event_id) {
+            return Err(TimeLoopError::Bookmark(format!(
+                "Event {} not found in session {}",
+                event_id, session_id
+            )));
+        }
+        if let Some(name) = name {
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                return Err(TimeLoopError::Bookmark(format!(
+                    "Invalid bookmark name '{}': it must be one word",
+                    name
+                )));
+            }
+            if events.iter().any(|e| e.id == name) {
+                return Err(TimeLoopError::Bookmark(format!(
+                    "Bookmark name '{}' is an event ID",
+                    name
+                )));
+            }
+            if self.list(session_id)?.iter().any(|b| b.name.as_deref() == Some(name)) {
+                return Err(TimeLoopError::Bookmark(format!(
+                    "Session {} already has a bookmark named '{}'",
+                    session_id, name
+                )));
+            }
+        }
+
+        let bookmark = Bookmark {
+            id: Uuid::new_v4().to_string(),
+            session_id: session_id.to_string(),
+            event_id: event_id.to_string(),
+            name: name.map(|s| s.to_string()),
+            note: note.to_string(),
+            created_at: Utc::now(),
+        };
+        self.storage.store_bookmark(&bookmark)?;
+        Ok(bookmark)
+    }
+
+    /// The session's bookmarks, oldest first
+    pub fn list(&self, session_id: &str) -> crate::Result<Vec<Bookmark>> {
+        self.storage.get_bookmarks_for_session(session_id)
+    }
+
+    /// A bookmark of the session by name or ID
+    pub fn find(&self, session_id: &str, name_or_id: &str) -> crate::Result<Option<Bookmark>> {
+        let bookmarks = self.list(session_id)?;
+        Ok(bookmarks
+            .iter()
+            .find(|b| b.name.as_deref() == Some(name_or_id))
+            .or_else(|| bookmarks.iter().find(|b| b.id == name_or_id))
+            .cloned())
+    }
+
+    /// Delete a bookmark of the session by name or ID
+    pub fn remove(&self, session_id: &str, name_or_id: &str) -> crate::Result<Bookmark> {
+        let bookmark = self.find(session_id, name_or_id)?.ok_or_else(|| {
+            TimeLoopError::Bookmark(format!(
+                "No bookmark '{}' in session {}",
+                name_or_id, session_id
+            ))
+        })?;
+        self.storage.delete_bookmark(&bookmark.id)?;
+        Ok(bookmark)
+    }
+
+    /// The event `target` refers to: an event of the session by ID, or the
+    /// event a bookmark of the session with that name or ID is on
+    pub fn resolve_event(&self, session_id: &str, target: &str) -> crate::Result<String> {
+        let events = self.storage.get_events_for_session(session_id)?;
+        if events.iter().any(|e| e.id == target) {
+            return Ok(target.to_string());
+        }
+        match self.find(session_id, target)? {
+            Some(bookmark) => Ok(bookmark.event_id),
+            None => Err(TimeLoopError::Bookmark(format!(
+                "'{}' is neither an event nor a bookmark of session {}",
+                target, session_id
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EventRecorder;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_bookmarks_resolve_by_name_and_follow_session() {
+        let tmp_dir = TempDir::new().unwrap();
+        let db_path = tmp_dir.path().join("events_bookmarks.db");
+        let storage = Storage::with_path(db_path.to_str().unwrap()).unwrap();
+        let mut recorder = EventRecorder::with_storage("bookmark-test", storage.clone());
+        recorder.record_command("cargo build", "", 0, "/tmp").unwrap();
+        recorder.record_command("cargo test", "", 101, "/tmp").unwrap();
+        let events = storage.get_events_for_session("bookmark-test").unwrap();
+
+        let manager = BookmarkManager::with_storage(storage.clone());
+        let named = manager
+            .add("bookmark-test", &events[0].id, Some("before-refactor"), "")
+            .unwrap();
+        let note = manager
+            .add("bookmark-test", &events[1].id, None, "this is where the migration broke")
+            .unwrap();
+        assert_eq!(named.label(), "before-refactor");
+        assert_eq!(note.label(), "this is where the migration broke");
+
+        // Names are unique per session, and must refer to a known event
+        assert!(manager.add("bookmark-test", &events[1].id, Some("before-refactor"), "").is_err());
+        assert!(manager.add("bookmark-test", "no-such-event", None, "x").is_err());
+        assert!(manager.add("bookmark-test", &events[1].id, Some("two words"), "").is_err());
+
+        assert_eq!(manager.resolve_event("bookmark-test", "before-refactor").unwrap(), events[0].id);
+        assert_eq!(manager.resolve_event("bookmark-test", &note.id).unwrap(), events[1].id);
+        assert_eq!(manager.resolve_event("bookmark-test", &events[1].id).unwrap(), events[1].id);
+        assert!(manager.resolve_event("bookmark-test", "missing").is_err());
+        assert!(manager.resolve_event("other-session", "before-refactor").is_err());
+
+        // Bookmarks travel with exports and go with their session
+        let export = tmp_dir.path().join("export.json");
+        storage
+            .store_session(&crate::Session {
+                id: "bookmark-test".to_string(),
+                name: "bookmarks".to_string(),
+                created_at: Utc::now(),
+                ended_at: None,
+                parent_session_id: None,
+                branch_name: None,
+                ..Default::default()
+            })
+            .unwrap();
+        storage
+            .export_session_to_file("bookmark-test", export.to_str().unwrap())
+            .unwrap();
+        manager.remove("bookmark-test", "before-refactor").unwrap();
+        assert_eq!(manager.list("bookmark-test").unwrap().len(), 1);
+        storage.delete_session("bookmark-test").unwrap();
+        assert!(manager.list("bookmark-test").unwrap().is_empty());
+
+        storage.import_session_from_file(export.to_str().unwrap()).unwrap();
+        let restored = manager.list("bookmark-test").unwrap();
+        assert_eq!(restored.len(), 2);
+        assert_eq!(restored[0].name.as_deref(), Some("before-refactor"));
+    }
+}
@@ -1,6 +1,9 @@
-use crate::{Event, Storage, TimeLoopError};
+use crate::session::SessionManager;
+use crate::workspace::{CheckoutReport, Workspace};
+use crate::{Event, EventType, Storage, TimeLoopError};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 use uuid::Uuid;
 use zeroize::Zeroize;
 
@@ -15,6 +18,20 @@ pub struct TimelineBranch {
     pub description: Option<String>,
 }
 
+/// Where a live session continuing a branch picks up from
+#[derive(Debug, Clone)]
+pub struct BranchStart {
+    /// The branch's own session, which the new events are recorded into
+    pub session_id: String,
+    pub branch: TimelineBranch,
+    /// The working directory at the branch point, if the parent session ran
+    /// any command before it
+    pub working_directory: Option<String>,
+    /// The variables the parent session had exported by the branch point, as
+    /// `NAME=value`
+    pub environment: Vec<String>,
+}
+
 pub struct BranchManager {
     storage: Storage,
 }
@@ -25,6 +42,10 @@ impl BranchManager {
         Ok(Self { storage })
     }
 
+    pub fn with_storage(storage: Storage) -> Self {
+        Self { storage }
+    }
+
     pub fn create_branch(
         &mut self,
         parent_session_id: &str,
@@ -123,6 +144,135 @@ impl BranchManager {
     pub fn delete_branch(&mut self, branch_id: &str) -> crate::Result<()> {
         self.storage.delete_branch(branch_id)
     }
+
+    /// Work out where to continue a branch from. `id` is either a timeline
+    /// branch ID or the ID of a branch's session, as printed by `timeloop
+    /// branch`; a branch without a session gets one.
+    pub fn branch_start(&self, id: &str) -> crate::Result<BranchStart> {
+        let mut session_manager = SessionManager::with_storage(self.storage.clone());
+        let (branch, session_id) = match self.get_branch(id)? {
+            Some(branch) => {
+                let session = session_manager
+                    .list_sessions()?
+                    .into_iter()
+                    .filter(|s| {
+                        s.parent_session_id.as_deref() == Some(branch.parent_session_id.as_str())
+                            && s.branch_name.as_deref() == Some(branch.name.as_str())
+                    })
+                    .max_by_key(|s| s.created_at);
+                let session_id = match session {
+                    Some(session) => session.id,
+                    None => session_manager.create_branch(&branch.parent_session_id, &branch.name)?,
+                };
+                (branch, session_id)
+            }
+            None => {
+                let session = session_manager
+                    .get_session(id)?
+                    .ok_or_else(|| TimeLoopError::Branch(format!("Branch {} not found", id)))?;
+                let (Some(parent), Some(name)) = (&session.parent_session_id, &session.branch_name) else {
+                    return Err(TimeLoopError::Branch(format!("Session {} is not a branch", id)));
+                };
+                let branch = self
+                    .get_branches_for_session(parent)?
+                    .into_iter()
+                    .filter(|b| &b.name == name)
+                    .max_by_key(|b| b.created_at)
+                    .ok_or_else(|| TimeLoopError::Branch(format!("Branch {} has no branch point", id)))?;
+                (branch, session.id)
+            }
+        };
+
+        let mut events = self.storage.get_events_for_session(&branch.parent_session_id)?;
+        events.sort_by_key(|e| e.sequence_number);
+        let index = events.iter().position(|e| e.id == branch.branch_point_event_id);
+        let working_directory = index.and_then(|index| working_directory_after(&events, index));
+        let environment = index
+            .and_then(|index| {
+                events[..=index].iter().rev().find_map(|e| match &e.event_type {
+                    EventType::Command {
+                        environment: Some(environment),
+                        ..
+                    } => Some(environment.clone()),
+                    _ => None,
+                })
+            })
+            .unwrap_or_default();
+        Ok(BranchStart {
+            session_id,
+            branch,
+            working_directory,
+            environment,
+        })
+    }
+
+    /// Write the parent session's files as they were at the branch point into
+    /// `into`, which must be empty, and move the start's working directory
+    /// there as well
+    pub fn restore_files(&self, start: &mut BranchStart, into: &Path) -> crate::Result<CheckoutReport> {
+        let workspace = Workspace::with_storage(&start.branch.parent_session_id, self.storage.clone());
+        let state = workspace.state_at(&start.branch.branch_point_event_id)?;
+        let root = state.common_root();
+        let report = workspace.write_state(&state, into, root.as_deref())?;
+
+        let moved = match (&start.working_directory, &root) {
+            (Some(dir), Some(root)) => match Path::new(dir).strip_prefix(root) {
+                Ok(rest) if !rest.as_os_str().is_empty() => Some(into.join(rest)),
+                _ => None,
+            },
+            _ => None,
+        };
+        let moved = moved.unwrap_or_else(|| into.to_path_buf());
+        std::fs::create_dir_all(&moved)?;
+        start.working_directory = Some(moved.to_string_lossy().to_string());
+        Ok(report)
+    }
+}
+
+/// The working directory right after `events[index]`: the one the next
+/// command ran in, else the last command's, followed if it changed directory
+fn working_directory_after(events: &[Event], index: usize) -> Option<String> {
+    let command_dir = |e: &Event| match &e.event_type {
+        EventType::Command {
+            working_directory, ..
+        } if !working_directory.is_empty() => Some(working_directory.clone()),
+        _ => None,
+    };
+    if let Some(next) = events[index + 1..].iter().find_map(command_dir) {
+        return Some(next);
+    }
+    let shell = events[..=index]
+        .iter()
+        .rev()
+        .find_map(|e| match &e.event_type {
+            EventType::SessionMetadata { shell, .. } => *shell,
+            _ => None,
+        })
+        .unwrap_or_default();
+    events[..=index].iter().rev().find_map(|e| {
+        let EventType::Command {
+            command,
+            working_directory,
+            exit_code,
+            ..
+        } = &e.event_type
+        else {
+            return None;
+        };
+        let dir = PathBuf::from(working_directory);
+        let dir = match (*exit_code, shell.adapter().directory_change(command)) {
+            (0, Some(Some(target))) => {
+                let joined = dir.join(target);
+                joined.canonicalize().unwrap_or(joined)
+            }
+            (0, Some(None)) => match std::env::var("HOME") {
+                Ok(home) => PathBuf::from(home),
+                Err(_) => dir,
+            },
+            _ => dir,
+        };
+        Some(dir.to_string_lossy().to_string())
+    })
 }
 
 #[derive(Debug, Clone)]
@@ -152,3 +302,69 @@ impl BranchTimeline {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CommandRecord, EventRecorder, FileChangeType};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_branch_start_restores_directory_and_files() {
+        let tmp_dir = TempDir::new().unwrap();
+        let db_path = tmp_dir.path().join("events_branch.db");
+        let storage = Storage::with_path(db_path.to_str().unwrap()).unwrap();
+        let mut session_manager = SessionManager::with_storage(storage.clone());
+        let parent = session_manager.create_session("parent").unwrap();
+        let mut recorder = EventRecorder::with_storage(&parent, storage.clone());
+
+        let work = tmp_dir.path().join("work");
+        let sub = work.join("sub");
+        std::fs::create_dir_all(&sub).unwrap();
+        let file = sub.join("a.txt");
+        std::fs::write(&file, "before").unwrap();
+        recorder.record_file_change(file.to_str().unwrap(), FileChangeType::Created).unwrap();
+        let exported = |command: &str, dir: &Path, environment: &[&str]| CommandRecord {
+            command: command.to_string(),
+            stdout: String::new(),
+            stderr: String::new(),
+            output_chunks: Vec::new(),
+            exit_code: 0,
+            signal: None,
+            core_dumped: false,
+            resource_usage: None,
+            working_directory: dir.to_str().unwrap().to_string(),
+            environment: Some(environment.iter().map(|v| v.to_string()).collect()),
+            started_at: chrono::Utc::now(),
+            ended_at: chrono::Utc::now(),
+        };
+        recorder.record_command_execution(exported("export MODE=dev", &work, &["MODE=dev"])).unwrap();
+        recorder.record_command("cd sub", "", 0, work.to_str().unwrap()).unwrap();
+        let branch_point = storage.get_last_event(&parent).unwrap().unwrap().id;
+        std::fs::write(&file, "after").unwrap();
+        recorder.record_file_change(file.to_str().unwrap(), FileChangeType::Modified).unwrap();
+        recorder.record_command_execution(exported("export MODE=prod", &sub.canonicalize().unwrap(), &["MODE=prod"])).unwrap();
+
+        let mut branch_manager = BranchManager::with_storage(storage.clone());
+        let timeline_id = branch_manager.create_branch(&parent, "retry", &branch_point, None).unwrap();
+
+        // The timeline branch gets a session of its own, found again by either ID
+        let start = branch_manager.branch_start(&timeline_id).unwrap();
+        assert_eq!(start.branch.id, timeline_id);
+        assert_eq!(start.working_directory.as_deref(), sub.canonicalize().unwrap().to_str());
+        // The variables as last recorded before the branch point
+        assert_eq!(start.environment, vec!["MODE=dev".to_string()]);
+        let again = branch_manager.branch_start(&start.session_id).unwrap();
+        assert_eq!(again.session_id, start.session_id);
+        assert_eq!(again.branch.id, timeline_id);
+        assert!(branch_manager.branch_start(&parent).is_err());
+
+        let mut start = start;
+        start.working_directory = Some(sub.to_str().unwrap().to_string());
+        let into = tmp_dir.path().join("restored");
+        let report = branch_manager.restore_files(&mut start, &into).unwrap();
+        assert_eq!(report.written.len(), 1);
+        assert_eq!(std::fs::read_to_string(into.join("a.txt")).unwrap(), "before");
+        assert_eq!(start.working_directory, Some(into.to_string_lossy().to_string()));
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// Candidates for the word under the cursor
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completion {
+    /// Character offset in the line where the word being completed starts
+    pub start: usize,
+    /// Possible replacements for the word, unescaped and sorted. Directories
+    /// end in a slash.
+    pub candidates: Vec<String>,
+}
+
+impl Completion {
+    /// The longest prefix shared by every candidate
+    pub fn common_prefix(&self) -> String {
+        let Some(first) = self.candidates.first() else {
+            return String::new();
+        };
+        let mut prefix: Vec<char> = first.chars().collect();
+        for candidate in &self.candidates[1..] {
+            let shared = prefix
+                .iter()
+                .zip(candidate.chars())
+                .take_while(|(a, b)| **a == *b)
+                .count();
+            prefix.truncate(shared);
+        }
+        prefix.into_iter().collect()
+    }
+}
+
+/// Tab completion for the recording shell's prompt. The first word of a
+/// command completes to TimeLoop built-ins and executables on `$PATH`; other
+/// words complete to paths relative to the session's working directory.
+#[derive(Debug, Clone)]
+pub struct Completer {
+    working_directory: PathBuf,
+    builtins: Vec<String>,
+}
+
+impl Completer {
+    pub fn new(working_directory: impl Into<PathBuf>, builtins: &[&str]) -> Self {
+        Self {
+            working_directory: working_directory.into(),
+            builtins: builtins.iter().map(|b| b.to_string()).collect(),
+        }
+    }
+
+    /// Complete the word ending at `cursor` (a character offset into `line`)
+    pub fn complete(&self, line: &str, cursor: usize) -> Completion {
+        let chars: Vec<char> = line.chars().collect();
+        let cursor = cursor.min(chars.len());
+        let start = word_start(&chars, cursor);
+        let word = unescape(&chars[start..cursor]);
+
+        let before: String = chars[..start].iter().collect();
+        let command_position = before
+            .trim_end()
+            .chars()
+            .last()
+            .is_none_or(|c| matches!(c, ';' | '|' | '&' | '('));
+
+        let mut candidates = BTreeSet::new();
+        if command_position && !word.contains('/') {
+            candidates.extend(
+                self.builtins
+                    .iter()
+                    .filter(|b| b.starts_with(&word))
+                    .cloned(),
+            );
+            candidates.extend(path_executables(&word));
+        } else {
+            candidates.extend(self.complete_path(&word));
+        }
+
+        Completion {
+            start,
+            candidates: candidates.into_iter().collect(),
+        }
+    }
+
+    /// Entries of the directory named by `word` that start with its last
+    /// component. Hidden entries are only offered when asked for with a dot.
+    fn complete_path(&self, word: &str) -> Vec<String> {
+        let (dir_part, prefix) = match word.rfind('/') {
+            Some(i) => word.split_at(i + 1),
+            None => ("", word),
+        };
+        let dir = if dir_part.is_empty() {
+            self.working_directory.clone()
+        } else if let Some(rest) = dir_part.strip_prefix("~/") {
+            match home_dir() {
+                Some(home) => home.join(rest),
+                None => return Vec::new(),
+            }
+        } else {
+            self.working_directory.join(dir_part)
+        };
+
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            return Vec::new();
+        };
+        entries
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                    return None;
+                }
+                // Follow symlinks so that links to directories get a slash
+                let is_dir = entry.path().is_dir();
+                Some(format!("{}{}{}", dir_part, name, if is_dir { "/" } else { "" }))
+            })
+            .collect()
+    }
+}
+
+/// Executables on `$PATH` whose name starts with `prefix`
+fn path_executables(prefix: &str) -> Vec<String> {
+    let Some(path) = std::env::var_os("PATH") else {
+        return Vec::new();
+    };
+    std::env::split_paths(&path)
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.flatten())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            (name.starts_with(prefix) && is_executable(&entry.path())).then_some(name)
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path)
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+}
+
+/// Start of the shell word ending at `cursor`. Backslash-escaped separators
+/// are part of the word.
+fn word_start(chars: &[char], cursor: usize) -> usize {
+    let mut i = cursor;
+    while i > 0 {
+        let c = chars[i - 1];
+        let escaped = i >= 2 && chars[i - 2] == '\\';
+        if !escaped && (c.is_whitespace() || matches!(c, ';' | '|' | '&' | '(' | '<' | '>')) {
+            break;
+        }
+        i -= 1;
+    }
+    i
+}
+
+fn unescape(chars: &[char]) -> String {
+    let mut word = String::new();
+    let mut escaped = false;
+    for &c in chars {
+        if c == '\\' && !escaped {
+            escaped = true;
+            continue;
+        }
+        word.push(c);
+        escaped = false;
+    }
+    word
+}
+
+/// Escape characters the shell would otherwise split or interpret
+pub fn escape(word: &str) -> String {
+    let mut escaped = String::new();
+    for c in word.chars() {
+        if c.is_whitespace() || "\\'\"$`;|&()<>*?[]!#".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_path_completion() {
+        let tmp_dir = TempDir::new().unwrap();
+        std::fs::create_dir(tmp_dir.path().join("src")).unwrap();
+        std::fs::write(tmp_dir.path().join("src/main.rs"), "").unwrap();
+        std::fs::write(tmp_dir.path().join("src/my file.rs"), "").unwrap();
+        std::fs::write(tmp_dir.path().join(".hidden"), "").unwrap();
+        let completer = Completer::new(tmp_dir.path(), &[]);
+
+        let completion = completer.complete("cat s", 5);
+        assert_eq!(completion.start, 4);
+        assert_eq!(completion.candidates, vec!["src/"]);
+
+        let completion = completer.complete("cat src/m", 9);
+        assert_eq!(completion.candidates, vec!["src/main.rs", "src/my file.rs"]);
+        assert_eq!(completion.common_prefix(), "src/m");
+
+        // Escaped spaces stay part of the word
+        let completion = completer.complete("cat src/my\\ f", 13);
+        assert_eq!(completion.start, 4);
+        assert_eq!(completion.candidates, vec!["src/my file.rs"]);
+
+        // Hidden files only when asked for
+        assert!(completer.complete("ls ", 3).candidates.iter().all(|c| !c.starts_with('.')));
+        assert_eq!(completer.complete("ls .h", 5).candidates, vec![".hidden"]);
+    }
+
+    #[test]
+    fn test_command_completion() {
+        let tmp_dir = TempDir::new().unwrap();
+        let completer = Completer::new(tmp_dir.path(), &["incognito", "exit"]);
+
+        assert_eq!(completer.complete("inc", 3).candidates, vec!["incognito"]);
+        // After a pipe the next word is a command again
+        assert!(completer.complete("ls | exi", 8).candidates.contains(&"exit".to_string()));
+        // Arguments are not completed as built-ins
+        assert!(completer.complete("echo inc", 8).candidates.is_empty());
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("my file.rs"), "my\\ file.rs");
+        assert_eq!(escape("plain"), "plain");
+    }
+}
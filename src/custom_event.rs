@@ -0,0 +1,112 @@
+//! Rendering of `EventType::Custom` events in replays, timelines and AI
+//! summaries. Each kind can have its own renderer; kinds without one are
+//! shown by their payload's fields.
+
+use once_cell::sync::Lazy;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// Turns the payload of one kind of custom event into a line of text
+pub trait CustomEventRenderer: Send + Sync {
+    fn render(&self, payload: &Value) -> String;
+}
+
+impl<F> CustomEventRenderer for F
+where
+    F: Fn(&Value) -> String + Send + Sync,
+{
+    fn render(&self, payload: &Value) -> String {
+        self(payload)
+    }
+}
+
+static RENDERERS: Lazy<RwLock<HashMap<String, Arc<dyn CustomEventRenderer>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// Use `renderer` for custom events of `kind`, replacing any registered before
+pub fn register_renderer(kind: &str, renderer: impl CustomEventRenderer + 'static) {
+    if let Ok(mut renderers) = RENDERERS.write() {
+        renderers.insert(kind.to_string(), Arc::new(renderer));
+    }
+}
+
+/// Go back to the default rendering for `kind`. Returns whether a renderer
+/// was registered.
+pub fn unregister_renderer(kind: &str) -> bool {
+    RENDERERS
+        .write()
+        .map(|mut renderers| renderers.remove(kind).is_some())
+        .unwrap_or(false)
+}
+
+/// A line of text for a custom event, from the renderer registered for its
+/// kind or else `default_render`
+pub fn render(kind: &str, payload: &Value) -> String {
+    // Cloned out so a renderer can itself register renderers
+    let renderer = RENDERERS.read().ok().and_then(|r| r.get(kind).cloned());
+    match renderer {
+        Some(renderer) => renderer.render(payload),
+        None => default_render(payload),
+    }
+}
+
+/// `key=value` for each field of an object payload, else the payload itself;
+/// nested values are shown as JSON
+pub fn default_render(payload: &Value) -> String {
+    match payload {
+        Value::Object(fields) => fields
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, scalar(value)))
+            .collect::<Vec<_>>()
+            .join(" "),
+        Value::Null => String::new(),
+        other => scalar(other),
+    }
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) if !s.is_empty() && !s.contains(char::is_whitespace) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EventRecorder, EventType, Storage};
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_custom_events_are_recorded_and_rendered() {
+        let tmp_dir = TempDir::new().unwrap();
+        let db_path = tmp_dir.path().join("events_custom.db");
+        let storage = Storage::with_path(db_path.to_str().unwrap()).unwrap();
+        let mut recorder = EventRecorder::with_storage_and_redaction("custom-test", storage.clone(), true, None);
+
+        let payload = json!({"suite": "unit", "passed": 12, "failed": 0, "note": "token=abc123"});
+        recorder.record_custom("test-result", payload).unwrap();
+        assert!(recorder.record_custom("two words", json!(null)).is_err());
+        assert!(recorder.record_custom("", json!(null)).is_err());
+
+        let events = storage.get_events_for_session("custom-test").unwrap();
+        assert_eq!(events.len(), 1);
+        let EventType::Custom { kind, payload, .. } = &events[0].event_type else {
+            panic!("expected a Custom event, got {:?}", events[0].event_type);
+        };
+        assert_eq!(kind, "test-result");
+        assert_eq!(payload["passed"], 12);
+        assert_eq!(payload["note"], "[REDACTED]");
+
+        // Fields in key order, strings with spaces quoted
+        assert_eq!(default_render(&json!({"b": 1, "a": "x y"})), "a=\"x y\" b=1");
+        assert_eq!(default_render(&json!("deployed")), "deployed");
+
+        register_renderer("test-result", |p: &Value| format!("{} passed, {} failed", p["passed"], p["failed"]));
+        assert_eq!(events[0].event_type.describe(), "Custom test-result 12 passed, 0 failed");
+        assert!(unregister_renderer("test-result"));
+        assert!(events[0].event_type.describe().contains("passed=12"));
+    }
+}
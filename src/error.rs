@@ -14,6 +14,9 @@ pub enum TimeLoopError {
     #[error("Invalid session ID: {0}")]
     InvalidSessionId(String),
 
+    #[error("Invalid session data: {0}")]
+    InvalidSessionData(String),
+
     #[error("File system error: {0}")]
     FileSystem(String),
 
@@ -22,6 +25,9 @@ pub enum TimeLoopError {
     #[error("CBOR serialization error: {0}")]
     SerializationCbor(#[from] serde_cbor::Error),
 
+    #[error("Schema error: {0}")]
+    Schema(String),
+
     #[error("Event recording error: {0}")]
     EventRecording(String),
 
@@ -31,6 +37,9 @@ pub enum TimeLoopError {
     #[error("Branch error: {0}")]
     Branch(String),
 
+    #[error("Bookmark error: {0}")]
+    Bookmark(String),
+
     #[error("File watcher error: {0}")]
     FileWatcher(#[from] notify::Error),
 
@@ -1,10 +1,13 @@
+use crate::shell_adapter::ShellKind;
 use crate::storage::Storage;
+use crate::workspace::WorkspaceState;
 use chrono::{DateTime, Utc};
 use regex::Regex;
 use sha2::{Digest, Sha256};
 use serde::{Deserialize, Serialize};
+use similar::TextDiff;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use uuid::Uuid;
 use zeroize::Zeroize;
 
@@ -19,9 +22,37 @@ pub enum EventType {
     /// A command execution
     Command {
         command: String,
+        /// Standard output as plain text (stdout and stderr merged in recordings
+        /// made before they were captured separately)
         output: String,
+        /// Standard error as plain text
+        #[serde(default)]
+        stderr: String,
         exit_code: i32,
         working_directory: String,
+        /// Raw terminal output as it arrived, for replay with the original timing
+        #[serde(default)]
+        output_chunks: Vec<OutputChunk>,
+        #[serde(default)]
+        #[zeroize(skip)]
+        started_at: Option<DateTime<Utc>>,
+        #[serde(default)]
+        #[zeroize(skip)]
+        ended_at: Option<DateTime<Utc>>,
+        /// Wall-clock duration in milliseconds
+        #[serde(default)]
+        duration_ms: Option<u64>,
+        /// Signal that terminated the command, if it was killed by one
+        #[serde(default)]
+        signal: Option<i32>,
+        #[serde(default)]
+        core_dumped: bool,
+        #[serde(default)]
+        resource_usage: Option<ResourceUsage>,
+        /// Exported variables the session's shell had set or changed when the
+        /// command finished, as `NAME=value`; None if they weren't recorded
+        #[serde(default)]
+        environment: Option<Vec<String>>,
         #[zeroize(skip)]
         timestamp: DateTime<Utc>,
     },
@@ -30,6 +61,9 @@ pub enum EventType {
         path: String,
         change_type: FileChangeType,
         content_hash: Option<String>,
+        /// Unified diff from the previous known version of a text file
+        #[serde(default)]
+        diff: Option<String>,
         #[zeroize(skip)]
         timestamp: DateTime<Utc>,
     },
@@ -40,11 +74,54 @@ pub enum EventType {
         #[zeroize(skip)]
         timestamp: DateTime<Utc>,
     },
+    /// The user interrupted, quit or suspended a running command
+    Interrupt {
+        /// SIGINT, SIGQUIT or SIGTSTP
+        signal: i32,
+        /// The command that was running
+        command: String,
+        #[zeroize(skip)]
+        timestamp: DateTime<Utc>,
+    },
     /// Session metadata
     SessionMetadata {
         name: String,
         #[zeroize(skip)]
         created_at: DateTime<Utc>,
+        /// The shell that ran the session's commands
+        #[serde(default)]
+        #[zeroize(skip)]
+        shell: Option<ShellKind>,
+        #[zeroize(skip)]
+        timestamp: DateTime<Utc>,
+    },
+    /// The watched files as they were when watching started, so later changes
+    /// have something to start from
+    Baseline {
+        /// The watched directory; manifest paths are relative to it
+        root: String,
+        files: Vec<ManifestEntry>,
+        #[zeroize(skip)]
+        timestamp: DateTime<Utc>,
+    },
+    /// `timeloop undo` put back the files touched by the last commands. The
+    /// files it wrote or removed are recorded as `FileChange` events before it.
+    Undo {
+        /// The commands whose file effects were undone, oldest first
+        commands: Vec<String>,
+        restored: Vec<String>,
+        deleted: Vec<String>,
+        #[zeroize(skip)]
+        timestamp: DateTime<Utc>,
+    },
+    /// An event defined outside TimeLoop, such as a test result or a deploy
+    /// marker emitted by a script. How it is shown can be customized with
+    /// `custom_event::register_renderer`.
+    Custom {
+        /// What the event is, e.g. "test-result" or "ci.deploy"
+        kind: String,
+        #[zeroize(skip)]
+        payload: serde_json::Value,
         #[zeroize(skip)]
         timestamp: DateTime<Utc>,
     },
@@ -58,6 +135,159 @@ pub enum FileChangeType {
     Renamed { old_path: String },
 }
 
+/// One file of a `Baseline`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Zeroize)]
+pub struct ManifestEntry {
+    /// Path relative to the baseline's root
+    pub path: String,
+    pub size: u64,
+    /// Unix permission bits
+    pub mode: u32,
+    pub content_hash: Option<String>,
+}
+
+/// Which output stream a chunk was written to
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Zeroize)]
+pub enum OutputStream {
+    #[default]
+    Stdout,
+    Stderr,
+}
+
+/// A piece of a command's terminal output, timed from when the command started
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Zeroize)]
+pub struct OutputChunk {
+    pub offset_ms: u64,
+    #[serde(default)]
+    pub stream: OutputStream,
+    /// Output bytes as written to the terminal, escape sequences included
+    pub data: String,
+}
+
+/// CPU time and peak memory used by a command and the processes it waited for
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Zeroize)]
+pub struct ResourceUsage {
+    pub user_time_ms: u64,
+    pub system_time_ms: u64,
+    /// Peak resident set size in kilobytes
+    pub max_rss_kb: u64,
+}
+
+/// Everything known about one finished command, as passed to
+/// `EventRecorder::record_command_execution`
+#[derive(Debug, Clone)]
+pub struct CommandRecord {
+    pub command: String,
+    pub stdout: String,
+    pub stderr: String,
+    pub output_chunks: Vec<OutputChunk>,
+    pub exit_code: i32,
+    pub signal: Option<i32>,
+    pub core_dumped: bool,
+    pub resource_usage: Option<ResourceUsage>,
+    pub working_directory: String,
+    /// See `EventType::Command::environment`
+    pub environment: Option<Vec<String>>,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+}
+
+/// Which changed files `EventRecorder` stores a diff for
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffPolicy {
+    /// Files larger than this, before or after the change, get no diff
+    pub max_file_size_bytes: u64,
+    /// Longer diffs are dropped rather than stored
+    pub max_diff_bytes: usize,
+}
+
+impl Default for DiffPolicy {
+    fn default() -> Self {
+        Self {
+            max_file_size_bytes: 1024 * 1024,
+            max_diff_bytes: 64 * 1024,
+        }
+    }
+}
+
+/// Collects output into `OutputChunk`s while a command runs. Reads that arrive
+/// close together are merged so chatty programs don't produce thousands of
+/// tiny chunks, and multi-byte characters split across reads are kept whole.
+#[derive(Debug)]
+pub struct OutputChunkCollector {
+    started: std::time::Instant,
+    chunks: Vec<OutputChunk>,
+    /// Trailing bytes of an incomplete UTF-8 sequence waiting for the next
+    /// read, per stream
+    partial: [Vec<u8>; 2],
+}
+
+impl OutputChunkCollector {
+    /// Reads within this many milliseconds of a chunk's start are merged into it
+    const MERGE_WINDOW_MS: u64 = 50;
+
+    pub fn new() -> Self {
+        Self {
+            started: std::time::Instant::now(),
+            chunks: Vec::new(),
+            partial: [Vec::new(), Vec::new()],
+        }
+    }
+
+    pub fn push(&mut self, stream: OutputStream, bytes: &[u8]) {
+        let offset_ms = self.started.elapsed().as_millis() as u64;
+        let partial = &mut self.partial[stream as usize];
+        partial.extend_from_slice(bytes);
+        let valid = match std::str::from_utf8(partial) {
+            Ok(_) => partial.len(),
+            // Hold back an incomplete sequence at the end; invalid bytes are
+            // passed on and replaced below
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => partial.len(),
+        };
+        if valid == 0 {
+            return;
+        }
+        let data = String::from_utf8_lossy(&partial[..valid]).into_owned();
+        partial.drain(..valid);
+
+        match self.chunks.last_mut() {
+            Some(last)
+                if last.stream == stream
+                    && offset_ms.saturating_sub(last.offset_ms) < Self::MERGE_WINDOW_MS =>
+            {
+                last.data.push_str(&data)
+            }
+            _ => self.chunks.push(OutputChunk {
+                offset_ms,
+                stream,
+                data,
+            }),
+        }
+    }
+
+    pub fn finish(mut self) -> Vec<OutputChunk> {
+        let offset_ms = self.started.elapsed().as_millis() as u64;
+        for stream in [OutputStream::Stdout, OutputStream::Stderr] {
+            let partial = std::mem::take(&mut self.partial[stream as usize]);
+            if !partial.is_empty() {
+                self.chunks.push(OutputChunk {
+                    offset_ms,
+                    stream,
+                    data: String::from_utf8_lossy(&partial).into_owned(),
+                });
+            }
+        }
+        self.chunks
+    }
+}
+
+impl Default for OutputChunkCollector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Zeroize)]
 pub struct Event {
     pub id: String,
@@ -68,6 +298,45 @@ pub struct Event {
     pub timestamp: DateTime<Utc>,
 }
 
+impl EventType {
+    /// One-line description used by timelines, e.g. "Command cargo build"
+    pub fn describe(&self) -> String {
+        match self {
+            EventType::KeyPress { key, .. } => format!("KeyPress {}", key),
+            EventType::Command { command, .. } => format!("Command {}", command),
+            EventType::FileChange {
+                path,
+                change_type,
+                diff,
+                ..
+            } => match diff {
+                Some(diff) => {
+                    let (added, removed) = diff_stat(diff);
+                    format!("FileChange {:?} {} (+{} -{})", change_type, path, added, removed)
+                }
+                None => format!("FileChange {:?} {}", change_type, path),
+            },
+            EventType::TerminalState { .. } => "TerminalState".to_string(),
+            EventType::Interrupt { signal, command, .. } => format!(
+                "Interrupt {} {}",
+                crate::process::signal_name(*signal),
+                command
+            ),
+            EventType::SessionMetadata { name, shell, .. } => match shell {
+                Some(shell) => format!("SessionMetadata {} ({})", name, shell),
+                None => format!("SessionMetadata {}", name),
+            },
+            EventType::Baseline { root, files, .. } => {
+                format!("Baseline {} ({} files)", root, files.len())
+            }
+            EventType::Undo { commands, .. } => format!("Undo {} commands", commands.len()),
+            EventType::Custom { kind, payload, .. } => {
+                format!("Custom {} {}", kind, crate::custom_event::render(kind, payload))
+            }
+        }
+    }
+}
+
 impl Event {
     pub fn new(session_id: &str, event_type: EventType, sequence_number: u64) -> Self {
         Self {
@@ -90,6 +359,10 @@ pub struct EventRecorder {
     redact_patterns: Vec<Regex>,
     redact_literals: Vec<String>,
     is_paused: bool,
+    diff_policy: DiffPolicy,
+    /// The session's files as recorded so far, loaded on first use, to diff
+    /// modified files against
+    known_files: Option<WorkspaceState>,
 }
 
 impl EventRecorder {
@@ -133,6 +406,8 @@ impl EventRecorder {
             redact_patterns: compiled,
             redact_literals: Vec::new(),
             is_paused: false,
+            diff_policy: DiffPolicy::default(),
+            known_files: None,
         };
         recorder.load_env_secrets();
         Ok(recorder)
@@ -182,6 +457,8 @@ impl EventRecorder {
             redact_patterns: compiled,
             redact_literals: Vec::new(),
             is_paused: false,
+            diff_policy: DiffPolicy::default(),
+            known_files: None,
         };
         if redact {
             recorder.load_env_secrets();
@@ -214,6 +491,8 @@ impl EventRecorder {
             redact_patterns: Vec::new(),
             redact_literals: Vec::new(),
             is_paused: false,
+            diff_policy: DiffPolicy::default(),
+            known_files: None,
         }
     }
 
@@ -231,6 +510,10 @@ impl EventRecorder {
         self.is_paused
     }
 
+    pub fn set_diff_policy(&mut self, policy: DiffPolicy) {
+        self.diff_policy = policy;
+    }
+
     pub fn record_key_press(&mut self, key: &str) -> crate::Result<()> {
         if self.is_paused {
             return Ok(());
@@ -256,24 +539,73 @@ impl EventRecorder {
         exit_code: i32,
         working_dir: &str,
     ) -> crate::Result<()> {
+        let now = Utc::now();
+        self.record_command_execution(CommandRecord {
+            command: command.to_string(),
+            stdout: output.to_string(),
+            stderr: String::new(),
+            output_chunks: Vec::new(),
+            exit_code,
+            signal: None,
+            core_dumped: false,
+            resource_usage: None,
+            working_directory: working_dir.to_string(),
+            environment: None,
+            started_at: now,
+            ended_at: now,
+        })
+    }
+
+    /// Record a finished command with separate stdout/stderr, timed output
+    /// chunks and start/end times
+    pub fn record_command_execution(&mut self, record: CommandRecord) -> crate::Result<()> {
         if self.is_paused {
             return Ok(());
         }
         self.sequence_counter += 1;
-        let (stored_command, stored_output) = if self.redact_output {
-            (self.apply_redaction(command), self.apply_redaction(output))
-        } else {
-            (command.to_string(), output.to_string())
-        };
+        let CommandRecord {
+            mut command,
+            mut stdout,
+            mut stderr,
+            mut output_chunks,
+            exit_code,
+            signal,
+            core_dumped,
+            resource_usage,
+            working_directory,
+            mut environment,
+            started_at,
+            ended_at,
+        } = record;
+        if self.redact_output {
+            command = self.apply_redaction(&command);
+            stdout = self.apply_redaction(&stdout);
+            stderr = self.apply_redaction(&stderr);
+            self.redact_chunks(&mut output_chunks);
+            // A redacted value couldn't be restored, so it isn't kept at all
+            if let Some(variables) = &mut environment {
+                variables.retain(|entry| self.apply_redaction(entry) == *entry);
+            }
+        }
 
+        let duration_ms = (ended_at - started_at).num_milliseconds().max(0) as u64;
         let event = Event::new(
             &self.session_id,
             EventType::Command {
-                command: stored_command,
-                output: stored_output,
+                command,
+                output: stdout,
+                stderr,
                 exit_code,
-                working_directory: working_dir.to_string(),
-                timestamp: Utc::now(),
+                working_directory,
+                output_chunks,
+                started_at: Some(started_at),
+                ended_at: Some(ended_at),
+                duration_ms: Some(duration_ms),
+                signal,
+                core_dumped,
+                resource_usage,
+                environment,
+                timestamp: ended_at,
             },
             self.sequence_counter,
         );
@@ -288,17 +620,25 @@ impl EventRecorder {
         path: &str,
         change_type: FileChangeType,
     ) -> crate::Result<()> {
-        if self.is_paused {
+        // Snapshotting a file writes to the storage, which must not be recorded
+        // in turn
+        if self.is_paused || self.storage.is_storage_path(Path::new(path)) {
             return Ok(());
         }
         self.sequence_counter += 1;
 
-        // Compute content hash if the file exists and isn't deleted
+        // Keep the contents of the file if it still exists, or at least its hash
         let content_hash = if change_type != FileChangeType::Deleted {
-            self.compute_file_hash(path)
+            self.snapshot_file(path)
         } else {
             None
         };
+        // Files are often replaced rather than written in place, e.g. by
+        // renaming a temporary file over them, so any new contents are diffed
+        let diff = match &content_hash {
+            Some(hash) => self.diff_against_known(path, hash),
+            None => None,
+        };
 
         let event = Event::new(
             &self.session_id,
@@ -306,12 +646,14 @@ impl EventRecorder {
                 path: path.to_string(),
                 change_type,
                 content_hash,
+                diff,
                 timestamp: Utc::now(),
             },
             self.sequence_counter,
         );
 
         self.storage.store_event(&event)?;
+        self.known_files()?.apply(&event);
         Ok(())
     }
 
@@ -338,6 +680,155 @@ impl EventRecorder {
         Ok(())
     }
 
+    /// Record the session's name and the shell it runs in
+    pub fn record_session_metadata(
+        &mut self,
+        name: &str,
+        created_at: DateTime<Utc>,
+        shell: Option<ShellKind>,
+    ) -> crate::Result<()> {
+        if self.is_paused {
+            return Ok(());
+        }
+        self.sequence_counter += 1;
+        let event = Event::new(
+            &self.session_id,
+            EventType::SessionMetadata {
+                name: name.to_string(),
+                created_at,
+                shell,
+                timestamp: Utc::now(),
+            },
+            self.sequence_counter,
+        );
+
+        self.storage.store_event(&event)?;
+        Ok(())
+    }
+
+    /// Record that the user sent `signal` to the running `command`
+    pub fn record_interrupt(
+        &mut self,
+        command: &str,
+        signal: i32,
+        at: DateTime<Utc>,
+    ) -> crate::Result<()> {
+        if self.is_paused {
+            return Ok(());
+        }
+        self.sequence_counter += 1;
+        let command = if self.redact_output {
+            self.apply_redaction(command)
+        } else {
+            command.to_string()
+        };
+        let mut event = Event::new(
+            &self.session_id,
+            EventType::Interrupt {
+                signal,
+                command,
+                timestamp: at,
+            },
+            self.sequence_counter,
+        );
+        event.timestamp = at;
+
+        self.storage.store_event(&event)?;
+        Ok(())
+    }
+
+    /// Record the contents of `files` under `root` as the session's baseline
+    pub fn record_baseline(&mut self, root: &Path, files: &[PathBuf]) -> crate::Result<()> {
+        if self.is_paused {
+            return Ok(());
+        }
+        let mut manifest = Vec::with_capacity(files.len());
+        for path in files {
+            if self.storage.is_storage_path(path) {
+                continue;
+            }
+            let (Ok(metadata), Ok(relative)) = (fs::metadata(path), path.strip_prefix(root)) else {
+                continue;
+            };
+            manifest.push(ManifestEntry {
+                path: relative.to_string_lossy().to_string(),
+                size: metadata.len(),
+                mode: file_mode(&metadata),
+                content_hash: self.snapshot_file(&path.to_string_lossy()),
+            });
+        }
+        self.sequence_counter += 1;
+        let event = Event::new(
+            &self.session_id,
+            EventType::Baseline {
+                root: root.to_string_lossy().to_string(),
+                files: manifest,
+                timestamp: Utc::now(),
+            },
+            self.sequence_counter,
+        );
+
+        self.storage.store_event(&event)?;
+        self.known_files()?.apply(&event);
+        Ok(())
+    }
+
+    /// Record that the file effects of `commands` were undone
+    pub fn record_undo(
+        &mut self,
+        commands: Vec<String>,
+        restored: Vec<String>,
+        deleted: Vec<String>,
+    ) -> crate::Result<()> {
+        if self.is_paused {
+            return Ok(());
+        }
+        self.sequence_counter += 1;
+        let event = Event::new(
+            &self.session_id,
+            EventType::Undo {
+                commands,
+                restored,
+                deleted,
+                timestamp: Utc::now(),
+            },
+            self.sequence_counter,
+        );
+
+        self.storage.store_event(&event)?;
+        Ok(())
+    }
+
+    /// Record a custom event. `kind` must be non-empty and free of whitespace;
+    /// strings in `payload` are redacted like command output.
+    pub fn record_custom(&mut self, kind: &str, mut payload: serde_json::Value) -> crate::Result<()> {
+        if kind.is_empty() || kind.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err(crate::error::TimeLoopError::EventRecording(format!(
+                "Invalid custom event kind {:?}",
+                kind
+            )));
+        }
+        if self.is_paused {
+            return Ok(());
+        }
+        if self.redact_output {
+            self.redact_value(&mut payload);
+        }
+        self.sequence_counter += 1;
+        let event = Event::new(
+            &self.session_id,
+            EventType::Custom {
+                kind: kind.to_string(),
+                payload,
+                timestamp: Utc::now(),
+            },
+            self.sequence_counter,
+        );
+
+        self.storage.store_event(&event)?;
+        Ok(())
+    }
+
     pub fn get_events_for_session(&self, session_id: &str) -> crate::Result<Vec<Event>> {
         self.storage.get_events_for_session(session_id)
     }
@@ -383,7 +874,7 @@ impl EventRecorder {
             }
         }
         // Sort by length descending to replace longest matches first
-        self.redact_literals.sort_by(|a, b| b.len().cmp(&a.len()));
+        self.redact_literals.sort_by_key(|s| std::cmp::Reverse(s.len()));
     }
 
     fn apply_redaction(&self, text: &str) -> String {
@@ -401,6 +892,128 @@ impl EventRecorder {
         s
     }
 
+    /// Redact each stream's chunks as one text, so a secret split across two
+    /// reads is caught too. A redacted secret goes to the chunk it started in.
+    fn redact_chunks(&self, chunks: &mut [OutputChunk]) {
+        for stream in [OutputStream::Stdout, OutputStream::Stderr] {
+            let mut joined = String::new();
+            let mut bounds = Vec::new();
+            for chunk in chunks.iter().filter(|c| c.stream == stream) {
+                let start = joined.len();
+                joined.push_str(&chunk.data);
+                bounds.push(start..joined.len());
+            }
+            let secrets = self.redaction_spans(&joined);
+            let parts = chunks.iter_mut().filter(|c| c.stream == stream);
+            for (chunk, range) in parts.zip(bounds) {
+                let mut data = String::new();
+                let mut pos = range.start;
+                for (secret, replacement) in secrets.iter().filter(|(r, _)| r.start < range.end && r.end > range.start) {
+                    if secret.start >= range.start {
+                        data.push_str(&joined[pos..secret.start]);
+                        data.push_str(replacement);
+                    }
+                    pos = pos.max(secret.end.min(range.end));
+                }
+                data.push_str(&joined[pos..range.end]);
+                chunk.data = data;
+            }
+        }
+    }
+
+    /// Non-overlapping byte ranges of `text` that `apply_redaction` replaces,
+    /// in order, with what they are replaced by
+    fn redaction_spans(&self, text: &str) -> Vec<(std::ops::Range<usize>, &'static str)> {
+        let mut spans: Vec<_> = self
+            .redact_literals
+            .iter()
+            .flat_map(|secret| text.match_indices(secret.as_str()))
+            .map(|(i, m)| (i..i + m.len(), "[REDACTED_ENV]"))
+            .chain(
+                self.redact_patterns
+                    .iter()
+                    .flat_map(|re| re.find_iter(text))
+                    .map(|m| (m.range(), "[REDACTED]")),
+            )
+            .filter(|(r, _)| !r.is_empty())
+            .collect();
+        // Earlier and longer matches win, as with the literals' replace order
+        spans.sort_by_key(|(r, _)| (r.start, std::cmp::Reverse(r.end)));
+        let mut kept: Vec<(std::ops::Range<usize>, &'static str)> = Vec::new();
+        for span in spans {
+            if kept.last().is_none_or(|(last, _)| span.0.start >= last.end) {
+                kept.push(span);
+            }
+        }
+        kept
+    }
+
+    fn redact_value(&self, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::String(s) => *s = self.apply_redaction(s),
+            serde_json::Value::Array(items) => items.iter_mut().for_each(|v| self.redact_value(v)),
+            serde_json::Value::Object(fields) => fields.values_mut().for_each(|v| self.redact_value(v)),
+            _ => {}
+        }
+    }
+
+    /// Store the file's contents in the blob store and return their hash. Files
+    /// the blob policy excludes are only hashed.
+    fn snapshot_file(&self, path: &str) -> Option<String> {
+        match self.storage.store_file_blob(Path::new(path)) {
+            Ok(Some(hash)) => Some(hash),
+            Ok(None) => self.compute_file_hash(path),
+            Err(e) => {
+                tracing::debug!("Could not snapshot {}: {}", path, e);
+                self.compute_file_hash(path)
+            }
+        }
+    }
+
+    /// The session's files as recorded before the event being recorded
+    fn known_files(&mut self) -> crate::Result<&mut WorkspaceState> {
+        if self.known_files.is_none() {
+            let mut events = self.storage.get_events_for_session(&self.session_id)?;
+            events.sort_by_key(|e| e.sequence_number);
+            self.known_files = Some(WorkspaceState::replay(&events));
+        }
+        Ok(self.known_files.get_or_insert_with(WorkspaceState::default))
+    }
+
+    /// A unified diff of `path` from its previous known contents to those
+    /// stored under `hash`, if both are text and within the diff policy
+    fn diff_against_known(&mut self, path: &str, hash: &str) -> Option<String> {
+        let previous = match self.known_files() {
+            Ok(known) => known.files().get(Path::new(path)).cloned().flatten()?,
+            Err(e) => {
+                tracing::debug!("Could not load the files of {}: {}", self.session_id, e);
+                return None;
+            }
+        };
+        if previous == hash {
+            return None;
+        }
+        let old = self.storage.get_blob(&previous).ok()??;
+        let new = self.storage.get_blob(hash).ok()??;
+        let limit = self.diff_policy.max_file_size_bytes;
+        if old.len() as u64 > limit || new.len() as u64 > limit {
+            return None;
+        }
+        let (Some(old), Some(new)) = (as_text(&old), as_text(&new)) else {
+            return None;
+        };
+        let diff = TextDiff::from_lines(old, new)
+            .unified_diff()
+            .header(path, path)
+            .to_string();
+        let diff = if self.redact_output {
+            self.apply_redaction(&diff)
+        } else {
+            diff
+        };
+        (!diff.is_empty() && diff.len() <= self.diff_policy.max_diff_bytes).then_some(diff)
+    }
+
     fn compute_file_hash(&self, path: &str) -> Option<String> {
         let path = Path::new(path);
         if !path.exists() {
@@ -423,6 +1036,83 @@ impl EventRecorder {
     }
 }
 
+/// How long the file watcher may take to deliver a change, so changes
+/// recorded this soon after a command ended may still be its own
+pub const FILE_WATCH_SETTLE: std::time::Duration = std::time::Duration::from_millis(300);
+
+/// Sort a session's events so each command's file changes come right before
+/// its `Command` event. The watcher can deliver a change after the command that
+/// made it was stored, so a change recorded while a command ran, or less than
+/// `FILE_WATCH_SETTLE` after it ended, goes with that command. Other changes
+/// keep their place, before the next command.
+pub fn sort_by_command(events: &mut [Event]) {
+    events.sort_by_key(|e| e.sequence_number);
+    let settle = chrono::Duration::from_std(FILE_WATCH_SETTLE).unwrap_or_default();
+    let runs: Vec<(u64, DateTime<Utc>, DateTime<Utc>)> = events
+        .iter()
+        .filter_map(|e| match &e.event_type {
+            EventType::Command {
+                started_at, ended_at, ..
+            } => {
+                let ended = ended_at.unwrap_or(e.timestamp);
+                Some((e.sequence_number, started_at.unwrap_or(ended), ended))
+            }
+            _ => None,
+        })
+        .collect();
+    let owner = |at: DateTime<Utc>| {
+        runs.iter()
+            .find(|(_, start, end)| *start <= at && at <= *end)
+            .or_else(|| runs.iter().rev().find(|(_, _, end)| *end < at && at <= *end + settle))
+            .map(|(seq, _, _)| *seq)
+    };
+    // A command sorts after the changes placed before it
+    events.sort_by_cached_key(|e| match e.event_type {
+        EventType::FileChange { .. } => (owner(e.timestamp).unwrap_or(e.sequence_number), 0, e.sequence_number),
+        EventType::Command { .. } => (e.sequence_number, 1, e.sequence_number),
+        _ => (e.sequence_number, 0, e.sequence_number),
+    });
+}
+
+/// The lines of a unified diff from its first hunk on, without the `---` and
+/// `+++` file names before it
+pub fn diff_hunks(diff: &str) -> impl Iterator<Item = &str> {
+    diff.lines().skip_while(|l| !l.starts_with("@@"))
+}
+
+/// The lines added and removed by a unified diff
+pub fn diff_stat(diff: &str) -> (usize, usize) {
+    diff_hunks(diff)
+        .fold((0, 0), |(added, removed), line| match line.as_bytes().first() {
+            Some(b'+') => (added + 1, removed),
+            Some(b'-') => (added, removed + 1),
+            _ => (added, removed),
+        })
+}
+
+/// `bytes` as text, or None for binary contents
+fn as_text(bytes: &[u8]) -> Option<&str> {
+    if bytes.iter().take(8000).any(|&b| b == 0) {
+        return None;
+    }
+    std::str::from_utf8(bytes).ok()
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn file_mode(metadata: &fs::Metadata) -> u32 {
+    if metadata.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -435,20 +1125,134 @@ mod tests {
         let mut recorder =
             EventRecorder::with_storage_and_redaction("redact-session", storage, true, None);
 
+        let now = Utc::now();
         recorder
-            .record_command(
-                "echo secret",
-                "password=supersecret token=abc123",
-                0,
-                "/tmp",
-            )
+            .record_command_execution(CommandRecord {
+                command: "echo secret".to_string(),
+                stdout: "password=supersecret token=abc123".to_string(),
+                stderr: "warning: password=supersecret".to_string(),
+                output_chunks: vec![OutputChunk {
+                    offset_ms: 5,
+                    stream: OutputStream::Stdout,
+                    data: "password=supersecret token=abc123\r\n".to_string(),
+                }],
+                exit_code: 0,
+                signal: None,
+                core_dumped: false,
+                resource_usage: None,
+                working_directory: "/tmp".to_string(),
+                environment: Some(vec!["API_TOKEN=abc123".to_string(), "MODE=dev".to_string()]),
+                started_at: now - chrono::Duration::milliseconds(1500),
+                ended_at: now,
+            })
             .unwrap();
         let events = recorder.get_events_for_session("redact-session").unwrap();
         assert_eq!(events.len(), 1);
-        if let EventType::Command { output, .. } = &events[0].event_type {
+        if let EventType::Command {
+            output,
+            stderr,
+            output_chunks,
+            environment,
+            duration_ms,
+            ..
+        } = &events[0].event_type
+        {
             assert!(output.contains("[REDACTED]"));
             assert!(!output.contains("supersecret"));
             assert!(!output.contains("abc123"));
+            assert!(!stderr.contains("supersecret"));
+            assert!(!output_chunks[0].data.contains("supersecret"));
+            assert_eq!(*duration_ms, Some(1500));
+            // Variables with redacted values are dropped rather than kept redacted
+            assert_eq!(environment, &Some(vec!["MODE=dev".to_string()]));
+        } else {
+            panic!("expected command event");
+        }
+    }
+
+    #[test]
+    fn test_redaction_of_secret_split_across_chunks() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let db_path = tmp_dir.path().join("events_redaction_chunks.db");
+        let storage = crate::storage::Storage::with_path(db_path.to_str().unwrap()).unwrap();
+        let mut recorder =
+            EventRecorder::with_storage_and_redaction("redact-chunks", storage, true, None);
+
+        let chunk = |offset_ms, stream, data: &str| OutputChunk {
+            offset_ms,
+            stream,
+            data: data.to_string(),
+        };
+        let now = Utc::now();
+        recorder
+            .record_command_execution(CommandRecord {
+                command: "./login".to_string(),
+                stdout: "user=me password=hunter2\ndone\n".to_string(),
+                stderr: " ord=x\n".to_string(),
+                output_chunks: vec![
+                    chunk(0, OutputStream::Stdout, "user=me passw"),
+                    chunk(1, OutputStream::Stderr, " ord=x\n"),
+                    chunk(2, OutputStream::Stdout, "ord=hun"),
+                    chunk(3, OutputStream::Stdout, "ter2\ndone\n"),
+                ],
+                exit_code: 0,
+                signal: None,
+                core_dumped: false,
+                resource_usage: None,
+                working_directory: "/tmp".to_string(),
+                environment: None,
+                started_at: now,
+                ended_at: now,
+            })
+            .unwrap();
+        let events = recorder.get_events_for_session("redact-chunks").unwrap();
+        let EventType::Command { output_chunks, .. } = &events[0].event_type else {
+            panic!("expected command event");
+        };
+        let data: Vec<_> = output_chunks.iter().map(|c| c.data.as_str()).collect();
+        // The secret is replaced in the chunk it started in, and the rest of it
+        // is dropped from the next one of the same stream
+        assert_eq!(data, vec!["user=me [REDACTED]", " ord=x\n", "", "\ndone\n"]);
+    }
+
+    #[test]
+    fn test_output_chunk_collector() {
+        let mut collector = OutputChunkCollector::new();
+        // "é" split across two reads
+        collector.push(OutputStream::Stdout, b"caf\xc3");
+        collector.push(OutputStream::Stdout, b"\xa9\n");
+        collector.push(OutputStream::Stderr, b"oops\n");
+        std::thread::sleep(std::time::Duration::from_millis(80));
+        collector.push(OutputStream::Stdout, b"done\n");
+
+        let chunks = collector.finish();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].data, "café\n");
+        assert_eq!(chunks[1].stream, OutputStream::Stderr);
+        assert_eq!(chunks[2].data, "done\n");
+        assert!(chunks[2].offset_ms >= 80);
+    }
+
+    #[test]
+    fn test_old_command_events_deserialize() {
+        let json = r#"{"Command":{"command":"ls","output":"a\nb\n","exit_code":0,"working_directory":"/tmp","timestamp":"2024-01-01T00:00:00Z"}}"#;
+        let event_type: EventType = serde_json::from_str(json).unwrap();
+        if let EventType::Command {
+            stderr,
+            output_chunks,
+            started_at,
+            duration_ms,
+            signal,
+            resource_usage,
+            ..
+        } = event_type
+        {
+            assert!(signal.is_none());
+            assert!(resource_usage.is_none());
+            assert!(stderr.is_empty());
+            assert!(output_chunks.is_empty());
+            assert!(started_at.is_none());
+            assert!(duration_ms.is_none());
         } else {
             panic!("expected command event");
         }
@@ -481,8 +1285,65 @@ mod tests {
             assert!(content_hash.is_some());
             // SHA256 of "Hello world"
             assert_eq!(content_hash.as_ref().unwrap(), "64ec88ca00b268e5ba1a35678a1b5316d212f4f366b2477232534a8aeca37f3c");
+            // The contents themselves are kept in the blob store
+            let blob = recorder.storage().get_blob(content_hash.as_ref().unwrap()).unwrap();
+            assert_eq!(blob.unwrap(), b"Hello world");
         } else {
             panic!("expected file change event");
         }
     }
+
+    #[test]
+    fn test_modified_text_files_get_a_diff() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let db_path = tmp_dir.path().join("events_diff.db");
+        let storage = crate::storage::Storage::with_path(db_path.to_str().unwrap()).unwrap();
+        let mut recorder = EventRecorder::with_storage("diff-session", storage.clone());
+        let text = tmp_dir.path().join("notes.txt");
+        let binary = tmp_dir.path().join("data.bin");
+        let path = |p: &Path| p.to_str().unwrap().to_string();
+
+        fs::write(&text, "one\ntwo\nthree\n").unwrap();
+        recorder.record_file_change(&path(&text), FileChangeType::Created).unwrap();
+        fs::write(&binary, b"\0\x01").unwrap();
+        recorder.record_file_change(&path(&binary), FileChangeType::Created).unwrap();
+
+        // A new recorder picks up the previous versions from storage
+        let mut recorder = EventRecorder::with_storage("diff-session", storage);
+        fs::write(&text, "one\n2\nthree\n").unwrap();
+        recorder.record_file_change(&path(&text), FileChangeType::Modified).unwrap();
+        fs::write(&binary, b"\0\x02").unwrap();
+        recorder.record_file_change(&path(&binary), FileChangeType::Modified).unwrap();
+
+        let mut events = recorder.get_events_for_session("diff-session").unwrap();
+        events.sort_by_key(|e| e.sequence_number);
+        let diffs: Vec<_> = events
+            .iter()
+            .map(|e| match &e.event_type {
+                EventType::FileChange { diff, .. } => diff.clone(),
+                _ => panic!("expected file change event"),
+            })
+            .collect();
+        assert!(diffs[0].is_none() && diffs[1].is_none() && diffs[3].is_none());
+        let diff = diffs[2].as_ref().unwrap();
+        assert!(diff.contains("-two\n+2\n"));
+        assert_eq!(diff_stat(diff), (1, 1));
+        assert!(events[2].event_type.describe().ends_with("(+1 -1)"));
+
+        // Content lines that look like the file names still count
+        let sql = tmp_dir.path().join("schema.sql");
+        let storage = crate::storage::Storage::with_path(db_path.to_str().unwrap()).unwrap();
+        let mut recorder = EventRecorder::with_storage("sql-session", storage);
+        fs::write(&sql, "-- comment\nselect 1;\n").unwrap();
+        recorder.record_file_change(&path(&sql), FileChangeType::Created).unwrap();
+        fs::write(&sql, "select 1;\n++i\n").unwrap();
+        recorder.record_file_change(&path(&sql), FileChangeType::Modified).unwrap();
+        let events = recorder.get_events_for_session("sql-session").unwrap();
+        let last = events.iter().max_by_key(|e| e.sequence_number).unwrap();
+        let EventType::FileChange { diff: Some(diff), .. } = &last.event_type else {
+            panic!("expected a diff, got {:?}", last.event_type);
+        };
+        assert_eq!(diff_stat(diff), (1, 1));
+        assert!(diff_hunks(diff).any(|l| l == "--- comment"));
+    }
 }
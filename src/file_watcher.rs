@@ -30,7 +30,7 @@ pub struct FileWatcher {
 }
 
 // Helper to determine if we should use Glob or Exact
-fn parse_ignore_pattern(pattern: &str) -> IgnorePattern {
+pub(crate) fn parse_ignore_pattern(pattern: &str) -> IgnorePattern {
     if pattern.contains('*') || pattern.contains('?') || pattern.contains('[') {
         match Pattern::new(pattern) {
             Ok(p) => IgnorePattern::Glob(p),
@@ -45,7 +45,7 @@ fn parse_ignore_pattern(pattern: &str) -> IgnorePattern {
 }
 
 // Static helper to avoid code duplication and allow usage without &self (e.g. in threads)
-fn should_ignore_path(path: &Path, ignore_patterns: &[IgnorePattern]) -> bool {
+pub(crate) fn should_ignore_path(path: &Path, ignore_patterns: &[IgnorePattern]) -> bool {
     let path_str = path.to_string_lossy();
     // Normalize path separators to forward slashes for glob matching (Windows compatibility)
     let normalized_path = if std::path::MAIN_SEPARATOR == '\\' {
@@ -80,6 +80,23 @@ fn should_ignore_path(path: &Path, ignore_patterns: &[IgnorePattern]) -> bool {
     })
 }
 
+fn scan_dir(dir: &Path, recursive: bool, ignore_patterns: &[IgnorePattern], files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if should_ignore_path(&path, ignore_patterns) {
+            continue;
+        }
+        match entry.file_type() {
+            Ok(t) if t.is_dir() && recursive => scan_dir(&path, recursive, ignore_patterns, files),
+            Ok(t) if t.is_file() => files.push(path),
+            _ => {}
+        }
+    }
+}
+
 impl FileWatcher {
     pub fn new(file_change_callback: FileChangeCallback) -> crate::Result<Self> {
         let defaults: &[&str] = &[
@@ -118,6 +135,18 @@ impl FileWatcher {
         should_ignore_path(path, &self.ignore_patterns)
     }
 
+    /// Every file under the watched paths that isn't ignored, in path order.
+    /// Symlinks aren't followed and unreadable directories are skipped.
+    pub fn scan(&self) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        for (path, recursive) in &self.watched_paths {
+            scan_dir(path, *recursive, &self.ignore_patterns, &mut files);
+        }
+        files.sort();
+        files.dedup();
+        files
+    }
+
     pub async fn start_watching(&mut self) -> crate::Result<()> {
         let (tx, mut rx) = tokio_mpsc::channel(100);
 
@@ -280,4 +309,23 @@ mod tests {
         assert!(file_watcher.should_ignore(&PathBuf::from("src/utils/file.rs.bk")));
         assert!(!file_watcher.should_ignore(&PathBuf::from("src/utils/file.rs")));
     }
+
+    #[test]
+    fn test_scan_skips_ignored_paths() {
+        let tmp_dir = TempDir::new().unwrap();
+        let root = tmp_dir.path();
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::create_dir_all(root.join("target/debug")).unwrap();
+        std::fs::write(root.join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(root.join("README.md"), "hi").unwrap();
+        std::fs::write(root.join("build.log"), "noise").unwrap();
+        std::fs::write(root.join("target/debug/app"), "bin").unwrap();
+
+        let callback: FileChangeCallback = Arc::new(Mutex::new(
+            move |_path: &str, _change_type: FileChangeType| Ok(()),
+        ));
+        let mut file_watcher = FileWatcher::new(callback).unwrap();
+        file_watcher.add_watch_path(root.to_path_buf(), true).unwrap();
+        assert_eq!(file_watcher.scan(), vec![root.join("README.md"), root.join("src/main.rs")]);
+    }
 }
@@ -1,21 +1,43 @@
 #[cfg(feature = "ai")]
 pub mod ai;
+pub mod bisect;
+pub mod blob_store;
+pub mod bookmark;
 pub mod branch;
+pub mod completion;
+pub mod custom_event;
 pub mod error;
 pub mod events;
 pub mod file_watcher;
+pub mod line_editor;
+pub mod meta_command;
+pub mod process;
+pub mod prompt_marks;
+pub mod pty;
+pub mod record;
 pub mod replay;
+pub mod rerun;
+pub mod schema;
 pub mod session;
+pub mod shell;
+pub mod shell_adapter;
 pub mod storage;
 pub mod terminal;
+pub mod undo;
+pub mod workspace;
 pub mod gpu_renderer;
 pub mod gpu_terminal;
 
+pub use bookmark::{Bookmark, BookmarkManager};
+pub use custom_event::CustomEventRenderer;
 pub use branch::{BranchManager, TimelineBranch};
 pub use error::TimeLoopError;
-pub use events::{Event, EventRecorder, EventType, FileChangeType};
+pub use events::{
+    CommandRecord, DiffPolicy, Event, EventRecorder, EventType, FileChangeType, ManifestEntry,
+    OutputChunk, OutputStream, ResourceUsage,
+};
 pub use replay::ReplayEngine;
-pub use session::{Session, SessionManager, SessionSummary};
+pub use session::{Session, SessionEdit, SessionManager, SessionSummary};
 pub use storage::Storage;
 pub use gpu_renderer::{GpuRenderer, GlyphInstance, Uniforms};
 pub use gpu_terminal::GpuTerminalEmulator;
@@ -67,6 +89,7 @@ mod tests {
             ended_at: None,
             parent_session_id: None,
             branch_name: None,
+            ..Default::default()
         };
 
         storage.store_session(&session).unwrap();
@@ -0,0 +1,623 @@
+use crate::completion::{self, Completer, Completion};
+use crate::pty::terminal_output_to_text;
+use crossterm::{
+    cursor::{MoveToColumn, MoveUp},
+    event::{self, Event as CEvent, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    terminal::{self, Clear, ClearType},
+    QueueableCommand,
+};
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use unicode_width::UnicodeWidthStr;
+
+/// Outcome of reading one line at the prompt
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReadLine {
+    /// The user pressed Enter
+    Line(String),
+    /// The user pressed Ctrl-C; the line was discarded
+    Interrupted,
+    /// The user pressed Ctrl-D on an empty line
+    Eof,
+}
+
+/// What the editor wants done after handling a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditAction {
+    Continue,
+    Submit,
+    Interrupt,
+    Eof,
+    ClearScreen,
+    Complete,
+}
+
+/// State of an incremental reverse history search (Ctrl-R)
+#[derive(Debug, Clone)]
+struct ReverseSearch {
+    query: String,
+    match_index: Option<usize>,
+    failed: bool,
+    // Line being edited when the search started, restored on Ctrl-G
+    original: (Vec<char>, usize),
+}
+
+/// Emacs-style line editor for the recording shell's prompt. Key handling is
+/// kept apart from terminal I/O so it can be driven directly in tests; see
+/// `read_line` for the interactive loop.
+#[derive(Debug, Clone, Default)]
+pub struct LineEditor {
+    buffer: Vec<char>,
+    cursor: usize,
+    // Position in the history while browsing with Up/Down, None on a new line
+    history_index: Option<usize>,
+    // The new line being typed before the user started browsing history
+    stashed: Option<Vec<char>>,
+    search: Option<ReverseSearch>,
+}
+
+impl LineEditor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The current contents of the line
+    pub fn line(&self) -> String {
+        self.buffer.iter().collect()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn is_searching(&self) -> bool {
+        self.search.is_some()
+    }
+
+    /// Apply a key press. `history` is ordered oldest first.
+    pub fn handle_key(&mut self, key: &KeyEvent, history: &VecDeque<String>) -> EditAction {
+        if self.search.is_some() {
+            if let Some(action) = self.handle_search_key(key, history) {
+                return action;
+            }
+        }
+
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+        match key.code {
+            KeyCode::Enter => return EditAction::Submit,
+            KeyCode::Char('c') if ctrl => return EditAction::Interrupt,
+            KeyCode::Char('d') if ctrl => {
+                if self.buffer.is_empty() {
+                    return EditAction::Eof;
+                }
+                self.delete_range(self.cursor, self.cursor + 1);
+            }
+            KeyCode::Char('l') if ctrl => return EditAction::ClearScreen,
+            KeyCode::Tab => return EditAction::Complete,
+            KeyCode::Char('r') if ctrl => {
+                self.search = Some(ReverseSearch {
+                    query: String::new(),
+                    match_index: None,
+                    failed: false,
+                    original: (self.buffer.clone(), self.cursor),
+                });
+            }
+            KeyCode::Char('a') if ctrl => self.cursor = 0,
+            KeyCode::Char('e') if ctrl => self.cursor = self.buffer.len(),
+            KeyCode::Char('b') if ctrl => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Char('f') if ctrl => self.cursor = (self.cursor + 1).min(self.buffer.len()),
+            KeyCode::Char('p') if ctrl => self.history_previous(history),
+            KeyCode::Char('n') if ctrl => self.history_next(history),
+            KeyCode::Char('u') if ctrl => self.delete_range(0, self.cursor),
+            KeyCode::Char('k') if ctrl => self.delete_range(self.cursor, self.buffer.len()),
+            KeyCode::Char('w') if ctrl => {
+                let mut start = self.cursor;
+                while start > 0 && self.buffer[start - 1].is_whitespace() {
+                    start -= 1;
+                }
+                while start > 0 && !self.buffer[start - 1].is_whitespace() {
+                    start -= 1;
+                }
+                self.delete_range(start, self.cursor);
+            }
+            KeyCode::Char('b') if alt => self.cursor = self.word_start_before(),
+            KeyCode::Char('f') if alt => self.cursor = self.word_end_after(),
+            KeyCode::Char('d') if alt => self.delete_range(self.cursor, self.word_end_after()),
+            KeyCode::Backspace if alt => self.delete_range(self.word_start_before(), self.cursor),
+            KeyCode::Left if ctrl || alt => self.cursor = self.word_start_before(),
+            KeyCode::Right if ctrl || alt => self.cursor = self.word_end_after(),
+            KeyCode::Char(c) if !ctrl && !alt && !c.is_control() => {
+                self.buffer.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.delete_range(self.cursor - 1, self.cursor)
+            }
+            KeyCode::Delete => self.delete_range(self.cursor, self.cursor + 1),
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.buffer.len()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.buffer.len(),
+            KeyCode::Up => self.history_previous(history),
+            KeyCode::Down => self.history_next(history),
+            _ => {}
+        }
+        EditAction::Continue
+    }
+
+    /// Keys typed during a reverse search. Returns None when the key ends the
+    /// search and should then be handled as a normal editing key.
+    fn handle_search_key(&mut self, key: &KeyEvent, history: &VecDeque<String>) -> Option<EditAction> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let search = self.search.as_mut()?;
+        match key.code {
+            KeyCode::Char('r') if ctrl => {
+                let before = search.match_index.unwrap_or(history.len());
+                Self::search_from(search, history, before);
+            }
+            KeyCode::Char('g') if ctrl => {
+                let (buffer, cursor) = search.original.clone();
+                self.buffer = buffer;
+                self.cursor = cursor;
+                self.search = None;
+            }
+            KeyCode::Esc => {
+                self.accept_search(history);
+            }
+            KeyCode::Char('c') if ctrl => {
+                self.search = None;
+                return Some(EditAction::Interrupt);
+            }
+            KeyCode::Char(c) if !ctrl && !c.is_control() => {
+                search.query.push(c);
+                // A longer query may still match the current entry
+                let before = search.match_index.map(|i| i + 1).unwrap_or(history.len());
+                Self::search_from(search, history, before);
+            }
+            KeyCode::Backspace => {
+                search.query.pop();
+                search.match_index = None;
+                Self::search_from(search, history, history.len());
+            }
+            _ => {
+                self.accept_search(history);
+                return None;
+            }
+        }
+        Some(EditAction::Continue)
+    }
+
+    /// Find the most recent entry before `before` containing the query
+    fn search_from(search: &mut ReverseSearch, history: &VecDeque<String>, before: usize) {
+        if search.query.is_empty() {
+            search.failed = false;
+            return;
+        }
+        let found = (0..before.min(history.len()))
+            .rev()
+            .find(|&i| history[i].contains(&search.query));
+        search.failed = found.is_none();
+        if found.is_some() {
+            search.match_index = found;
+        }
+    }
+
+    /// Leave the search with the matched entry as the line being edited
+    fn accept_search(&mut self, history: &VecDeque<String>) {
+        let Some(search) = self.search.take() else {
+            return;
+        };
+        if let Some(entry) = search.match_index.and_then(|i| history.get(i)) {
+            let position = entry.find(&search.query).unwrap_or(entry.len());
+            self.buffer = entry.chars().collect();
+            self.cursor = entry[..position].chars().count();
+            self.history_index = search.match_index;
+        }
+    }
+
+    /// Replace the word being completed with the candidates' common prefix.
+    /// A single candidate is completed in full, followed by a space unless it
+    /// is a directory. Returns false when the line was left unchanged.
+    pub fn apply_completion(&mut self, completion: &Completion) -> bool {
+        let replacement = match completion.candidates.as_slice() {
+            [] => return false,
+            [only] if only.ends_with('/') => completion::escape(only),
+            [only] => format!("{} ", completion::escape(only)),
+            _ => completion::escape(&completion.common_prefix()),
+        };
+        let start = completion.start.min(self.cursor);
+        let current: String = self.buffer[start..self.cursor].iter().collect();
+        if replacement == current {
+            return false;
+        }
+        self.buffer.splice(start..self.cursor, replacement.chars());
+        self.cursor = start + replacement.chars().count();
+        true
+    }
+
+    fn history_previous(&mut self, history: &VecDeque<String>) {
+        let index = match self.history_index {
+            Some(0) => return,
+            Some(i) => i - 1,
+            None if history.is_empty() => return,
+            None => {
+                self.stashed = Some(self.buffer.clone());
+                history.len() - 1
+            }
+        };
+        self.history_index = Some(index);
+        self.set_line(history[index].chars().collect());
+    }
+
+    fn history_next(&mut self, history: &VecDeque<String>) {
+        let Some(index) = self.history_index else {
+            return;
+        };
+        if index + 1 < history.len() {
+            self.history_index = Some(index + 1);
+            self.set_line(history[index + 1].chars().collect());
+        } else {
+            self.history_index = None;
+            let stashed = self.stashed.take().unwrap_or_default();
+            self.set_line(stashed);
+        }
+    }
+
+    fn set_line(&mut self, buffer: Vec<char>) {
+        self.buffer = buffer;
+        self.cursor = self.buffer.len();
+    }
+
+    fn delete_range(&mut self, start: usize, end: usize) {
+        let end = end.min(self.buffer.len());
+        if start < end {
+            self.buffer.drain(start..end);
+            self.cursor = start;
+        }
+    }
+
+    fn word_start_before(&self) -> usize {
+        let mut i = self.cursor;
+        while i > 0 && !self.buffer[i - 1].is_alphanumeric() {
+            i -= 1;
+        }
+        while i > 0 && self.buffer[i - 1].is_alphanumeric() {
+            i -= 1;
+        }
+        i
+    }
+
+    fn word_end_after(&self) -> usize {
+        let mut i = self.cursor;
+        while i < self.buffer.len() && !self.buffer[i].is_alphanumeric() {
+            i += 1;
+        }
+        while i < self.buffer.len() && self.buffer[i].is_alphanumeric() {
+            i += 1;
+        }
+        i
+    }
+
+    /// Prompt, line and cursor column (in characters) to show on screen.
+    /// During a reverse search the prompt is replaced by the search status.
+    fn display(&self, prompt: &str, history: &VecDeque<String>) -> (String, String, usize) {
+        match &self.search {
+            Some(search) => {
+                let label = if search.failed {
+                    "failed reverse-i-search"
+                } else {
+                    "reverse-i-search"
+                };
+                let prompt = format!("({})`{}': ", label, search.query);
+                let entry = search
+                    .match_index
+                    .and_then(|i| history.get(i))
+                    .cloned()
+                    .unwrap_or_default();
+                let cursor = entry
+                    .find(&search.query)
+                    .map(|p| entry[..p].chars().count())
+                    .unwrap_or(0);
+                (prompt, entry, cursor)
+            }
+            None => (prompt.to_string(), self.line(), self.cursor),
+        }
+    }
+}
+
+/// Read a line from the user's terminal, which must already be in raw mode.
+/// `prompt` may contain color escape sequences. Every key event is passed to
+/// `on_key` before it is applied so that it can be recorded. Tab completes
+/// with `completer`, listing the candidates when there is nothing to insert.
+pub fn read_line(
+    prompt: &str,
+    history: &VecDeque<String>,
+    completer: &Completer,
+    on_key: &mut dyn FnMut(&KeyEvent) -> crate::Result<()>,
+) -> crate::Result<ReadLine> {
+    let mut stdout = io::stdout();
+    let mut editor = LineEditor::new();
+    let mut cursor_row = 0;
+    render(&mut stdout, &editor, prompt, history, &mut cursor_row)?;
+
+    loop {
+        let keys = match event::read()? {
+            CEvent::Key(key) if key.kind != KeyEventKind::Release => vec![key],
+            CEvent::Paste(text) => text
+                .chars()
+                .map(|c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE))
+                .collect(),
+            CEvent::Resize(..) => {
+                render(&mut stdout, &editor, prompt, history, &mut cursor_row)?;
+                continue;
+            }
+            _ => continue,
+        };
+
+        for key in keys {
+            on_key(&key)?;
+            match editor.handle_key(&key, history) {
+                EditAction::Continue => {}
+                EditAction::ClearScreen => {
+                    stdout.queue(Clear(ClearType::All))?;
+                    stdout.queue(crossterm::cursor::MoveTo(0, 0))?;
+                    cursor_row = 0;
+                }
+                EditAction::Complete => {
+                    let completion = completer.complete(&editor.line(), editor.cursor());
+                    if !editor.apply_completion(&completion) && completion.candidates.len() > 1 {
+                        list_candidates(&mut stdout, &editor, prompt, history, &mut cursor_row, &completion)?;
+                    }
+                }
+                action => {
+                    // Leave the finished line on screen and move below it
+                    let result = match action {
+                        EditAction::Submit => ReadLine::Line(editor.line()),
+                        EditAction::Interrupt => ReadLine::Interrupted,
+                        _ => ReadLine::Eof,
+                    };
+                    editor.search = None;
+                    editor.cursor = editor.buffer.len();
+                    render(&mut stdout, &editor, prompt, history, &mut cursor_row)?;
+                    if result == ReadLine::Interrupted {
+                        write!(stdout, "^C")?;
+                    }
+                    write!(stdout, "\r\n")?;
+                    stdout.flush()?;
+                    return Ok(result);
+                }
+            }
+        }
+        render(&mut stdout, &editor, prompt, history, &mut cursor_row)?;
+    }
+}
+
+/// Redraw the prompt and line, which may wrap over several rows. `cursor_row`
+/// is the row of the cursor relative to the first row of the prompt.
+fn render(
+    out: &mut impl Write,
+    editor: &LineEditor,
+    prompt: &str,
+    history: &VecDeque<String>,
+    cursor_row: &mut u16,
+) -> crate::Result<()> {
+    // Some terminals (e.g. a freshly opened PTY) report a width of zero
+    let cols = match terminal::size() {
+        Ok((cols, _)) if cols > 0 => cols as usize,
+        _ => 80,
+    };
+    let (prompt, line, cursor) = editor.display(prompt, history);
+    let prompt_width = terminal_output_to_text(prompt.as_bytes()).width();
+    let before_cursor: String = line.chars().take(cursor).collect();
+
+    if *cursor_row > 0 {
+        out.queue(MoveUp(*cursor_row))?;
+    }
+    out.queue(MoveToColumn(0))?;
+    out.queue(Clear(ClearType::FromCursorDown))?;
+    write!(out, "{}{}", prompt, line)?;
+
+    let end = prompt_width + line.width();
+    // A line exactly filling the last row leaves the cursor pending a wrap
+    if end > 0 && end.is_multiple_of(cols) {
+        write!(out, "\r\n")?;
+    }
+    let target = prompt_width + before_cursor.width();
+    let (end_row, target_row) = (end / cols, target / cols);
+    if end_row > target_row {
+        out.queue(MoveUp((end_row - target_row) as u16))?;
+    }
+    out.queue(MoveToColumn((target % cols) as u16))?;
+    *cursor_row = target_row as u16;
+    out.flush()?;
+    Ok(())
+}
+
+/// Print completion candidates in columns below the line, then redraw the
+/// prompt underneath them
+fn list_candidates(
+    out: &mut impl Write,
+    editor: &LineEditor,
+    prompt: &str,
+    history: &VecDeque<String>,
+    cursor_row: &mut u16,
+    completion: &Completion,
+) -> crate::Result<()> {
+    // Move below the whole line before printing
+    let mut at_end = editor.clone();
+    at_end.cursor = at_end.buffer.len();
+    render(out, &at_end, prompt, history, cursor_row)?;
+    write!(out, "\r\n")?;
+
+    let cols = match terminal::size() {
+        Ok((cols, _)) if cols > 0 => cols as usize,
+        _ => 80,
+    };
+    // Paths are listed by their last component, as shells do
+    let names: Vec<&str> = completion
+        .candidates
+        .iter()
+        .map(|c| {
+            let trimmed = c.trim_end_matches('/');
+            let start = trimmed.rfind('/').map(|i| i + 1).unwrap_or(0);
+            &c[start..]
+        })
+        .collect();
+    let width = names.iter().map(|n| n.width()).max().unwrap_or(0) + 2;
+    let per_row = (cols / width).max(1);
+    for row in names.chunks(per_row) {
+        for name in row {
+            write!(out, "{}{}", name, " ".repeat(width - name.width()))?;
+        }
+        write!(out, "\r\n")?;
+    }
+
+    *cursor_row = 0;
+    render(out, editor, prompt, history, cursor_row)
+}
+
+/// Readable name for a key event as recorded in the session, e.g. "a",
+/// "Enter", "Ctrl+R" or "Alt+Backspace"
+pub fn key_name(key: &KeyEvent) -> String {
+    let mut name = String::new();
+    if key.modifiers.contains(KeyModifiers::CONTROL) {
+        name.push_str("Ctrl+");
+    }
+    if key.modifiers.contains(KeyModifiers::ALT) {
+        name.push_str("Alt+");
+    }
+    match key.code {
+        KeyCode::Char(' ') => name.push_str("Space"),
+        KeyCode::Char(c) if !name.is_empty() => name.push(c.to_ascii_uppercase()),
+        KeyCode::Char(c) => name.push(c),
+        KeyCode::F(n) => name.push_str(&format!("F{}", n)),
+        KeyCode::BackTab => name.push_str("Shift+Tab"),
+        code => name.push_str(&format!("{:?}", code)),
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(editor: &mut LineEditor, history: &VecDeque<String>, keys: &[KeyEvent]) -> EditAction {
+        let mut action = EditAction::Continue;
+        for key in keys {
+            action = editor.handle_key(key, history);
+        }
+        action
+    }
+
+    fn text(s: &str) -> Vec<KeyEvent> {
+        s.chars()
+            .map(|c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE))
+            .collect()
+    }
+
+    fn ctrl(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL)
+    }
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn test_editing_keys() {
+        let history = VecDeque::new();
+        let mut editor = LineEditor::new();
+        press(&mut editor, &history, &text("echo hello world"));
+        press(&mut editor, &history, &[ctrl('w')]);
+        assert_eq!(editor.line(), "echo hello ");
+
+        press(&mut editor, &history, &[ctrl('a'), key(KeyCode::Delete)]);
+        press(&mut editor, &history, &text("E"));
+        assert_eq!(editor.line(), "Echo hello ");
+        assert_eq!(editor.cursor(), 1);
+
+        press(&mut editor, &history, &[ctrl('e'), key(KeyCode::Left), ctrl('k')]);
+        assert_eq!(editor.line(), "Echo hello");
+        press(&mut editor, &history, &[ctrl('u')]);
+        assert_eq!(editor.line(), "");
+        assert_eq!(press(&mut editor, &history, &[ctrl('d')]), EditAction::Eof);
+    }
+
+    #[test]
+    fn test_history_navigation() {
+        let history: VecDeque<String> = ["ls", "cargo build"].iter().map(|s| s.to_string()).collect();
+        let mut editor = LineEditor::new();
+        press(&mut editor, &history, &text("gi"));
+        press(&mut editor, &history, &[key(KeyCode::Up)]);
+        assert_eq!(editor.line(), "cargo build");
+        press(&mut editor, &history, &[key(KeyCode::Up), key(KeyCode::Up)]);
+        assert_eq!(editor.line(), "ls");
+        press(&mut editor, &history, &[key(KeyCode::Down), key(KeyCode::Down)]);
+        // Going past the newest entry restores the line being typed
+        assert_eq!(editor.line(), "gi");
+    }
+
+    #[test]
+    fn test_reverse_search() {
+        let history: VecDeque<String> = ["git status", "ls -la", "git commit -m wip"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let mut editor = LineEditor::new();
+        press(&mut editor, &history, &[ctrl('r')]);
+        press(&mut editor, &history, &text("git"));
+        assert!(editor.is_searching());
+        press(&mut editor, &history, &[ctrl('r')]);
+        // Enter accepts the older match and submits it
+        assert_eq!(press(&mut editor, &history, &[key(KeyCode::Enter)]), EditAction::Submit);
+        assert_eq!(editor.line(), "git status");
+
+        // Ctrl-G abandons the search and restores the line
+        let mut editor = LineEditor::new();
+        press(&mut editor, &history, &text("pwd"));
+        press(&mut editor, &history, &[ctrl('r')]);
+        press(&mut editor, &history, &text("ls"));
+        press(&mut editor, &history, &[ctrl('g')]);
+        assert_eq!(editor.line(), "pwd");
+        assert!(!editor.is_searching());
+    }
+
+    #[test]
+    fn test_apply_completion() {
+        let history = VecDeque::new();
+        let mut editor = LineEditor::new();
+        press(&mut editor, &history, &text("cat sr"));
+        assert_eq!(press(&mut editor, &history, &[key(KeyCode::Tab)]), EditAction::Complete);
+
+        let completion = Completion { start: 4, candidates: vec!["src/".to_string()] };
+        assert!(editor.apply_completion(&completion));
+        assert_eq!(editor.line(), "cat src/");
+
+        let completion = Completion {
+            start: 4,
+            candidates: vec!["src/my file.rs".to_string()],
+        };
+        assert!(editor.apply_completion(&completion));
+        assert_eq!(editor.line(), "cat src/my\\ file.rs ");
+        assert_eq!(editor.cursor(), editor.line().chars().count());
+
+        // Nothing to add when the candidates share no more than the word
+        let mut editor = LineEditor::new();
+        press(&mut editor, &history, &text("ls m"));
+        let completion = Completion {
+            start: 3,
+            candidates: vec!["main.rs".to_string(), "mod.rs".to_string()],
+        };
+        assert!(!editor.apply_completion(&completion));
+        assert_eq!(editor.line(), "ls m");
+    }
+
+    #[test]
+    fn test_key_name() {
+        assert_eq!(key_name(&KeyEvent::new(KeyCode::Char('a'), KeyModifiers::NONE)), "a");
+        assert_eq!(key_name(&ctrl('r')), "Ctrl+R");
+        assert_eq!(key_name(&key(KeyCode::Enter)), "Enter");
+        assert_eq!(key_name(&KeyEvent::new(KeyCode::Backspace, KeyModifiers::ALT)), "Alt+Backspace");
+    }
+}
@@ -1,8 +1,15 @@
 use chrono::Utc;
 use clap::{Parser, Subcommand};
+use std::path::{Path, PathBuf};
 use timeloop_terminal::{
-    error::TimeLoopError, events::EventRecorder, replay::ReplayEngine, session::SessionManager,
-    storage::Storage, terminal::TerminalEmulator,
+    bookmark::BookmarkManager,
+    error::TimeLoopError,
+    events::{EventRecorder, EventType},
+    replay::ReplayEngine,
+    session::{SessionEdit, SessionManager},
+    shell_adapter::ShellKind,
+    storage::Storage,
+    terminal::TerminalEmulator,
 };
 use tracing::info;
 
@@ -61,6 +68,15 @@ struct Cli {
     /// Branch from a specific session ID
     #[arg(short, long)]
     branch: Option<String>,
+
+    /// Shell that runs commands (bash|zsh|fish|sh|powershell). Defaults to
+    /// $TIMELOOP_SHELL, then bash (PowerShell on Windows)
+    #[arg(long)]
+    shell: Option<ShellKind>,
+
+    /// Don't record the watched files as a baseline when a session starts
+    #[arg(long, default_value_t = false)]
+    no_baseline: bool,
 }
 
 #[derive(Subcommand)]
@@ -70,9 +86,48 @@ enum Commands {
         /// Session name
         #[arg(short, long)]
         name: Option<String>,
+        /// Continue a branch (timeline branch or branch session ID) from its
+        /// branch point, recording into the branch's session. The shell starts
+        /// in the working directory of the branch point, with the variables
+        /// exported by then
+        #[arg(long, conflicts_with = "name")]
+        from_branch: Option<String>,
+        /// Also check out the files as they were at the branch point into this
+        /// directory, which must be empty, and work there
+        #[arg(long, requires = "from_branch")]
+        restore_files: Option<PathBuf>,
+    },
+    /// Run a program in a pseudo-terminal and record it into a new session
+    Record {
+        /// Session name (defaults to the program name)
+        #[arg(short, long)]
+        name: Option<String>,
+        /// Program to run, followed by its arguments
+        #[arg(last = true, required = true)]
+        program: Vec<String>,
+    },
+    /// Run a command without the prompt and record it, e.g. in CI
+    Exec {
+        /// Name or ID of the session to record into. An unknown name starts a
+        /// new session with that name
+        #[arg(short, long, default_value = "exec")]
+        session: String,
+        /// Command to run. A single argument is run by the shell
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
     },
     /// List all sessions
-    List,
+    List {
+        /// Only list sessions with this tag; repeat it, or separate tags with
+        /// commas, to require several
+        #[arg(short, long, value_delimiter = ',')]
+        tag: Vec<String>,
+    },
+    /// Show or edit a session's name, description, metadata and tags
+    Session {
+        #[command(subcommand)]
+        action: SessionCommands,
+    },
     /// Replay a session
     Replay {
         /// Session ID to replay
@@ -80,6 +135,9 @@ enum Commands {
         /// Playback speed (1.0 = normal, 2.0 = 2x speed)
         #[arg(short, long, default_value = "1.0")]
         speed: f32,
+        /// Stop at each bookmark until space is pressed
+        #[arg(long)]
+        pause_at_bookmarks: bool,
     },
     /// Replay a session within a time range
     ReplayRange {
@@ -101,7 +159,7 @@ enum Commands {
         session_id: String,
         /// Branch name
         name: String,
-        /// Create branch at specific event ID
+        /// Create branch at specific event ID, or at a bookmark by name or ID
         #[arg(long)]
         event_id: Option<String>,
         /// Create branch at timestamp (RFC3339)
@@ -125,12 +183,116 @@ enum Commands {
         /// Branch ID to delete
         branch_id: String,
     },
+    /// Attach a note to an event of a session
+    Bookmark {
+        /// Session ID
+        session_id: String,
+        /// The note
+        #[arg(default_value = "")]
+        note: String,
+        /// Name to refer to the bookmark by, e.g. before-refactor
+        #[arg(short, long)]
+        name: Option<String>,
+        /// Event to bookmark (defaults to the last event)
+        #[arg(long)]
+        event_id: Option<String>,
+    },
+    /// List the bookmarks of a session
+    Bookmarks {
+        /// Session ID
+        session_id: String,
+    },
+    /// Delete a bookmark
+    DeleteBookmark {
+        /// Session ID
+        session_id: String,
+        /// Bookmark name or ID
+        bookmark: String,
+    },
+    /// Rebuild the watched files as they were at an event
+    Checkout {
+        /// Session ID
+        session_id: String,
+        /// Event ID, or RFC3339 timestamp meaning the last event before it
+        #[arg(long)]
+        at: String,
+        /// Directory to write the files into; must be empty or not exist
+        #[arg(long)]
+        into: PathBuf,
+        /// Watched directory the files are relative to (defaults to the root
+        /// of the latest baseline if it contains all of them, else the
+        /// deepest directory that does)
+        #[arg(long)]
+        root: Option<PathBuf>,
+    },
+    /// Re-run a session's commands in a sandbox and report what changed
+    ///
+    /// The commands run one after another in a single shell, so variables,
+    /// aliases and functions they set carry over as when they were recorded.
+    /// Whatever the session's shell had before its first command comes from
+    /// the current environment instead.
+    Rerun {
+        /// Session ID
+        session_id: String,
+        /// Sandbox directory. When empty, it is first filled with the files
+        /// recorded before the first command
+        #[arg(long = "in")]
+        sandbox: PathBuf,
+        /// Directory the session worked in (defaults to the baseline's root)
+        #[arg(long)]
+        root: Option<PathBuf>,
+        /// Kill commands still running after this many seconds; the commands
+        /// after them run in a fresh shell
+        #[arg(long)]
+        timeout: Option<u64>,
+        /// Report format (text|json)
+        #[arg(long, default_value = "text")]
+        format: String,
+    },
+    /// Find the command whose file changes broke a test, like git bisect
+    Bisect {
+        /// Session ID
+        session_id: String,
+        /// Test command; exit code 0 means good. A single argument is run by
+        /// the shell
+        #[arg(last = true, required = true)]
+        test: Vec<String>,
+    },
+    /// Put back the files changed by the last commands, after showing a diff
+    Undo {
+        /// Number of commands to undo
+        #[arg(default_value_t = 1)]
+        count: usize,
+        /// Session ID (defaults to the most recent session)
+        #[arg(short, long)]
+        session: Option<String>,
+        /// Only show what would change
+        #[arg(long)]
+        dry_run: bool,
+        /// Don't ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Record a custom event, e.g. a test result or deploy marker from a script
+    Emit {
+        /// What the event is, e.g. test-result
+        kind: String,
+        /// JSON payload, or - to read it from stdin
+        payload: Option<String>,
+        /// Name or ID of the session to record into (defaults to the most
+        /// recent session)
+        #[arg(short, long)]
+        session: Option<String>,
+    },
     /// Show session tree (parent/child relationships)
     Tree,
     /// Show event timeline for a session
     Timeline {
         /// Session ID
         session_id: String,
+        /// Show what changed in modified files
+        #[arg(long)]
+        diff: bool,
     },
     /// Show session summary
     Summary {
@@ -165,6 +327,52 @@ enum Commands {
     },
 }
 
+#[derive(Subcommand)]
+enum SessionCommands {
+    /// Show a session's details
+    Show {
+        /// Session ID
+        session_id: String,
+    },
+    /// Change a session's name, description or metadata
+    Edit {
+        /// Session ID
+        session_id: String,
+        /// New name
+        #[arg(long)]
+        name: Option<String>,
+        /// New description; an empty one removes it
+        #[arg(long)]
+        description: Option<String>,
+        /// Set a metadata entry, e.g. --set ticket=PROJ-123
+        #[arg(long, value_name = "KEY=VALUE", value_parser = parse_key_value)]
+        set: Vec<(String, String)>,
+        /// Remove a metadata entry
+        #[arg(long, value_name = "KEY")]
+        unset: Vec<String>,
+    },
+    /// Add tags to a session
+    Tag {
+        /// Session ID
+        session_id: String,
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+    /// Remove tags from a session
+    Untag {
+        /// Session ID
+        session_id: String,
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+}
+
+fn parse_key_value(arg: &str) -> Result<(String, String), String> {
+    arg.split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got '{}'", arg))
+}
+
 #[tokio::main]
 async fn main() -> Result<(), TimeLoopError> {
     // Initialize logging
@@ -233,16 +441,43 @@ async fn main() -> Result<(), TimeLoopError> {
         timeloop_terminal::storage::Storage::set_global_compaction_policy(pol);
     }
 
+    let shell = match cli.shell {
+        Some(shell) => shell,
+        None => ShellKind::from_env()?.unwrap_or_default(),
+    };
+
     match &cli.command {
-        Some(Commands::Start { name }) => {
+        Some(Commands::Start {
+            from_branch: Some(branch_id),
+            restore_files,
+            ..
+        }) => {
+            start_from_branch(branch_id, restore_files.as_deref(), shell, !cli.no_baseline).await?;
+        }
+        Some(Commands::Start { name, .. }) => {
             let session_name = name.as_deref().unwrap_or("default");
-            start_session(session_name).await?;
+            start_session(session_name, shell, !cli.no_baseline).await?;
         }
-        Some(Commands::List) => {
-            list_sessions().await?;
+        Some(Commands::Record { name, program }) => {
+            let code = record_program(name.as_deref(), program).await?;
+            std::process::exit(code);
         }
-        Some(Commands::Replay { session_id, speed }) => {
-            replay_session(session_id, *speed).await?;
+        Some(Commands::Exec { session, command }) => {
+            let code = exec_command(session, command, shell, !cli.no_baseline).await?;
+            std::process::exit(code);
+        }
+        Some(Commands::List { tag }) => {
+            list_sessions(tag).await?;
+        }
+        Some(Commands::Session { action }) => {
+            session_command(action).await?;
+        }
+        Some(Commands::Replay {
+            session_id,
+            speed,
+            pause_at_bookmarks,
+        }) => {
+            replay_session(session_id, *speed, *pause_at_bookmarks).await?;
         }
         Some(Commands::ReplayRange {
             session_id,
@@ -272,14 +507,67 @@ async fn main() -> Result<(), TimeLoopError> {
         Some(Commands::DeleteBranch { branch_id }) => {
             delete_branch(branch_id).await?;
         }
+        Some(Commands::Bookmark {
+            session_id,
+            note,
+            name,
+            event_id,
+        }) => {
+            add_bookmark(session_id, note, name.as_deref(), event_id.as_deref()).await?;
+        }
+        Some(Commands::Bookmarks { session_id }) => {
+            list_bookmarks(session_id).await?;
+        }
+        Some(Commands::DeleteBookmark {
+            session_id,
+            bookmark,
+        }) => {
+            delete_bookmark(session_id, bookmark).await?;
+        }
+        Some(Commands::Checkout {
+            session_id,
+            at,
+            into,
+            root,
+        }) => {
+            checkout(session_id, at, into, root.as_deref()).await?;
+        }
+        Some(Commands::Rerun {
+            session_id,
+            sandbox,
+            root,
+            timeout,
+            format,
+        }) => {
+            let code = rerun(session_id, sandbox, root.clone(), *timeout, format, shell).await?;
+            std::process::exit(code);
+        }
+        Some(Commands::Bisect { session_id, test }) => {
+            bisect(session_id, test, shell).await?;
+        }
+        Some(Commands::Undo {
+            count,
+            session,
+            dry_run,
+            yes,
+        }) => {
+            undo(*count, session.as_deref(), *dry_run, *yes).await?;
+        }
+        Some(Commands::Emit {
+            kind,
+            payload,
+            session,
+        }) => {
+            emit_event(kind, payload.as_deref(), session.as_deref()).await?;
+        }
         Some(Commands::Summary { session_id }) => {
             show_summary(session_id).await?;
         }
         Some(Commands::Tree) => {
             show_session_tree().await?;
         }
-        Some(Commands::Timeline { session_id }) => {
-            show_event_timeline(session_id).await?;
+        Some(Commands::Timeline { session_id, diff }) => {
+            show_event_timeline(session_id, *diff).await?;
         }
         Some(Commands::Export { session_id, output }) => {
             export_session(session_id, output).await?;
@@ -305,14 +593,14 @@ async fn main() -> Result<(), TimeLoopError> {
         None => {
             // Default behavior: start a new session
             let session_name = cli.session.as_deref().unwrap_or("default");
-            start_session(session_name).await?;
+            start_session(session_name, shell, !cli.no_baseline).await?;
         }
     }
 
     Ok(())
 }
 
-async fn start_session(name: &str) -> Result<(), TimeLoopError> {
+async fn start_session(name: &str, shell: ShellKind, baseline: bool) -> Result<(), TimeLoopError> {
     info!("🎬 Starting new session: {}", name);
 
     let _storage = Storage::new()?;
@@ -321,9 +609,10 @@ async fn start_session(name: &str) -> Result<(), TimeLoopError> {
     let session_id = session_manager.create_session(name)?;
 
     let event_recorder = EventRecorder::new(&session_id)?;
-    let mut terminal = TerminalEmulator::new(event_recorder)?;
+    let mut terminal = TerminalEmulator::with_shell(event_recorder, shell)?;
+    terminal.set_baseline_snapshot(baseline);
 
-    info!("📝 Session {} started with ID: {}", name, session_id);
+    info!("📝 Session {} started with ID: {} using {}", name, session_id, shell);
 
     // Start the terminal emulator
     terminal.run().await?;
@@ -331,31 +620,183 @@ async fn start_session(name: &str) -> Result<(), TimeLoopError> {
     Ok(())
 }
 
-async fn list_sessions() -> Result<(), TimeLoopError> {
+async fn start_from_branch(
+    branch_id: &str,
+    restore_files: Option<&Path>,
+    shell: ShellKind,
+    baseline: bool,
+) -> Result<(), TimeLoopError> {
+    let branch_manager = timeloop_terminal::branch::BranchManager::new()?;
+    let mut start = branch_manager.branch_start(branch_id)?;
+    info!(
+        "🧬 Continuing branch '{}' from event {}",
+        start.branch.name, start.branch.branch_point_event_id
+    );
+    if let Some(into) = restore_files {
+        let report = branch_manager.restore_files(&mut start, into)?;
+        for path in &report.missing {
+            println!("⚠️  Contents not recorded, skipped: {}", path.display());
+        }
+        println!("📂 Restored {} files into {}", report.written.len(), into.display());
+    }
+
+    let event_recorder = EventRecorder::new(&start.session_id)?;
+    let mut terminal = TerminalEmulator::with_shell(event_recorder, shell)?;
+    terminal.set_baseline_snapshot(baseline);
+    terminal.set_environment(start.environment);
+    match &start.working_directory {
+        Some(dir) if Path::new(dir).is_dir() => terminal.set_working_directory(dir),
+        Some(dir) => println!("⚠️  {} no longer exists; staying in the current directory", dir),
+        None => {}
+    }
+    terminal.run().await?;
+
+    Ok(())
+}
+
+async fn record_program(name: Option<&str>, program: &[String]) -> Result<i32, TimeLoopError> {
+    let (program, args) = program
+        .split_first()
+        .ok_or_else(|| TimeLoopError::Configuration("No program given to record".to_string()))?;
+    let name = name.unwrap_or(program.as_str());
+    info!("🎬 Recording {} into new session: {}", program, name);
+
+    let mut session_manager = SessionManager::new()?;
+    let session_id = session_manager.create_session(name)?;
+    let created_at = session_manager
+        .get_session(&session_id)?
+        .map(|s| s.created_at)
+        .unwrap_or_else(Utc::now);
+
+    let mut event_recorder = EventRecorder::new(&session_id)?;
+    // Record which shell ran, when the program is one
+    event_recorder.record_session_metadata(name, created_at, program.parse().ok())?;
+
+    let cwd = std::env::current_dir()?.to_string_lossy().to_string();
+    let mut recorder = timeloop_terminal::record::ProgramRecorder::new(event_recorder);
+    let code = recorder.run(program, args, &cwd).await?;
+    session_manager.end_session(&session_id)?;
+
+    println!("📝 Recorded session {} ({})", name, session_id);
+    Ok(code)
+}
+
+async fn exec_command(
+    session: &str,
+    command: &[String],
+    shell: ShellKind,
+    baseline: bool,
+) -> Result<i32, TimeLoopError> {
+    let mut session_manager = SessionManager::new()?;
+    // An existing session, else a new one with that name
+    let (session_id, is_new) = match session_manager.find_by_id_or_name(session)? {
+        Some(found) => (found.id, false),
+        None => (session_manager.create_session(session)?, true),
+    };
+    info!("⚙️  Recording command into session: {}", session_id);
+
+    let mut event_recorder = EventRecorder::new(&session_id)?;
+    if is_new {
+        event_recorder.record_session_metadata(session, Utc::now(), Some(shell))?;
+    }
+    // Snapshotting the tree for every command would be slow in large repos,
+    // so only the session's first exec records a baseline
+    let has_baseline = !is_new
+        && event_recorder
+            .get_events_for_session(&session_id)?
+            .iter()
+            .any(|e| matches!(e.event_type, EventType::Baseline { .. }));
+    let mut terminal = TerminalEmulator::with_shell(event_recorder, shell)?;
+    terminal.set_baseline_snapshot(baseline && !has_baseline);
+    let record = terminal.exec(command).await?;
+    if is_new {
+        session_manager.end_session(&session_id)?;
+    }
+
+    Ok(match record.signal {
+        Some(signal) => 128 + signal,
+        None => record.exit_code,
+    })
+}
+
+async fn list_sessions(tags: &[String]) -> Result<(), TimeLoopError> {
     info!("📋 Listing all sessions...");
 
     let session_manager = SessionManager::new()?;
-    let sessions = session_manager.list_sessions()?;
+    let sessions = session_manager.list_sessions_with_tags(tags)?;
 
     println!("🕰️  TimeLoop Sessions:");
     println!("{}", "─".repeat(50));
 
     for session in sessions {
-        println!(
+        print!(
             "📁 {} - {} ({})",
             session.id,
             session.name,
             session.created_at.format("%Y-%m-%d %H:%M:%S")
         );
+        for tag in &session.tags {
+            print!(" #{}", tag);
+        }
+        println!();
+        if let Some(description) = &session.description {
+            println!("   {}", description);
+        }
     }
 
     Ok(())
 }
 
-async fn replay_session(session_id: &str, speed: f32) -> Result<(), TimeLoopError> {
+async fn session_command(action: &SessionCommands) -> Result<(), TimeLoopError> {
+    let mut session_manager = SessionManager::new()?;
+    let session = match action {
+        SessionCommands::Show { session_id } => session_manager
+            .get_session(session_id)?
+            .ok_or_else(|| TimeLoopError::SessionNotFound(session_id.to_string()))?,
+        SessionCommands::Edit {
+            session_id,
+            name,
+            description,
+            set,
+            unset,
+        } => {
+            let edit = SessionEdit {
+                name: name.clone(),
+                // An empty description removes it
+                description: description.as_ref().map(|d| Some(d.clone()).filter(|d| !d.is_empty())),
+                set_metadata: set.clone(),
+                unset_metadata: unset.clone(),
+            };
+            session_manager.edit_session(session_id, &edit)?
+        }
+        SessionCommands::Tag { session_id, tags } => session_manager.add_tags(session_id, tags)?,
+        SessionCommands::Untag { session_id, tags } => {
+            session_manager.remove_tags(session_id, tags)?
+        }
+    };
+
+    println!("📁 {} - {}", session.id, session.name);
+    if let Some(description) = &session.description {
+        println!("   {}", description);
+    }
+    if !session.tags.is_empty() {
+        println!("   Tags: {}", session.tags.join(", "));
+    }
+    for (key, value) in &session.metadata {
+        println!("   {}: {}", key, value);
+    }
+    Ok(())
+}
+
+async fn replay_session(
+    session_id: &str,
+    speed: f32,
+    pause_at_bookmarks: bool,
+) -> Result<(), TimeLoopError> {
     info!("🎥 Replaying session: {} at {}x speed", session_id, speed);
 
-    let replay_engine = ReplayEngine::new(session_id)?;
+    let mut replay_engine = ReplayEngine::new(session_id)?;
+    replay_engine.set_pause_at_bookmarks(pause_at_bookmarks);
     replay_engine.replay(speed).await?;
 
     Ok(())
@@ -393,7 +834,7 @@ async fn create_branch(
     // Also record a timeline branch at the last event as branch point
     let storage = Storage::new()?;
     let branch_point_id = if let Some(eid) = event_id {
-        eid.to_string()
+        BookmarkManager::with_storage(storage.clone()).resolve_event(session_id, eid)?
     } else if let Some(at_ts) = at {
         let ts = chrono::DateTime::parse_from_rfc3339(at_ts)
             .map_err(|e| TimeLoopError::Branch(format!("Invalid --at timestamp: {}", e)))?
@@ -425,6 +866,163 @@ async fn create_branch(
     Ok(())
 }
 
+async fn checkout(
+    session_id: &str,
+    at: &str,
+    into: &Path,
+    root: Option<&Path>,
+) -> Result<(), TimeLoopError> {
+    info!("⏪ Checking out session {} at {}", session_id, at);
+    let workspace = timeloop_terminal::workspace::Workspace::new(session_id)?;
+    let report = workspace.checkout(at, into, root)?;
+
+    for path in &report.missing {
+        println!("⚠️  Contents not recorded, skipped: {}", path.display());
+    }
+    for path in &report.outside_root {
+        println!("⚠️  Outside the workspace root, skipped: {}", path.display());
+    }
+    println!(
+        "✅ Checked out {} files into {}",
+        report.written.len(),
+        into.display()
+    );
+    Ok(())
+}
+
+async fn rerun(
+    session_id: &str,
+    sandbox: &Path,
+    root: Option<PathBuf>,
+    timeout: Option<u64>,
+    format: &str,
+    shell: ShellKind,
+) -> Result<i32, TimeLoopError> {
+    if !matches!(format, "text" | "json") {
+        return Err(TimeLoopError::Configuration(format!(
+            "Unknown report format: {}",
+            format
+        )));
+    }
+    info!("🔁 Rerunning session {} in {}", session_id, sandbox.display());
+    let options = timeloop_terminal::rerun::RerunOptions {
+        shell,
+        root,
+        timeout: timeout.map(std::time::Duration::from_secs),
+    };
+    let report = timeloop_terminal::rerun::Rerun::new(session_id)?
+        .run(sandbox, &options)
+        .await?;
+    match format {
+        "json" => println!("{}", serde_json::to_string_pretty(&report)?),
+        _ => print!("{}", report.to_text()),
+    }
+    // Usable as a regression check
+    Ok(if report.changed() > 0 { 1 } else { 0 })
+}
+
+async fn bisect(session_id: &str, test: &[String], shell: ShellKind) -> Result<(), TimeLoopError> {
+    info!("🔎 Bisecting session {}", session_id);
+    let bisect = timeloop_terminal::bisect::Bisect::new(session_id)?;
+    let report = bisect.run(test, shell, |step| {
+        let at = match &step.command {
+            Some(command) => format!("after '{}'", command),
+            None => "before the first change".to_string(),
+        };
+        let result = if step.passed { "✅ good" } else { "❌ bad" };
+        println!("{} {}", result, at);
+    })?;
+
+    for path in &report.missing {
+        println!("⚠️  Contents not recorded, left out: {}", path.display());
+    }
+    println!(
+        "🎯 First bad command: {} (event {})",
+        report.culprit.command, report.culprit.event_id
+    );
+    for path in &report.culprit.files {
+        println!("   {}", path);
+    }
+    Ok(())
+}
+
+async fn undo(
+    count: usize,
+    session_id: Option<&str>,
+    dry_run: bool,
+    yes: bool,
+) -> Result<(), TimeLoopError> {
+    let session_id = match session_id {
+        Some(id) => id.to_string(),
+        None => SessionManager::new()?
+            .list_sessions()?
+            .into_iter()
+            .max_by_key(|s| s.created_at)
+            .map(|s| s.id)
+            .ok_or_else(|| TimeLoopError::SessionNotFound("no sessions recorded".to_string()))?,
+    };
+    info!("↩️  Undoing {} commands of session {}", count, session_id);
+    let undo = timeloop_terminal::undo::Undo::new(&session_id)?;
+    let plan = undo.plan(count)?;
+
+    println!("Undoing: {}", plan.commands.join(", "));
+    for path in &plan.unknown {
+        println!("⚠️  Previous contents not recorded, left alone: {}", path.display());
+    }
+    if plan.is_empty() {
+        println!("Nothing to undo");
+        return Ok(());
+    }
+    print!("{}", plan.diff());
+    if dry_run {
+        return Ok(());
+    }
+    if !yes {
+        print!("Apply these changes? [y/N] ");
+        std::io::Write::flush(&mut std::io::stdout())?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            println!("Cancelled");
+            return Ok(());
+        }
+    }
+    undo.apply(&plan)?;
+    println!("✅ Undid {} files", plan.actions.len());
+    Ok(())
+}
+
+async fn emit_event(
+    kind: &str,
+    payload: Option<&str>,
+    session: Option<&str>,
+) -> Result<(), TimeLoopError> {
+    let payload = match payload {
+        None => serde_json::json!({}),
+        Some("-") => serde_json::from_reader(std::io::stdin())?,
+        Some(text) => serde_json::from_str(text).map_err(|e| {
+            TimeLoopError::Configuration(format!("Payload is not valid JSON ({}): {}", e, text))
+        })?,
+    };
+    let session_manager = SessionManager::new()?;
+    let session_id = match session {
+        Some(session) => session_manager
+            .find_by_id_or_name(session)?
+            .map(|s| s.id)
+            .ok_or_else(|| TimeLoopError::SessionNotFound(session.to_string()))?,
+        None => session_manager
+            .list_sessions()?
+            .into_iter()
+            .max_by_key(|s| s.created_at)
+            .map(|s| s.id)
+            .ok_or_else(|| TimeLoopError::SessionNotFound("no sessions recorded".to_string()))?,
+    };
+    let mut event_recorder = EventRecorder::new(&session_id)?;
+    event_recorder.record_custom(kind, payload)?;
+    println!("🧩 Recorded {} event in session {}", kind, session_id);
+    Ok(())
+}
+
 async fn list_branches(session_id: &str) -> Result<(), TimeLoopError> {
     let branch_manager = timeloop_terminal::branch::BranchManager::new()?;
     let branches = branch_manager.get_branches_for_session(session_id)?;
@@ -452,6 +1050,43 @@ async fn delete_branch(branch_id: &str) -> Result<(), TimeLoopError> {
     Ok(())
 }
 
+async fn add_bookmark(
+    session_id: &str,
+    note: &str,
+    name: Option<&str>,
+    event_id: Option<&str>,
+) -> Result<(), TimeLoopError> {
+    if note.is_empty() && name.is_none() {
+        return Err(TimeLoopError::Bookmark("Give a note, a --name, or both".to_string()));
+    }
+    let storage = Storage::new()?;
+    let event_id = match event_id {
+        Some(id) => id.to_string(),
+        None => storage
+            .get_last_event(session_id)?
+            .map(|e| e.id)
+            .ok_or_else(|| TimeLoopError::Bookmark(format!("Session {} has no events", session_id)))?,
+    };
+    let bookmark = BookmarkManager::with_storage(storage).add(session_id, &event_id, name, note)?;
+    println!("🔖 Bookmarked event {}: {}", bookmark.event_id, bookmark.label());
+    Ok(())
+}
+
+async fn list_bookmarks(session_id: &str) -> Result<(), TimeLoopError> {
+    let bookmarks = BookmarkManager::new()?.list(session_id)?;
+    println!("Bookmarks for session {}:", session_id);
+    for b in bookmarks {
+        println!("- {} (id: {}, event: {})", b.label(), b.id, b.event_id);
+    }
+    Ok(())
+}
+
+async fn delete_bookmark(session_id: &str, bookmark: &str) -> Result<(), TimeLoopError> {
+    let removed = BookmarkManager::new()?.remove(session_id, bookmark)?;
+    println!("Deleted bookmark {}", removed.label());
+    Ok(())
+}
+
 async fn show_summary(session_id: &str) -> Result<(), TimeLoopError> {
     info!("📊 Showing summary for session: {}", session_id);
 
@@ -460,10 +1095,7 @@ async fn show_summary(session_id: &str) -> Result<(), TimeLoopError> {
 
     println!("📈 Session Summary for: {}", session_id);
     println!("{}", "─".repeat(50));
-    println!("⏱️  Duration: {}", summary.duration);
-    println!("⌨️  Commands executed: {}", summary.commands_executed);
-    println!("📁 Files modified: {}", summary.files_modified);
-    println!("🎯 Last command: {}", summary.last_command);
+    print!("{}", summary);
 
     Ok(())
 }
@@ -494,28 +1126,27 @@ async fn show_session_tree() -> Result<(), TimeLoopError> {
     Ok(())
 }
 
-async fn show_event_timeline(session_id: &str) -> Result<(), TimeLoopError> {
+async fn show_event_timeline(session_id: &str, show_diffs: bool) -> Result<(), TimeLoopError> {
     let storage = Storage::new()?;
     let mut events = storage.get_events_for_session(session_id)?;
     events.sort_by_key(|e| e.sequence_number);
+    let bookmarks = storage.get_bookmarks_for_session(session_id)?;
     println!("Event timeline for session {}:", session_id);
     for e in events {
         println!(
             "{} [{}] seq={}",
             e.timestamp.to_rfc3339(),
-            match &e.event_type {
-                timeloop_terminal::EventType::KeyPress { key, .. } => format!("KeyPress {}", key),
-                timeloop_terminal::EventType::Command { command, .. } =>
-                    format!("Command {}", command),
-                timeloop_terminal::EventType::FileChange {
-                    path, change_type, ..
-                } => format!("FileChange {:?} {}", change_type, path),
-                timeloop_terminal::EventType::TerminalState { .. } => "TerminalState".to_string(),
-                timeloop_terminal::EventType::SessionMetadata { name, .. } =>
-                    format!("SessionMetadata {}", name),
-            },
+            e.event_type.describe(),
             e.sequence_number
         );
+        for b in bookmarks.iter().filter(|b| b.event_id == e.id) {
+            println!("    🔖 {}", b.label());
+        }
+        if let (true, EventType::FileChange { diff: Some(diff), .. }) = (show_diffs, &e.event_type) {
+            for line in diff.lines() {
+                println!("    {}", line);
+            }
+        }
     }
     Ok(())
 }
@@ -0,0 +1,167 @@
+use crate::TimeLoopError;
+
+/// Names of the meta-commands, for completion and help
+pub const META_COMMANDS: &[&str] = &[":mark", ":branch", ":timeline", ":summary", ":replay", ":help"];
+
+/// Number of commands `:replay` goes back when no count is given
+const DEFAULT_REPLAY_COMMANDS: usize = 10;
+
+/// A TimeLoop command typed at the session prompt. Meta-commands start with a
+/// colon so they can't be confused with shell commands.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetaCommand {
+    /// `:mark [--name <name>] <note>` bookmarks the latest event
+    Mark { name: Option<String>, note: String },
+    /// `:branch <name>` branches the session at the latest event
+    Branch { name: String },
+    /// `:timeline` lists the session's events, without key presses
+    Timeline,
+    /// `:summary` shows the session summary
+    Summary,
+    /// `:replay [last] [N] [speed]` replays the last N commands
+    Replay { commands: usize, speed: f32 },
+    /// `:help` lists the meta-commands
+    Help,
+}
+
+impl MetaCommand {
+    /// Whether `input` is meant as a meta-command rather than for the shell.
+    /// Only the known names count, so the shell's `:` builtin still works.
+    pub fn is_meta_command(input: &str) -> bool {
+        let name = input.split_whitespace().next().unwrap_or("");
+        META_COMMANDS.contains(&name)
+    }
+
+    pub fn parse(input: &str) -> crate::Result<Self> {
+        let input = input.trim();
+        let (name, rest) = match input.split_once(char::is_whitespace) {
+            Some((name, rest)) => (name, rest.trim()),
+            None => (input, ""),
+        };
+
+        match name {
+            ":mark" => parse_mark(rest),
+            ":branch" if !rest.is_empty() && !rest.contains(char::is_whitespace) => {
+                Ok(MetaCommand::Branch {
+                    name: rest.to_string(),
+                })
+            }
+            ":branch" => Err(usage(":branch <name>")),
+            ":timeline" if rest.is_empty() => Ok(MetaCommand::Timeline),
+            ":summary" if rest.is_empty() => Ok(MetaCommand::Summary),
+            ":replay" => parse_replay(rest),
+            ":help" => Ok(MetaCommand::Help),
+            ":timeline" | ":summary" => Err(usage(name)),
+            _ => Err(TimeLoopError::CommandExecution(format!(
+                "Unknown meta-command: {} (try :help)",
+                name
+            ))),
+        }
+    }
+
+    /// One line per meta-command, for `:help`
+    pub fn help() -> &'static str {
+        ":mark [--name n] <note>   Bookmark this point in the session\n\
+         :branch <name>            Branch the session here\n\
+         :timeline                 Show the session's events\n\
+         :summary                  Show the session summary\n\
+         :replay [last] [N] [x]    Replay the last N commands (default 10) at speed x\n\
+         :help                     Show this help"
+    }
+}
+
+fn parse_mark(args: &str) -> crate::Result<MetaCommand> {
+    let (name, note) = match args.strip_prefix("--name") {
+        Some(rest) if rest.is_empty() || rest.starts_with(char::is_whitespace) => {
+            let rest = rest.trim_start();
+            match rest.split_once(char::is_whitespace) {
+                Some((name, note)) => (Some(name), note.trim()),
+                None => (Some(rest), ""),
+            }
+        }
+        _ => (None, args),
+    };
+    match name {
+        Some("") => Err(usage(":mark [--name <name>] <note>")),
+        None if note.is_empty() => Err(usage(":mark [--name <name>] <note>")),
+        _ => Ok(MetaCommand::Mark {
+            name: name.map(|n| n.to_string()),
+            note: note.to_string(),
+        }),
+    }
+}
+
+fn parse_replay(args: &str) -> crate::Result<MetaCommand> {
+    let mut words = args.split_whitespace().peekable();
+    if words.peek() == Some(&"last") {
+        words.next();
+    }
+    let commands = match words.next() {
+        Some(n) => n.parse().ok().filter(|n| *n > 0),
+        None => Some(DEFAULT_REPLAY_COMMANDS),
+    };
+    let speed = match words.next() {
+        Some(s) => s.trim_end_matches('x').parse().ok().filter(|s: &f32| *s > 0.0),
+        None => Some(1.0),
+    };
+    match (commands, speed, words.next()) {
+        (Some(commands), Some(speed), None) => Ok(MetaCommand::Replay { commands, speed }),
+        _ => Err(usage(":replay [last] [N] [speed]")),
+    }
+}
+
+fn usage(usage: &str) -> TimeLoopError {
+    TimeLoopError::CommandExecution(format!("Usage: {}", usage))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_meta_commands() {
+        assert_eq!(
+            MetaCommand::parse(":mark  tests pass here ").unwrap(),
+            MetaCommand::Mark {
+                name: None,
+                note: "tests pass here".to_string()
+            }
+        );
+        assert_eq!(
+            MetaCommand::parse(":mark --name before-refactor").unwrap(),
+            MetaCommand::Mark {
+                name: Some("before-refactor".to_string()),
+                note: String::new()
+            }
+        );
+        assert_eq!(
+            MetaCommand::parse(":branch try-fix").unwrap(),
+            MetaCommand::Branch {
+                name: "try-fix".to_string()
+            }
+        );
+        assert_eq!(MetaCommand::parse(":timeline").unwrap(), MetaCommand::Timeline);
+        assert_eq!(MetaCommand::parse(":summary").unwrap(), MetaCommand::Summary);
+        assert_eq!(
+            MetaCommand::parse(":replay last 3 2x").unwrap(),
+            MetaCommand::Replay { commands: 3, speed: 2.0 }
+        );
+        assert_eq!(
+            MetaCommand::parse(":replay").unwrap(),
+            MetaCommand::Replay { commands: 10, speed: 1.0 }
+        );
+
+        assert!(MetaCommand::parse(":mark").is_err());
+        assert!(MetaCommand::parse(":mark --name").is_err());
+        assert!(MetaCommand::parse(":branch two words").is_err());
+        assert!(MetaCommand::parse(":replay last zero").is_err());
+        assert!(MetaCommand::parse(":rewind").is_err());
+
+        // The shell's `:` builtin isn't a meta-command
+        assert!(MetaCommand::is_meta_command(":mark here"));
+        assert!(MetaCommand::is_meta_command(":timeline"));
+        assert!(!MetaCommand::is_meta_command(": > x"));
+        assert!(!MetaCommand::is_meta_command(":; make"));
+        assert!(!MetaCommand::is_meta_command(":marked"));
+    }
+}
@@ -0,0 +1,268 @@
+use crate::events::ResourceUsage;
+
+/// How a child process ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessExit {
+    /// Exit status, or 128 + signal number if the process was killed by a
+    /// signal (the same convention shells use for `$?`)
+    pub exit_code: i32,
+    pub signal: Option<i32>,
+    pub core_dumped: bool,
+    pub resource_usage: Option<ResourceUsage>,
+}
+
+impl ProcessExit {
+    /// An exit known only by its code
+    pub fn from_code(exit_code: i32) -> Self {
+        Self {
+            exit_code,
+            signal: None,
+            core_dumped: false,
+            resource_usage: None,
+        }
+    }
+}
+
+/// Reap `pid` if it has exited, collecting its resource usage with `wait4`
+#[cfg(unix)]
+pub fn try_wait4(pid: i32) -> std::io::Result<Option<ProcessExit>> {
+    let mut status: libc::c_int = 0;
+    // SAFETY: rusage is plain data and wait4 only writes to the pointers given
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    let ret = unsafe { libc::wait4(pid, &mut status, libc::WNOHANG, &mut usage) };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    if ret == 0 {
+        return Ok(None);
+    }
+
+    let (exit_code, signal, core_dumped) = if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+        (128 + signal, Some(signal), libc::WCOREDUMP(status))
+    } else {
+        (libc::WEXITSTATUS(status), None, false)
+    };
+    Ok(Some(ProcessExit {
+        exit_code,
+        signal,
+        core_dumped,
+        resource_usage: Some(ResourceUsage {
+            user_time_ms: timeval_ms(usage.ru_utime),
+            system_time_ms: timeval_ms(usage.ru_stime),
+            max_rss_kb: max_rss_kb(usage.ru_maxrss),
+        }),
+    }))
+}
+
+#[cfg(unix)]
+fn timeval_ms(tv: libc::timeval) -> u64 {
+    (tv.tv_sec as u64) * 1000 + (tv.tv_usec as u64) / 1000
+}
+
+/// `ru_maxrss` is in kilobytes on Linux but in bytes on macOS
+#[cfg(unix)]
+fn max_rss_kb(value: libc::c_long) -> u64 {
+    if cfg!(target_os = "macos") {
+        value as u64 / 1024
+    } else {
+        value as u64
+    }
+}
+
+/// Interpret a shell's `$?` for a command we could not wait on ourselves.
+/// Shells report death by signal N as 128 + N.
+pub fn signal_from_shell_status(status: i32) -> Option<i32> {
+    if (129..=128 + 64).contains(&status) {
+        Some(status - 128)
+    } else {
+        None
+    }
+}
+
+/// Conventional name for a signal number, e.g. "SIGINT"
+pub fn signal_name(signal: i32) -> String {
+    let name = match signal {
+        s if is_suspend_signal(s) => "SIGTSTP",
+        1 => "SIGHUP",
+        2 => "SIGINT",
+        3 => "SIGQUIT",
+        4 => "SIGILL",
+        5 => "SIGTRAP",
+        6 => "SIGABRT",
+        7 if cfg!(target_os = "linux") => "SIGBUS",
+        8 => "SIGFPE",
+        9 => "SIGKILL",
+        10 if cfg!(target_os = "linux") => "SIGUSR1",
+        11 => "SIGSEGV",
+        12 if cfg!(target_os = "linux") => "SIGUSR2",
+        13 => "SIGPIPE",
+        14 => "SIGALRM",
+        15 => "SIGTERM",
+        _ => return format!("signal {}", signal),
+    };
+    name.to_string()
+}
+
+/// True for SIGTSTP, which stops a job rather than ending it
+pub fn is_suspend_signal(signal: i32) -> bool {
+    #[cfg(unix)]
+    {
+        signal == libc::SIGTSTP
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = signal;
+        false
+    }
+}
+
+/// Tracks CPU time and peak memory of commands run by a long-lived shell,
+/// which reaps them itself so `wait4` is not available to us. CPU time comes
+/// from the shell's accumulated child times in `/proc/<pid>/stat`; peak RSS is
+/// sampled from the processes in the terminal's foreground process group while
+/// the command runs, so very short-lived processes may be missed.
+#[derive(Debug)]
+pub struct ChildUsageTracker {
+    #[cfg(target_os = "linux")]
+    shell_pid: i32,
+    #[cfg(target_os = "linux")]
+    start_cpu: Option<(u64, u64)>,
+    #[cfg(target_os = "linux")]
+    max_rss_kb: u64,
+}
+
+impl ChildUsageTracker {
+    pub fn start(shell_pid: Option<u32>) -> Self {
+        #[cfg(target_os = "linux")]
+        {
+            let shell_pid = shell_pid.map(|pid| pid as i32).unwrap_or(-1);
+            Self {
+                shell_pid,
+                start_cpu: linux::children_cpu_ms(shell_pid),
+                max_rss_kb: 0,
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = shell_pid;
+            Self {}
+        }
+    }
+
+    /// Record the memory use of the current foreground process group
+    pub fn sample(&mut self, foreground_pgrp: Option<i32>) {
+        #[cfg(target_os = "linux")]
+        if let Some(pgrp) = foreground_pgrp.filter(|&pgrp| pgrp != self.shell_pid) {
+            self.max_rss_kb = self.max_rss_kb.max(linux::group_peak_rss_kb(pgrp));
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = foreground_pgrp;
+    }
+
+    pub fn finish(self) -> Option<ResourceUsage> {
+        #[cfg(target_os = "linux")]
+        {
+            let (start_user, start_sys) = self.start_cpu?;
+            let (user, sys) = linux::children_cpu_ms(self.shell_pid)?;
+            Some(ResourceUsage {
+                user_time_ms: user.saturating_sub(start_user),
+                system_time_ms: sys.saturating_sub(start_sys),
+                max_rss_kb: self.max_rss_kb,
+            })
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            None
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::fs;
+
+    /// Accumulated user and system CPU time of `pid`'s reaped children
+    pub fn children_cpu_ms(pid: i32) -> Option<(u64, u64)> {
+        let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+        // The command name may contain spaces; fields are counted after it
+        let fields: Vec<&str> = stat[stat.rfind(')')? + 2..].split_whitespace().collect();
+        // cutime and cstime are fields 16 and 17 of the full line
+        let cutime: u64 = fields.get(13)?.parse().ok()?;
+        let cstime: u64 = fields.get(14)?.parse().ok()?;
+        // SAFETY: sysconf has no preconditions
+        let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u64;
+        Some((cutime * 1000 / ticks, cstime * 1000 / ticks))
+    }
+
+    /// Largest VmHWM among the processes in process group `pgrp`
+    pub fn group_peak_rss_kb(pgrp: i32) -> u64 {
+        let Ok(entries) = fs::read_dir("/proc") else {
+            return 0;
+        };
+        let mut peak = 0;
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let Some(pid) = name.to_str().and_then(|n| n.parse::<i32>().ok()) else {
+                continue;
+            };
+            let Ok(stat) = fs::read_to_string(format!("/proc/{}/stat", pid)) else {
+                continue;
+            };
+            let in_group = stat
+                .rfind(')')
+                .and_then(|i| stat[i + 2..].split_whitespace().nth(2))
+                .and_then(|f| f.parse::<i32>().ok())
+                == Some(pgrp);
+            if !in_group {
+                continue;
+            }
+            if let Ok(status) = fs::read_to_string(format!("/proc/{}/status", pid)) {
+                let hwm = status
+                    .lines()
+                    .find_map(|l| l.strip_prefix("VmHWM:"))
+                    .and_then(|v| v.trim().trim_end_matches("kB").trim().parse().ok())
+                    .unwrap_or(0);
+                peak = peak.max(hwm);
+            }
+        }
+        peak
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signal_from_shell_status() {
+        assert_eq!(signal_from_shell_status(130), Some(2));
+        assert_eq!(signal_from_shell_status(137), Some(9));
+        assert_eq!(signal_from_shell_status(1), None);
+        assert_eq!(signal_from_shell_status(255), None);
+        assert_eq!(signal_name(2), "SIGINT");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    // The child is reaped with wait4 rather than Child::wait
+    #[allow(clippy::zombie_processes)]
+    fn test_wait4_reports_signal_and_usage() {
+        let child = std::process::Command::new("sh")
+            .args(["-c", "i=0; while [ $i -lt 20000 ]; do i=$((i+1)); done; kill -TERM $$"])
+            .spawn()
+            .unwrap();
+        let pid = child.id() as i32;
+        let exit = loop {
+            if let Some(exit) = try_wait4(pid).unwrap() {
+                break exit;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        };
+
+        assert_eq!(exit.signal, Some(libc::SIGTERM));
+        assert_eq!(exit.exit_code, 128 + libc::SIGTERM);
+        assert!(!exit.core_dumped);
+        assert!(exit.resource_usage.unwrap().max_rss_kb > 0);
+    }
+}
@@ -0,0 +1,440 @@
+use crate::events::{CommandRecord, OutputChunkCollector, OutputStream};
+use crate::pty::terminal_output_to_text;
+use crate::shell_adapter::{IntegratedShell, ShellKind};
+use chrono::{DateTime, Utc};
+use std::path::PathBuf;
+
+/// Longest OSC sequence held back while waiting for its terminator. Anything
+/// longer is not a mark and is passed on as output.
+const MAX_OSC_LEN: usize = 4096;
+
+/// A shell-integration mark in terminal output, as defined by the OSC 133
+/// ("FinalTerm") prompt-mark protocol plus OSC 7 for the working directory.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PromptMark {
+    /// `OSC 133;A`: the prompt is about to be drawn
+    PromptStart,
+    /// `OSC 133;B`: the prompt has been drawn and the command line follows
+    CommandStart,
+    /// `OSC 133;C`: the command line was accepted and its output follows. The
+    /// command line itself comes from an optional `cmdline_url` parameter.
+    OutputStart { command_line: Option<String> },
+    /// `OSC 133;D[;status]`: the command has finished
+    CommandEnd { exit_code: Option<i32> },
+    /// `OSC 7;file://host/path`: the shell's working directory
+    WorkingDirectory(String),
+}
+
+/// A piece of terminal output: either plain output or a mark
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    Output(Vec<u8>),
+    Mark(PromptMark),
+}
+
+/// Splits terminal output into plain output and prompt marks. Marks may be
+/// split across reads; an unfinished OSC sequence is held back until the
+/// next `feed`.
+#[derive(Debug, Default)]
+pub struct PromptMarkParser {
+    pending: Vec<u8>,
+}
+
+impl PromptMarkParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<Segment> {
+        let mut data = std::mem::take(&mut self.pending);
+        data.extend_from_slice(bytes);
+
+        let mut segments = Vec::new();
+        let mut output_start = 0;
+        let mut i = 0;
+        while i < data.len() {
+            if data[i] != 0x1b {
+                i += 1;
+                continue;
+            }
+            let osc_end = match data.get(i + 1) {
+                Some(b']') => find_osc_end(&data[i + 2..]),
+                Some(_) => {
+                    i += 1;
+                    continue;
+                }
+                None => None,
+            };
+            match osc_end {
+                Some((body_len, terminator_len)) => {
+                    let end = i + 2 + body_len + terminator_len;
+                    if let Some(mark) = parse_mark(&data[i + 2..i + 2 + body_len]) {
+                        push_output(&mut segments, &data[output_start..i]);
+                        segments.push(Segment::Mark(mark));
+                        output_start = end;
+                    }
+                    i = end;
+                }
+                None if data.len() - i <= MAX_OSC_LEN => {
+                    push_output(&mut segments, &data[output_start..i]);
+                    self.pending = data[i..].to_vec();
+                    return segments;
+                }
+                None => i += 1,
+            }
+        }
+        push_output(&mut segments, &data[output_start..]);
+        segments
+    }
+}
+
+fn push_output(segments: &mut Vec<Segment>, bytes: &[u8]) {
+    if !bytes.is_empty() {
+        segments.push(Segment::Output(bytes.to_vec()));
+    }
+}
+
+/// Length of an OSC body and of its terminator (BEL or ST), or None if the
+/// terminator hasn't arrived yet. Another escape sequence also ends the body.
+fn find_osc_end(body: &[u8]) -> Option<(usize, usize)> {
+    for (i, &b) in body.iter().enumerate() {
+        match b {
+            0x07 => return Some((i, 1)),
+            0x1b => {
+                return match body.get(i + 1) {
+                    Some(b'\\') => Some((i, 2)),
+                    Some(_) => Some((i, 0)),
+                    None => None,
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parse_mark(body: &[u8]) -> Option<PromptMark> {
+    let body = String::from_utf8_lossy(body);
+    if let Some(url) = body.strip_prefix("7;") {
+        // file://host/path, where the host may be empty
+        let path = url.strip_prefix("file://")?;
+        let path = &path[path.find('/')?..];
+        return Some(PromptMark::WorkingDirectory(percent_decode(path)));
+    }
+
+    let mut params = body.strip_prefix("133;")?.split(';');
+    match params.next()? {
+        "A" => Some(PromptMark::PromptStart),
+        "B" => Some(PromptMark::CommandStart),
+        "C" => Some(PromptMark::OutputStart {
+            command_line: params
+                .find_map(|p| p.strip_prefix("cmdline_url="))
+                .map(percent_decode),
+        }),
+        "D" => Some(PromptMark::CommandEnd {
+            exit_code: params.next().and_then(|s| s.parse().ok()),
+        }),
+        _ => None,
+    }
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+struct RunningCommand {
+    command: String,
+    working_directory: String,
+    output: Vec<u8>,
+    chunks: OutputChunkCollector,
+    started_at: DateTime<Utc>,
+}
+
+enum TrackerState {
+    Idle,
+    /// Between `B` and `C`, while the shell echoes the line being typed
+    Editing { echo: Vec<u8> },
+    Running(RunningCommand),
+}
+
+/// Turns the marked regions of a shell's output into `CommandRecord`s. The
+/// command line comes from the `C` mark, or failing that from what the shell
+/// echoed after the prompt; the output is everything between `C` and `D`; the
+/// working directory is the last one reported with OSC 7 before `C`. A
+/// command that ends without a status is recorded with status 0.
+pub struct CommandTracker {
+    parser: PromptMarkParser,
+    working_directory: String,
+    state: TrackerState,
+    seen_marks: bool,
+}
+
+impl CommandTracker {
+    pub fn new(working_directory: &str) -> Self {
+        Self {
+            parser: PromptMarkParser::new(),
+            working_directory: working_directory.to_string(),
+            state: TrackerState::Idle,
+            seen_marks: false,
+        }
+    }
+
+    /// Whether the output contained any prompt marks so far
+    pub fn has_seen_marks(&self) -> bool {
+        self.seen_marks
+    }
+
+    pub fn working_directory(&self) -> &str {
+        &self.working_directory
+    }
+
+    /// Feed terminal output and return the commands it completed
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<CommandRecord> {
+        let mut finished = Vec::new();
+        for segment in self.parser.feed(bytes) {
+            let mark = match segment {
+                Segment::Output(bytes) => {
+                    match &mut self.state {
+                        TrackerState::Editing { echo } => echo.extend_from_slice(&bytes),
+                        TrackerState::Running(command) => {
+                            command.output.extend_from_slice(&bytes);
+                            command.chunks.push(OutputStream::Stdout, &bytes);
+                        }
+                        TrackerState::Idle => {}
+                    }
+                    continue;
+                }
+                Segment::Mark(mark) => mark,
+            };
+            self.seen_marks = true;
+
+            match mark {
+                PromptMark::WorkingDirectory(dir) => self.working_directory = dir,
+                PromptMark::PromptStart => {
+                    finished.extend(self.finish(0));
+                    self.state = TrackerState::Idle;
+                }
+                PromptMark::CommandStart => {
+                    finished.extend(self.finish(0));
+                    self.state = TrackerState::Editing { echo: Vec::new() };
+                }
+                PromptMark::OutputStart { command_line } => {
+                    let echoed = match &self.state {
+                        TrackerState::Editing { echo } => terminal_output_to_text(echo),
+                        _ => String::new(),
+                    };
+                    finished.extend(self.finish(0));
+                    // The echo ends with the newline the user typed, and maybe
+                    // a bracketed-paste switch after it
+                    let command = command_line.unwrap_or_else(|| {
+                        echoed
+                            .lines()
+                            .rfind(|line| !line.trim().is_empty())
+                            .unwrap_or_default()
+                            .to_string()
+                    });
+                    self.state = TrackerState::Running(RunningCommand {
+                        command: command.trim().to_string(),
+                        working_directory: self.working_directory.clone(),
+                        output: Vec::new(),
+                        chunks: OutputChunkCollector::new(),
+                        started_at: Utc::now(),
+                    });
+                }
+                PromptMark::CommandEnd { exit_code } => {
+                    finished.extend(self.finish(exit_code.unwrap_or(0)));
+                }
+            }
+        }
+        finished
+    }
+
+    /// Finish the running command, if any, with `exit_code`. Used when the
+    /// shell exits in the middle of a command, e.g. after `exit`.
+    pub fn finish(&mut self, exit_code: i32) -> Option<CommandRecord> {
+        let TrackerState::Running(command) = std::mem::replace(&mut self.state, TrackerState::Idle)
+        else {
+            return None;
+        };
+        if command.command.is_empty() {
+            return None;
+        }
+        Some(CommandRecord {
+            command: command.command,
+            stdout: terminal_output_to_text(&command.output),
+            stderr: String::new(),
+            output_chunks: command.chunks.finish(),
+            exit_code,
+            signal: None,
+            core_dumped: false,
+            resource_usage: None,
+            working_directory: command.working_directory,
+            environment: None,
+            started_at: command.started_at,
+            ended_at: Utc::now(),
+        })
+    }
+}
+
+/// A private directory holding the startup files that load the prompt-mark
+/// snippet into a user's interactive shell. Removed when dropped.
+pub struct ShellIntegration {
+    dir: PathBuf,
+    launch: IntegratedShell,
+}
+
+impl ShellIntegration {
+    /// Write the startup files for `kind`, or return None if there is no
+    /// snippet for that shell
+    pub fn prepare(kind: ShellKind) -> crate::Result<Option<Self>> {
+        let dir = std::env::temp_dir().join(format!("timeloop-shell-{}", uuid::Uuid::new_v4()));
+        let Some(launch) = kind.adapter().prompt_marks_launch(&dir.to_string_lossy()) else {
+            return Ok(None);
+        };
+
+        std::fs::create_dir_all(&dir)?;
+        let integration = Self { dir, launch };
+        for (name, contents) in &integration.launch.files {
+            std::fs::write(integration.dir.join(name), contents)?;
+        }
+        Ok(Some(integration))
+    }
+
+    pub fn program(&self) -> &str {
+        self.launch.program
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.launch.args
+    }
+
+    pub fn env(&self) -> &[(String, String)] {
+        &self.launch.env
+    }
+}
+
+impl Drop for ShellIntegration {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_marks_split_across_reads() {
+        let mut parser = PromptMarkParser::new();
+        let mut segments = parser.feed(b"\x1b]133;A\x07$ \x1b]133;B\x07ls\r\n\x1b]13");
+        segments.extend(parser.feed(b"3;C;cmdline_url=ls%20-a\x1b\\out\x1b[1mput\x1b]133;D;2\x07"));
+        segments.extend(parser.feed(b"\x1b]7;file://host/tmp/my%20dir\x07"));
+
+        assert_eq!(
+            segments,
+            vec![
+                Segment::Mark(PromptMark::PromptStart),
+                Segment::Output(b"$ ".to_vec()),
+                Segment::Mark(PromptMark::CommandStart),
+                Segment::Output(b"ls\r\n".to_vec()),
+                Segment::Mark(PromptMark::OutputStart {
+                    command_line: Some("ls -a".to_string())
+                }),
+                Segment::Output(b"out\x1b[1mput".to_vec()),
+                Segment::Mark(PromptMark::CommandEnd { exit_code: Some(2) }),
+                Segment::Mark(PromptMark::WorkingDirectory("/tmp/my dir".to_string())),
+            ]
+        );
+        // Other OSC sequences, such as window titles, are left in the output
+        assert_eq!(
+            parser.feed(b"\x1b]0;title\x07"),
+            vec![Segment::Output(b"\x1b]0;title\x07".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_tracker_builds_command_records() {
+        let mut tracker = CommandTracker::new("/start");
+        let records = tracker.feed(
+            b"\x1b]7;file:///work\x07\x1b]133;A\x07$ \x1b]133;B\x07echo hi\r\n\x1b]133;C\x07hi\r\n\
+              \x1b]133;D;0\x07\x1b]133;A\x07$ \x1b]133;B\x07\x1b]133;C;cmdline_url=false\x07",
+        );
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].command, "echo hi");
+        assert_eq!(records[0].stdout, "hi\n");
+        assert_eq!(records[0].exit_code, 0);
+        assert_eq!(records[0].working_directory, "/work");
+
+        let records = tracker.feed(b"\x1b]133;D;1\x07\x1b]133;A\x07$ ");
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].command, "false");
+        assert_eq!(records[0].exit_code, 1);
+        assert!(tracker.finish(0).is_none());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_bash_integration_marks_commands() {
+        let integration = ShellIntegration::prepare(ShellKind::Bash).unwrap().unwrap();
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let cwd = tmp_dir.path().to_str().unwrap();
+        let args: Vec<&str> = integration.args().iter().map(String::as_str).collect();
+        let (mut process, mut output_rx) =
+            crate::pty::PtyProcess::spawn_with_env(integration.program(), &args, cwd, integration.env())
+                .unwrap();
+
+        // The last line is left out of the history, so its text must come
+        // from the terminal's echo rather than the previous history entry
+        let lines: &[&[u8]] = &[
+            b"echo 'a b' && (exit 3)\n",
+            b"HISTCONTROL=ignorespace\n",
+            b" echo hidden\n",
+        ];
+        let mut tracker = CommandTracker::new(cwd);
+        let mut records = Vec::new();
+        let mut typed = 0;
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(20);
+        while records.len() < lines.len() {
+            let chunk = tokio::time::timeout_at(deadline, output_rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            records.extend(tracker.feed(&chunk));
+            // Type each line once the prompt before it is up
+            if typed == records.len() && tracker.has_seen_marks() && typed < lines.len() {
+                process.write_input(lines[typed]).unwrap();
+                typed += 1;
+            }
+        }
+        process.kill().unwrap();
+
+        assert_eq!(records[0].command, "echo 'a b' && (exit 3)");
+        assert_eq!(records[0].stdout.trim(), "a b");
+        assert_eq!(records[0].exit_code, 3);
+        assert_eq!(
+            std::fs::canonicalize(&records[0].working_directory).unwrap(),
+            std::fs::canonicalize(cwd).unwrap()
+        );
+        assert_eq!(records[2].command, "echo hidden");
+        assert_eq!(records[2].stdout.trim(), "hidden");
+    }
+}
@@ -0,0 +1,618 @@
+use crate::process::ProcessExit;
+use crate::TimeLoopError;
+use crossterm::event::{self, Event as CEvent, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+#[cfg(unix)]
+use portable_pty::SlavePty;
+use portable_pty::{native_pty_system, Child, CommandBuilder, MasterPty, PtySize};
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Input forwarded from the user's terminal to a child running in a PTY.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PtyInput {
+    /// Raw bytes to write to the PTY master
+    Bytes(Vec<u8>),
+    /// The user's terminal was resized to (cols, rows)
+    Resize(u16, u16),
+}
+
+/// A child process attached to a pseudo-terminal. Output is read on a
+/// dedicated thread and delivered through the receiver returned by `spawn`.
+pub struct PtyProcess {
+    master: Box<dyn MasterPty + Send>,
+    writer: Box<dyn Write + Send>,
+    child: Box<dyn Child + Send + Sync>,
+    // Set once the child has been reaped
+    exit: Option<ProcessExit>,
+}
+
+impl PtyProcess {
+    /// Spawn `program` with `args` in `cwd` inside a new PTY sized like the
+    /// user's terminal (80x24 when there is no terminal).
+    pub fn spawn(
+        program: &str,
+        args: &[&str],
+        cwd: &str,
+    ) -> crate::Result<(Self, mpsc::UnboundedReceiver<Vec<u8>>)> {
+        Self::spawn_with_env(program, args, cwd, &[])
+    }
+
+    /// Like `spawn`, with extra environment variables for the child
+    pub fn spawn_with_env(
+        program: &str,
+        args: &[&str],
+        cwd: &str,
+        env: &[(String, String)],
+    ) -> crate::Result<(Self, mpsc::UnboundedReceiver<Vec<u8>>)> {
+        let (cols, rows) = crossterm::terminal::size().unwrap_or((80, 24));
+        let pair = native_pty_system()
+            .openpty(PtySize {
+                rows,
+                cols,
+                pixel_width: 0,
+                pixel_height: 0,
+            })
+            .map_err(|e| TimeLoopError::CommandExecution(format!("Failed to open PTY: {}", e)))?;
+
+        let mut cmd = CommandBuilder::new(program);
+        cmd.args(args);
+        cmd.cwd(cwd);
+        if std::env::var_os("TERM").is_none() {
+            cmd.env("TERM", "xterm-256color");
+        }
+        for (key, value) in env {
+            cmd.env(key, value);
+        }
+
+        let child = pair
+            .slave
+            .spawn_command(cmd)
+            .map_err(|e| TimeLoopError::CommandExecution(e.to_string()))?;
+        // Only the child should hold the slave side open, so that reads on the
+        // master report EOF once it (and anything it spawned) has exited.
+        drop(pair.slave);
+
+        let reader = pair
+            .master
+            .try_clone_reader()
+            .map_err(|e| TimeLoopError::CommandExecution(e.to_string()))?;
+        let writer = pair
+            .master
+            .take_writer()
+            .map_err(|e| TimeLoopError::CommandExecution(e.to_string()))?;
+
+        let rx = spawn_reader(reader);
+
+        Ok((
+            Self {
+                master: pair.master,
+                writer,
+                child,
+                exit: None,
+            },
+            rx,
+        ))
+    }
+
+    /// Write bytes to the child's terminal input
+    pub fn write_input(&mut self, bytes: &[u8]) -> crate::Result<()> {
+        self.writer.write_all(bytes)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Resize the PTY, which delivers SIGWINCH to the foreground process
+    pub fn resize(&self, cols: u16, rows: u16) -> crate::Result<()> {
+        self.master
+            .resize(PtySize {
+                rows,
+                cols,
+                pixel_width: 0,
+                pixel_height: 0,
+            })
+            .map_err(|e| TimeLoopError::CommandExecution(e.to_string()))
+    }
+
+    /// Apply a forwarded input event to the PTY
+    pub fn apply_input(&mut self, input: PtyInput) -> crate::Result<()> {
+        match input {
+            PtyInput::Bytes(bytes) => self.write_input(&bytes),
+            PtyInput::Resize(cols, rows) => self.resize(cols, rows),
+        }
+    }
+
+    /// Return how the child exited, if it has. On Unix the child is reaped
+    /// with `wait4` so its signal and resource usage are known.
+    pub fn try_wait(&mut self) -> crate::Result<Option<ProcessExit>> {
+        if self.exit.is_none() {
+            #[cfg(unix)]
+            if let Some(pid) = self.child.process_id() {
+                self.exit = crate::process::try_wait4(pid as i32)?;
+                return Ok(self.exit);
+            }
+            self.exit = self
+                .child
+                .try_wait()?
+                .map(|status| ProcessExit::from_code(status.exit_code() as i32));
+        }
+        Ok(self.exit)
+    }
+
+    pub fn process_id(&self) -> Option<u32> {
+        self.child.process_id()
+    }
+
+    /// Signal the PTY's line discipline would raise for `bytes` typed by the
+    /// user (SIGINT for Ctrl-C, SIGQUIT for Ctrl-\, SIGTSTP for Ctrl-Z), or None
+    /// if the foreground program has signal generation turned off (e.g. an
+    /// editor in raw mode) or the input holds no such character.
+    pub fn signal_for_input(&self, bytes: &[u8]) -> Option<i32> {
+        #[cfg(unix)]
+        {
+            let fd = self.master.as_raw_fd()?;
+            // SAFETY: termios is plain data filled in by tcgetattr
+            let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+            if unsafe { libc::tcgetattr(fd, &mut termios) } != 0 || termios.c_lflag & libc::ISIG == 0 {
+                return None;
+            }
+            let chars = [
+                (termios.c_cc[libc::VINTR], libc::SIGINT),
+                (termios.c_cc[libc::VQUIT], libc::SIGQUIT),
+                (termios.c_cc[libc::VSUSP], libc::SIGTSTP),
+            ];
+            bytes.iter().find_map(|b| {
+                chars
+                    .iter()
+                    .find(|(c, _)| *c != 0 && c == b)
+                    .map(|(_, signal)| *signal)
+            })
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = bytes;
+            None
+        }
+    }
+
+    #[cfg(unix)]
+    pub fn master_fd(&self) -> Option<std::os::unix::io::RawFd> {
+        self.master.as_raw_fd()
+    }
+
+    /// Process group currently in the foreground of the PTY
+    pub fn foreground_process_group(&self) -> Option<i32> {
+        #[cfg(unix)]
+        {
+            self.master.process_group_leader()
+        }
+        #[cfg(not(unix))]
+        {
+            None
+        }
+    }
+
+    /// Kill the child process
+    pub fn kill(&mut self) -> crate::Result<()> {
+        if self.exit.is_none() {
+            self.child.kill()?;
+        }
+        Ok(())
+    }
+}
+
+/// A pseudo-terminal without a child of its own. Processes write to it by
+/// opening its slave device by path (e.g. `2>/dev/pts/5`), which keeps that
+/// output apart from the main PTY while programs still see a terminal. Used to
+/// capture stderr separately from stdout.
+#[cfg(unix)]
+pub struct CapturePty {
+    master: Box<dyn MasterPty + Send>,
+    // Held open so reads on the master don't fail while no process has the
+    // device open
+    _slave: Box<dyn SlavePty + Send>,
+    path: String,
+}
+
+#[cfg(unix)]
+impl CapturePty {
+    /// Open a PTY sized like the user's terminal. Output written to the slave
+    /// is delivered through the returned receiver.
+    pub fn open() -> crate::Result<(Self, mpsc::UnboundedReceiver<Vec<u8>>)> {
+        let (cols, rows) = crossterm::terminal::size().unwrap_or((80, 24));
+        let pair = native_pty_system()
+            .openpty(PtySize {
+                rows,
+                cols,
+                pixel_width: 0,
+                pixel_height: 0,
+            })
+            .map_err(|e| TimeLoopError::CommandExecution(format!("Failed to open PTY: {}", e)))?;
+
+        let fd = pair.master.as_raw_fd().ok_or_else(|| {
+            TimeLoopError::CommandExecution("PTY master has no file descriptor".to_string())
+        })?;
+        let path = slave_path(fd)?;
+        let reader = pair
+            .master
+            .try_clone_reader()
+            .map_err(|e| TimeLoopError::CommandExecution(e.to_string()))?;
+
+        Ok((
+            Self {
+                master: pair.master,
+                _slave: pair.slave,
+                path,
+            },
+            spawn_reader(reader),
+        ))
+    }
+
+    /// Path of the slave device, e.g. `/dev/pts/5`
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn resize(&self, cols: u16, rows: u16) -> crate::Result<()> {
+        self.master
+            .resize(PtySize {
+                rows,
+                cols,
+                pixel_width: 0,
+                pixel_height: 0,
+            })
+            .map_err(|e| TimeLoopError::CommandExecution(e.to_string()))
+    }
+}
+
+/// Forwards SIGINT, SIGQUIT and SIGTSTP received by TimeLoop itself to the
+/// foreground process group of a PTY for as long as it is alive. Keyboard
+/// interrupts normally reach the child as input bytes through the PTY; this
+/// covers signals sent to TimeLoop directly, e.g. when its input is not a
+/// terminal or via `kill`. The previous handlers are restored on drop.
+#[cfg(unix)]
+pub struct SignalForwarder {
+    previous: Vec<(libc::c_int, libc::sigaction)>,
+}
+
+#[cfg(unix)]
+static FORWARD_FD: std::sync::atomic::AtomicI32 = std::sync::atomic::AtomicI32::new(-1);
+#[cfg(unix)]
+static FORWARDED_SIGNALS: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+
+#[cfg(unix)]
+impl SignalForwarder {
+    const SIGNALS: [libc::c_int; 3] = [libc::SIGINT, libc::SIGQUIT, libc::SIGTSTP];
+
+    pub fn install(master_fd: std::os::unix::io::RawFd) -> Self {
+        FORWARD_FD.store(master_fd, Ordering::SeqCst);
+        FORWARDED_SIGNALS.store(0, Ordering::SeqCst);
+        let mut previous = Vec::new();
+        for signal in Self::SIGNALS {
+            // SAFETY: the handler only uses async-signal-safe calls and atomics
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = forward_signal as extern "C" fn(libc::c_int) as usize;
+                action.sa_flags = libc::SA_RESTART;
+                libc::sigemptyset(&mut action.sa_mask);
+                let mut old: libc::sigaction = std::mem::zeroed();
+                if libc::sigaction(signal, &action, &mut old) == 0 {
+                    previous.push((signal, old));
+                }
+            }
+        }
+        Self { previous }
+    }
+
+    /// Signals forwarded since the last call
+    pub fn take_forwarded(&self) -> Vec<i32> {
+        let mask = FORWARDED_SIGNALS.swap(0, Ordering::SeqCst);
+        Self::SIGNALS
+            .into_iter()
+            .filter(|signal| mask & (1 << signal) != 0)
+            .collect()
+    }
+}
+
+#[cfg(unix)]
+impl Drop for SignalForwarder {
+    fn drop(&mut self) {
+        for (signal, old) in &self.previous {
+            // SAFETY: restores the action saved by install
+            unsafe {
+                libc::sigaction(*signal, old, std::ptr::null_mut());
+            }
+        }
+        FORWARD_FD.store(-1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn forward_signal(signal: libc::c_int) {
+    let fd = FORWARD_FD.load(Ordering::SeqCst);
+    if fd < 0 {
+        return;
+    }
+    // SAFETY: tcgetpgrp and killpg are async-signal-safe
+    unsafe {
+        let pgrp = libc::tcgetpgrp(fd);
+        if pgrp > 0 {
+            libc::killpg(pgrp, signal);
+        }
+    }
+    FORWARDED_SIGNALS.fetch_or(1 << signal, Ordering::SeqCst);
+}
+
+/// Look up the slave device path for a PTY master
+#[cfg(unix)]
+fn slave_path(master_fd: std::os::unix::io::RawFd) -> crate::Result<String> {
+    // ptsname returns a pointer to static storage, so calls are serialized
+    static PTSNAME_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    let _guard = PTSNAME_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    // SAFETY: the fd is a valid PTY master and the returned string is copied
+    // before the lock is released
+    unsafe {
+        let name = libc::ptsname(master_fd);
+        if name.is_null() {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(std::ffi::CStr::from_ptr(name).to_string_lossy().into_owned())
+    }
+}
+
+/// Read from a PTY master (or any pipe) on a dedicated thread until it closes
+pub(crate) fn spawn_reader(mut reader: Box<dyn Read + Send>) -> mpsc::UnboundedReceiver<Vec<u8>> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    thread::spawn(move || {
+        let mut buf = [0u8; 8192];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    if tx.send(buf[..n].to_vec()).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+    rx
+}
+
+/// Forwards keystrokes, pastes and resizes from the user's terminal to a
+/// channel while a child owns the screen. Runs on its own thread and polls
+/// with a short timeout so it can be stopped before the prompt reads input again.
+pub struct InputForwarder {
+    stop: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl InputForwarder {
+    pub fn start(tx: mpsc::UnboundedSender<PtyInput>) -> Self {
+        Self::spawn(tx, None)
+    }
+
+    /// Like `start`, and also send the name of every key forwarded (as given
+    /// by `line_editor::key_name`) to `keys`, so that it can be recorded
+    pub fn start_with_key_log(
+        tx: mpsc::UnboundedSender<PtyInput>,
+        keys: mpsc::UnboundedSender<String>,
+    ) -> Self {
+        Self::spawn(tx, Some(keys))
+    }
+
+    fn spawn(tx: mpsc::UnboundedSender<PtyInput>, keys: Option<mpsc::UnboundedSender<String>>) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_flag = stop.clone();
+        let handle = thread::spawn(move || {
+            let log_key = |key: &KeyEvent| {
+                if let Some(keys) = &keys {
+                    let _ = keys.send(crate::line_editor::key_name(key));
+                }
+            };
+            while !stop_flag.load(Ordering::SeqCst) {
+                // Polling fails when there is no terminal attached (e.g. in tests);
+                // there is nothing to forward in that case.
+                match event::poll(Duration::from_millis(20)) {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(_) => break,
+                }
+                let input = match event::read() {
+                    Ok(CEvent::Key(key)) if key.kind != KeyEventKind::Release => {
+                        log_key(&key);
+                        PtyInput::Bytes(encode_key(&key))
+                    }
+                    Ok(CEvent::Paste(text)) => {
+                        for c in text.chars() {
+                            log_key(&KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+                        }
+                        PtyInput::Bytes(text.into_bytes())
+                    }
+                    Ok(CEvent::Resize(cols, rows)) => PtyInput::Resize(cols, rows),
+                    Ok(_) => continue,
+                    Err(_) => break,
+                };
+                if tx.send(input).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    /// Stop forwarding and wait for the polling thread to finish
+    pub fn stop(mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for InputForwarder {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Encode a key event as the byte sequence an xterm-compatible terminal sends
+pub fn encode_key(key: &KeyEvent) -> Vec<u8> {
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    let alt = key.modifiers.contains(KeyModifiers::ALT);
+
+    let mut bytes: Vec<u8> = match key.code {
+        KeyCode::Char(c) if ctrl => match c.to_ascii_lowercase() {
+            l @ 'a'..='z' => vec![l as u8 - b'a' + 1],
+            ' ' | '@' | '2' => vec![0x00],
+            '[' | '3' => vec![0x1b],
+            '\\' | '4' => vec![0x1c],
+            ']' | '5' => vec![0x1d],
+            '^' | '6' => vec![0x1e],
+            '_' | '7' => vec![0x1f],
+            _ => c.to_string().into_bytes(),
+        },
+        KeyCode::Char(c) => c.to_string().into_bytes(),
+        KeyCode::Enter => vec![b'\r'],
+        KeyCode::Tab => vec![b'\t'],
+        KeyCode::BackTab => b"\x1b[Z".to_vec(),
+        KeyCode::Backspace => vec![0x7f],
+        KeyCode::Esc => vec![0x1b],
+        KeyCode::Up => b"\x1b[A".to_vec(),
+        KeyCode::Down => b"\x1b[B".to_vec(),
+        KeyCode::Right => b"\x1b[C".to_vec(),
+        KeyCode::Left => b"\x1b[D".to_vec(),
+        KeyCode::Home => b"\x1b[H".to_vec(),
+        KeyCode::End => b"\x1b[F".to_vec(),
+        KeyCode::PageUp => b"\x1b[5~".to_vec(),
+        KeyCode::PageDown => b"\x1b[6~".to_vec(),
+        KeyCode::Insert => b"\x1b[2~".to_vec(),
+        KeyCode::Delete => b"\x1b[3~".to_vec(),
+        KeyCode::F(n) => match n {
+            1 => b"\x1bOP".to_vec(),
+            2 => b"\x1bOQ".to_vec(),
+            3 => b"\x1bOR".to_vec(),
+            4 => b"\x1bOS".to_vec(),
+            5 => b"\x1b[15~".to_vec(),
+            6 => b"\x1b[17~".to_vec(),
+            7 => b"\x1b[18~".to_vec(),
+            8 => b"\x1b[19~".to_vec(),
+            9 => b"\x1b[20~".to_vec(),
+            10 => b"\x1b[21~".to_vec(),
+            11 => b"\x1b[23~".to_vec(),
+            12 => b"\x1b[24~".to_vec(),
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    };
+
+    if alt && !bytes.is_empty() {
+        bytes.insert(0, 0x1b);
+    }
+    bytes
+}
+
+/// Convert raw terminal output into plain text: CRLF line endings become LF
+/// and ANSI escape sequences (colors, cursor movement, titles) are removed.
+pub fn terminal_output_to_text(raw: &[u8]) -> String {
+    let text = String::from_utf8_lossy(raw);
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                // CSI: parameters and intermediates up to a final byte in @..~
+                Some('[') => {
+                    for n in chars.by_ref() {
+                        if ('@'..='~').contains(&n) {
+                            break;
+                        }
+                    }
+                }
+                // OSC: terminated by BEL or ST (ESC \)
+                Some(']') => {
+                    while let Some(n) = chars.next() {
+                        if n == '\x07' {
+                            break;
+                        }
+                        if n == '\x1b' && chars.peek() == Some(&'\\') {
+                            chars.next();
+                            break;
+                        }
+                    }
+                }
+                // Character set selection and similar two-byte sequences
+                Some('(') | Some(')') | Some('#') => {
+                    chars.next();
+                }
+                _ => {}
+            },
+            '\r' => {
+                if chars.peek() == Some(&'\n') {
+                    continue;
+                }
+                out.push('\n');
+            }
+            '\x07' => {}
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_key() {
+        let key = |code, modifiers| KeyEvent::new(code, modifiers);
+        assert_eq!(encode_key(&key(KeyCode::Char('a'), KeyModifiers::NONE)), b"a");
+        assert_eq!(encode_key(&key(KeyCode::Char('c'), KeyModifiers::CONTROL)), vec![0x03]);
+        assert_eq!(encode_key(&key(KeyCode::Char('b'), KeyModifiers::ALT)), b"\x1bb");
+        assert_eq!(encode_key(&key(KeyCode::Enter, KeyModifiers::NONE)), b"\r");
+        assert_eq!(encode_key(&key(KeyCode::Up, KeyModifiers::NONE)), b"\x1b[A");
+        assert_eq!(encode_key(&key(KeyCode::Char('é'), KeyModifiers::NONE)), "é".as_bytes());
+    }
+
+    #[test]
+    fn test_terminal_output_to_text() {
+        let raw = b"\x1b[1;32mok\x1b[0m\r\n\x1b]0;title\x07done\r\n";
+        assert_eq!(terminal_output_to_text(raw), "ok\ndone\n");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_child_sees_a_terminal() {
+        let cwd = std::env::temp_dir();
+        let (mut process, mut rx) =
+            PtyProcess::spawn("sh", &["-c", "test -t 1 && echo tty"], cwd.to_str().unwrap())
+                .unwrap();
+
+        let mut output = Vec::new();
+        let exit = loop {
+            if let Some(exit) = process.try_wait().unwrap() {
+                break exit;
+            }
+            tokio::select! {
+                Some(chunk) = rx.recv() => output.extend_from_slice(&chunk),
+                _ = tokio::time::sleep(Duration::from_millis(20)) => {}
+            }
+        };
+        while let Ok(Some(chunk)) =
+            tokio::time::timeout(Duration::from_millis(200), rx.recv()).await
+        {
+            output.extend_from_slice(&chunk);
+        }
+
+        assert_eq!(exit.exit_code, 0);
+        assert_eq!(terminal_output_to_text(&output).trim(), "tty");
+    }
+}
@@ -0,0 +1,218 @@
+#[cfg(unix)]
+use crate::pty::SignalForwarder;
+use crate::pty::{terminal_output_to_text, InputForwarder, PtyInput, PtyProcess};
+use crate::events::{CommandRecord, OutputChunkCollector, OutputStream};
+use crate::prompt_marks::{CommandTracker, ShellIntegration};
+use crate::shell_adapter::{quote_word, ShellKind};
+use crate::terminal::RawModeGuard;
+use crate::EventRecorder;
+use chrono::Utc;
+use std::io::{self, Write};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Runs any program (a login shell, a REPL, a TUI) in a pseudo-terminal and
+/// records it into a session, the way `script(1)` does. Keystrokes and resizes
+/// are recorded as they happen; the program's output, exit status and resource
+/// usage are recorded as one `Command` event once it exits.
+///
+/// A shell that marks its prompts with OSC 133 is recorded one `Command` event
+/// per command instead. Bash, zsh and fish started without arguments get
+/// TimeLoop's prompt-mark snippet loaded after the user's own startup files.
+pub struct ProgramRecorder {
+    event_recorder: EventRecorder,
+}
+
+impl ProgramRecorder {
+    pub fn new(event_recorder: EventRecorder) -> Self {
+        Self { event_recorder }
+    }
+
+    /// Run `program` with `args` in `cwd` until it exits and return its exit
+    /// code, using the shell convention of 128 + N for a program killed by
+    /// signal N.
+    pub async fn run(&mut self, program: &str, args: &[String], cwd: &str) -> crate::Result<i32> {
+        let command = std::iter::once(program)
+            .chain(args.iter().map(String::as_str))
+            .map(quote_word)
+            .collect::<Vec<_>>()
+            .join(" ");
+        let (cols, rows) = crossterm::terminal::size().unwrap_or((80, 24));
+        self.event_recorder.record_terminal_state((0, 0), (cols, rows))?;
+
+        let integration = match program.parse::<ShellKind>() {
+            Ok(kind) if args.is_empty() => ShellIntegration::prepare(kind)?,
+            _ => None,
+        };
+        let (program, args, env) = match &integration {
+            Some(integration) => (integration.program(), integration.args(), integration.env()),
+            None => (program, args, &[][..]),
+        };
+        let arg_refs: Vec<&str> = args.iter().map(String::as_str).collect();
+        let started_at = Utc::now();
+        let (mut process, mut output_rx) = PtyProcess::spawn_with_env(program, &arg_refs, cwd, env)?;
+        let mut tracker = CommandTracker::new(cwd);
+
+        let raw_mode = RawModeGuard::enable();
+        let (input_tx, mut input_rx) = mpsc::unbounded_channel();
+        let (key_tx, mut key_rx) = mpsc::unbounded_channel();
+        let forwarder = InputForwarder::start_with_key_log(input_tx, key_tx);
+
+        let mut stdout = io::stdout();
+        let mut captured = Vec::new();
+        let mut chunks = OutputChunkCollector::new();
+        #[cfg(unix)]
+        let signals = process.master_fd().map(SignalForwarder::install);
+        let mut exit_check = tokio::time::interval(Duration::from_millis(20));
+        let exit = loop {
+            tokio::select! {
+                Some(chunk) = output_rx.recv() => {
+                    stdout.write_all(&chunk)?;
+                    stdout.flush()?;
+                    captured.extend_from_slice(&chunk);
+                    chunks.push(OutputStream::Stdout, &chunk);
+                    for record in tracker.feed(&chunk) {
+                        self.event_recorder.record_command_execution(record)?;
+                    }
+                }
+                Some(key) = key_rx.recv() => {
+                    self.event_recorder.record_key_press(&key)?;
+                }
+                Some(input) = input_rx.recv() => {
+                    match &input {
+                        PtyInput::Bytes(bytes) => {
+                            if let Some(signal) = process.signal_for_input(bytes) {
+                                self.event_recorder.record_interrupt(&command, signal, Utc::now())?;
+                            }
+                        }
+                        PtyInput::Resize(cols, rows) => {
+                            self.event_recorder.record_terminal_state((0, 0), (*cols, *rows))?;
+                        }
+                    }
+                    // The program may already have exited; its exit is picked up below
+                    let _ = process.apply_input(input);
+                }
+                _ = exit_check.tick() => {
+                    #[cfg(unix)]
+                    if let Some(signals) = &signals {
+                        for signal in signals.take_forwarded() {
+                            self.event_recorder.record_interrupt(&command, signal, Utc::now())?;
+                        }
+                    }
+                    if let Some(exit) = process.try_wait()? {
+                        break exit;
+                    }
+                }
+            }
+        };
+        forwarder.stop();
+        #[cfg(unix)]
+        drop(signals);
+
+        // Drain what the program wrote right before exiting
+        while let Ok(Some(chunk)) =
+            tokio::time::timeout(Duration::from_millis(50), output_rx.recv()).await
+        {
+            stdout.write_all(&chunk)?;
+            captured.extend_from_slice(&chunk);
+            chunks.push(OutputStream::Stdout, &chunk);
+            for record in tracker.feed(&chunk) {
+                self.event_recorder.record_command_execution(record)?;
+            }
+        }
+        stdout.flush()?;
+        drop(raw_mode);
+
+        if tracker.has_seen_marks() {
+            // The shell exited part way through a command, e.g. `exit`
+            if let Some(record) = tracker.finish(exit.exit_code) {
+                self.event_recorder.record_command_execution(record)?;
+            }
+        } else {
+            self.event_recorder.record_command_execution(CommandRecord {
+                command,
+                stdout: terminal_output_to_text(&captured),
+                stderr: String::new(),
+                output_chunks: chunks.finish(),
+                exit_code: exit.exit_code,
+                signal: exit.signal,
+                core_dumped: exit.core_dumped,
+                resource_usage: exit.resource_usage,
+                working_directory: cwd.to_string(),
+                environment: None,
+                started_at,
+                ended_at: Utc::now(),
+            })?;
+        }
+        // Callers usually exit with the program's code right away
+        self.event_recorder.storage().flush()?;
+
+        Ok(match exit.signal {
+            Some(signal) => 128 + signal,
+            None => exit.exit_code,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_records_program_output_and_exit() {
+        let tmp_dir = TempDir::new().unwrap();
+        let db_path = tmp_dir.path().join("events_record.db");
+        let storage = crate::storage::Storage::with_path(db_path.to_str().unwrap()).unwrap();
+        let event_recorder = EventRecorder::with_storage("record-test", storage.clone());
+        let mut recorder = ProgramRecorder::new(event_recorder);
+
+        let args = vec!["-c".to_string(), "echo recorded; exit 4".to_string()];
+        let code = recorder.run("sh", &args, tmp_dir.path().to_str().unwrap()).await.unwrap();
+        assert_eq!(code, 4);
+
+        let events = storage.get_events_for_session("record-test").unwrap();
+        let command = events
+            .iter()
+            .find_map(|e| match &e.event_type {
+                crate::EventType::Command { command, output, exit_code, .. } => {
+                    Some((command.clone(), output.clone(), *exit_code))
+                }
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(command.0, "sh -c 'echo recorded; exit 4'");
+        assert_eq!(command.1.trim(), "recorded");
+        assert_eq!(command.2, 4);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_prompt_marks_split_commands() {
+        let tmp_dir = TempDir::new().unwrap();
+        let db_path = tmp_dir.path().join("events_marks.db");
+        let storage = crate::storage::Storage::with_path(db_path.to_str().unwrap()).unwrap();
+        let event_recorder = EventRecorder::with_storage("marks-test", storage.clone());
+        let mut recorder = ProgramRecorder::new(event_recorder);
+
+        // A stand-in for an integrated shell that runs two commands
+        let script = r"printf '\033]133;A\007$ \033]133;B\007'; \
+                       printf '\033]133;C;cmdline_url=make%%20test\007built\n\033]133;D;2\007'; \
+                       printf '\033]133;A\007$ \033]133;B\007ls\n\033]133;C\007a.txt\n\033]133;D;0\007'";
+        let args = vec!["-c".to_string(), script.to_string()];
+        recorder.run("sh", &args, tmp_dir.path().to_str().unwrap()).await.unwrap();
+
+        let events = storage.get_events_for_session("marks-test").unwrap();
+        let commands: Vec<_> = events
+            .iter()
+            .filter_map(|e| match &e.event_type {
+                crate::EventType::Command { command, output, exit_code, .. } => {
+                    Some((command.as_str(), output.trim(), *exit_code))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(commands, vec![("make test", "built", 2), ("ls", "a.txt", 0)]);
+    }
+}
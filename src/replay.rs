@@ -1,3 +1,5 @@
+use crate::bookmark::Bookmark;
+use crate::process::{is_suspend_signal, signal_name};
 use crate::{Event, EventType, FileChangeType, Storage};
 use crossterm::event::{self, Event as CEvent, KeyCode};
 use crossterm::{
@@ -13,6 +15,7 @@ use tokio::time::{sleep, sleep_until, Instant};
 pub struct ReplayEngine {
     storage: Storage,
     session_id: String,
+    pause_at_bookmarks: bool,
 }
 
 impl ReplayEngine {
@@ -21,11 +24,27 @@ impl ReplayEngine {
         Ok(Self {
             storage,
             session_id: session_id.to_string(),
+            pause_at_bookmarks: false,
         })
     }
 
+    pub fn with_storage(session_id: &str, storage: Storage) -> Self {
+        Self {
+            storage,
+            session_id: session_id.to_string(),
+            pause_at_bookmarks: false,
+        }
+    }
+
+    /// Make `replay` stop at each bookmarked event until space is pressed.
+    /// Bookmarks are highlighted either way.
+    pub fn set_pause_at_bookmarks(&mut self, pause: bool) {
+        self.pause_at_bookmarks = pause;
+    }
+
     pub async fn replay(&self, speed: f32) -> crate::Result<()> {
         let events = self.storage.get_events_for_session(&self.session_id)?;
+        let bookmarks = self.storage.get_bookmarks_for_session(&self.session_id)?;
 
         if events.is_empty() {
             println!("No events found for session: {}", self.session_id);
@@ -67,66 +86,51 @@ impl ReplayEngine {
         });
 
         for (i, event) in events.iter().enumerate() {
-            // Calculate delay based on speed
+            // Calculate delay based on speed. Commands with timed output start
+            // playing early enough to finish at their recorded end time.
             let delay = if i > 0 {
                 let time_diff = event.timestamp - last_timestamp;
-                let delay_ms = time_diff.num_milliseconds() as u64;
+                let delay_ms = (time_diff.num_milliseconds() - output_span_ms(event) as i64).max(0) as u64;
                 (delay_ms as f32 / current_speed) as u64
             } else {
                 0
             };
 
-            if delay > 0 {
-                let start = Instant::now();
-                let deadline = start + Duration::from_millis(delay);
-
-                loop {
-                    let now = Instant::now();
-                    if now >= deadline {
-                        break;
-                    }
-
-                    // Handle interactive input during delay
-                    let mut input_event = None;
+            if delay > 0 && !wait_with_controls(delay, &mut rx, &mut paused, &mut current_speed).await {
+                println!("\n⏹️  Quit replay");
+                return Ok(());
+            }
 
-                    if paused {
-                        // If paused, we wait for input or a small timeout to emulate original behavior
-                        // Original behavior: sleep 50ms, then check loop condition (which might exit if delay passed)
-                        tokio::select! {
-                            evt = rx.recv() => input_event = evt,
-                            _ = sleep(Duration::from_millis(50)) => {}
-                        }
-                    } else {
-                        // Wait for deadline or input
-                        tokio::select! {
-                            evt = rx.recv() => input_event = evt,
-                            _ = sleep_until(deadline) => {}
-                        }
-                    }
+            // Display the event
+            self.display_event(event, i + 1, events.len())?;
 
-                    if let Some(CEvent::Key(key)) = input_event {
-                        match key.code {
-                            KeyCode::Char(' ') => {
-                                paused = !paused;
-                            }
-                            KeyCode::Char('+') => {
-                                current_speed *= 2.0;
-                            }
-                            KeyCode::Char('-') => {
-                                current_speed = (current_speed / 2.0).max(0.25);
-                            }
-                            KeyCode::Char('q') => {
-                                println!("\n⏹️  Quit replay");
-                                return Ok(());
-                            }
-                            _ => {}
+            if let EventType::Command { output_chunks, .. } = &event.event_type {
+                if !output_chunks.is_empty() {
+                    let mut stdout = std::io::stdout();
+                    let mut previous_offset = 0;
+                    for chunk in output_chunks {
+                        let gap = chunk.offset_ms.saturating_sub(previous_offset);
+                        let wait = (gap as f32 / current_speed) as u64;
+                        if wait > 0 && !wait_with_controls(wait, &mut rx, &mut paused, &mut current_speed).await {
+                            println!("\n⏹️  Quit replay");
+                            return Ok(());
                         }
+                        stdout.write_all(chunk.data.as_bytes())?;
+                        stdout.flush()?;
+                        previous_offset = chunk.offset_ms;
                     }
+                    self.display_command_exit(&event.event_type)?;
                 }
             }
 
-            // Display the event
-            self.display_event(event, i + 1, events.len())?;
+            let marks: Vec<&Bookmark> = bookmarks.iter().filter(|b| b.event_id == event.id).collect();
+            if !marks.is_empty() {
+                self.display_bookmarks(&marks, self.pause_at_bookmarks)?;
+                if self.pause_at_bookmarks && !wait_for_resume(&mut rx).await {
+                    println!("\n⏹️  Quit replay");
+                    return Ok(());
+                }
+            }
 
             last_timestamp = event.timestamp;
         }
@@ -166,16 +170,28 @@ impl ReplayEngine {
             EventType::Command {
                 command,
                 output,
-                exit_code,
-                working_directory,
+                stderr,
+                output_chunks,
+                duration_ms,
                 ..
             } => {
                 stdout.execute(SetForegroundColor(Color::Blue))?;
                 stdout.execute(Print("💻 "))?;
                 stdout.execute(ResetColor)?;
                 stdout.execute(Print(format!("Command: {}", command)))?;
+                if let Some(duration_ms) = duration_ms {
+                    stdout.execute(SetForegroundColor(Color::DarkGrey))?;
+                    stdout.execute(Print(format!(" ({:.2}s)", *duration_ms as f64 / 1000.0)))?;
+                    stdout.execute(ResetColor)?;
+                }
                 stdout.execute(Print("\n"))?;
 
+                // Timed output is played back by the caller, followed by the exit line
+                if !output_chunks.is_empty() {
+                    stdout.flush()?;
+                    return Ok(());
+                }
+
                 if !output.is_empty() {
                     stdout.execute(SetForegroundColor(Color::DarkGrey))?;
                     stdout.execute(Print("   Output: "))?;
@@ -183,15 +199,20 @@ impl ReplayEngine {
                     stdout.execute(Print(output))?;
                 }
 
-                stdout.execute(SetForegroundColor(Color::Magenta))?;
-                stdout.execute(Print(format!(
-                    "   Exit: {}, Dir: {}",
-                    exit_code, working_directory
-                )))?;
-                stdout.execute(ResetColor)?;
+                if !stderr.is_empty() {
+                    stdout.execute(SetForegroundColor(Color::Red))?;
+                    stdout.execute(Print("   Stderr: "))?;
+                    stdout.execute(ResetColor)?;
+                    stdout.execute(Print(stderr))?;
+                }
+
+                return self.display_command_exit(&event.event_type);
             }
             EventType::FileChange {
-                path, change_type, ..
+                path,
+                change_type,
+                diff,
+                ..
             } => {
                 stdout.execute(SetForegroundColor(Color::Red))?;
                 stdout.execute(Print("📁 "))?;
@@ -206,6 +227,21 @@ impl ReplayEngine {
                     },
                     path
                 )))?;
+                if let Some(diff) = diff {
+                    // The file names are already shown above
+                    for line in crate::events::diff_hunks(diff) {
+                        let color = match line.as_bytes().first() {
+                            Some(b'+') => Color::Green,
+                            Some(b'-') => Color::Red,
+                            Some(b'@') => Color::Cyan,
+                            _ => Color::DarkGrey,
+                        };
+                        stdout.execute(Print("\n   "))?;
+                        stdout.execute(SetForegroundColor(color))?;
+                        stdout.execute(Print(line))?;
+                        stdout.execute(ResetColor)?;
+                    }
+                }
             }
             EventType::TerminalState {
                 cursor_position,
@@ -220,14 +256,126 @@ impl ReplayEngine {
                     screen_size.0, screen_size.1, cursor_position
                 )))?;
             }
-            EventType::SessionMetadata { name, .. } => {
+            EventType::Interrupt {
+                signal, command, ..
+            } => {
+                stdout.execute(SetForegroundColor(Color::Red))?;
+                stdout.execute(Print("⛔ "))?;
+                stdout.execute(ResetColor)?;
+                let action = if is_suspend_signal(*signal) {
+                    "Suspended"
+                } else {
+                    "Interrupted"
+                };
+                stdout.execute(Print(format!(
+                    "{} by user ({}): {}",
+                    action,
+                    signal_name(*signal),
+                    command
+                )))?;
+            }
+            EventType::SessionMetadata { name, shell, .. } => {
                 stdout.execute(SetForegroundColor(Color::White))?;
                 stdout.execute(Print("📝 "))?;
                 stdout.execute(ResetColor)?;
                 stdout.execute(Print(format!("Session: {}", name)))?;
+                if let Some(shell) = shell {
+                    stdout.execute(Print(format!(" ({})", shell)))?;
+                }
+            }
+            EventType::Baseline { root, files, .. } => {
+                stdout.execute(SetForegroundColor(Color::DarkGrey))?;
+                stdout.execute(Print("📸 "))?;
+                stdout.execute(ResetColor)?;
+                stdout.execute(Print(format!("Baseline: {} files in {}", files.len(), root)))?;
+            }
+            EventType::Undo {
+                commands,
+                restored,
+                deleted,
+                ..
+            } => {
+                stdout.execute(SetForegroundColor(Color::Magenta))?;
+                stdout.execute(Print("↩️  "))?;
+                stdout.execute(ResetColor)?;
+                stdout.execute(Print(format!(
+                    "Undid {} commands: {} files restored, {} removed",
+                    commands.len(),
+                    restored.len(),
+                    deleted.len()
+                )))?;
+            }
+            EventType::Custom { kind, payload, .. } => {
+                stdout.execute(SetForegroundColor(Color::Cyan))?;
+                stdout.execute(Print("🧩 "))?;
+                stdout.execute(ResetColor)?;
+                stdout.execute(Print(format!(
+                    "{}: {}",
+                    kind,
+                    crate::custom_event::render(kind, payload)
+                )))?;
+            }
+        }
+
+        stdout.execute(Print("\n"))?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    /// Highlight the bookmarks on an event, and say if the replay waits there
+    fn display_bookmarks(&self, bookmarks: &[&Bookmark], paused: bool) -> crate::Result<()> {
+        let mut stdout = std::io::stdout();
+        stdout.execute(SetForegroundColor(Color::Yellow))?;
+        for bookmark in bookmarks {
+            stdout.execute(Print(format!("   🔖 {}\n", bookmark.label())))?;
+        }
+        if paused {
+            stdout.execute(Print("   ⏸️  Paused at bookmark, press space to continue\n"))?;
+        }
+        stdout.execute(ResetColor)?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    /// Print the exit line of a command event: status, how it was terminated,
+    /// resources used and the directory it ran in
+    fn display_command_exit(&self, event_type: &EventType) -> crate::Result<()> {
+        let EventType::Command {
+            exit_code,
+            signal,
+            core_dumped,
+            resource_usage,
+            working_directory,
+            ..
+        } = event_type
+        else {
+            return Ok(());
+        };
+
+        let mut status = format!("   Exit: {}", exit_code);
+        if let Some(signal) = signal {
+            status.push_str(&format!(" ({}", signal_name(*signal)));
+            if *core_dumped {
+                status.push_str(", core dumped");
+            }
+            status.push(')');
+        }
+        if let Some(usage) = resource_usage {
+            status.push_str(&format!(
+                ", CPU: {:.2}s user {:.2}s sys",
+                usage.user_time_ms as f64 / 1000.0,
+                usage.system_time_ms as f64 / 1000.0
+            ));
+            if usage.max_rss_kb > 0 {
+                status.push_str(&format!(", Peak RSS: {} MB", usage.max_rss_kb / 1024));
             }
         }
+        status.push_str(&format!(", Dir: {}", working_directory));
 
+        let mut stdout = std::io::stdout();
+        stdout.execute(SetForegroundColor(Color::Magenta))?;
+        stdout.execute(Print(status))?;
+        stdout.execute(ResetColor)?;
         stdout.execute(Print("\n"))?;
         stdout.flush()?;
         Ok(())
@@ -242,6 +390,7 @@ impl ReplayEngine {
         let events = self
             .storage
             .get_events_in_range(&self.session_id, start_time, end_time)?;
+        let bookmarks = self.storage.get_bookmarks_for_session(&self.session_id)?;
 
         if events.is_empty() {
             println!("No events found in the specified time range");
@@ -260,7 +409,7 @@ impl ReplayEngine {
         for (i, event) in events.iter().enumerate() {
             let delay = if i > 0 {
                 let time_diff = event.timestamp - last_timestamp;
-                let delay_ms = time_diff.num_milliseconds() as u64;
+                let delay_ms = (time_diff.num_milliseconds() - output_span_ms(event) as i64).max(0) as u64;
                 (delay_ms as f32 / speed) as u64
             } else {
                 0
@@ -271,18 +420,56 @@ impl ReplayEngine {
             }
 
             self.display_event(event, i + 1, events.len())?;
+
+            if let EventType::Command { output_chunks, .. } = &event.event_type {
+                if !output_chunks.is_empty() {
+                    let mut stdout = std::io::stdout();
+                    let mut previous_offset = 0;
+                    for chunk in output_chunks {
+                        let gap = chunk.offset_ms.saturating_sub(previous_offset);
+                        sleep(Duration::from_millis((gap as f32 / speed) as u64)).await;
+                        stdout.write_all(chunk.data.as_bytes())?;
+                        stdout.flush()?;
+                        previous_offset = chunk.offset_ms;
+                    }
+                    self.display_command_exit(&event.event_type)?;
+                }
+            }
+
+            // There are no replay controls here to resume with, so bookmarks
+            // are only highlighted
+            let marks: Vec<&Bookmark> = bookmarks.iter().filter(|b| b.event_id == event.id).collect();
+            if !marks.is_empty() {
+                self.display_bookmarks(&marks, false)?;
+            }
+
             last_timestamp = event.timestamp;
         }
 
         Ok(())
     }
 
+    /// Replay the last `count` commands of the session, including the
+    /// keystrokes that typed them
+    pub async fn replay_last_commands(&self, count: usize, speed: f32) -> crate::Result<()> {
+        let mut events = self.storage.get_events_for_session(&self.session_id)?;
+        events.sort_by_key(|e| e.timestamp);
+
+        let (Some(start), Some(last)) = (last_commands_start(&events, count), events.last()) else {
+            println!("No commands recorded yet in session: {}", self.session_id);
+            return Ok(());
+        };
+        self.replay_range(start, last.timestamp, speed).await
+    }
+
     pub fn get_session_summary(&self) -> crate::Result<ReplaySummary> {
         let events = self.storage.get_events_for_session(&self.session_id)?;
 
         let mut commands = 0;
         let mut key_presses = 0;
         let mut file_changes = 0;
+        let mut slowest_commands = Vec::new();
+        let mut commands_with_stderr = 0;
         let mut duration = chrono::Duration::zero();
 
         if let (Some(first), Some(last)) = (events.first(), events.last()) {
@@ -291,28 +478,150 @@ impl ReplayEngine {
 
         for event in &events {
             match &event.event_type {
-                EventType::Command { .. } => commands += 1,
+                EventType::Command {
+                    command,
+                    stderr,
+                    duration_ms,
+                    ..
+                } => {
+                    commands += 1;
+                    if let Some(duration_ms) = duration_ms {
+                        slowest_commands.push((command.clone(), *duration_ms));
+                    }
+                    if !stderr.trim().is_empty() {
+                        commands_with_stderr += 1;
+                    }
+                }
                 EventType::KeyPress { .. } => key_presses += 1,
                 EventType::FileChange { .. } => file_changes += 1,
                 _ => {}
             }
         }
 
+        slowest_commands.sort_by_key(|(_, duration_ms)| std::cmp::Reverse(*duration_ms));
+        slowest_commands.truncate(5);
+
         Ok(ReplaySummary {
             total_events: events.len(),
             commands,
             key_presses,
             file_changes,
+            slowest_commands,
+            commands_with_stderr,
             duration,
         })
     }
 }
 
+/// How long a command's recorded output took to arrive. The command event is
+/// stored when the command finishes, so its output started this long before.
+fn output_span_ms(event: &Event) -> u64 {
+    match &event.event_type {
+        EventType::Command { output_chunks, .. } => {
+            output_chunks.last().map(|chunk| chunk.offset_ms).unwrap_or(0)
+        }
+        _ => 0,
+    }
+}
+
+/// Wait at a bookmark until space is pressed, or there is no input to wait
+/// for. Returns false if the user asked to quit.
+async fn wait_for_resume(rx: &mut mpsc::UnboundedReceiver<CEvent>) -> bool {
+    while let Some(input) = rx.recv().await {
+        if let CEvent::Key(key) = input {
+            match key.code {
+                KeyCode::Char(' ') | KeyCode::Enter => return true,
+                KeyCode::Char('q') => return false,
+                _ => {}
+            }
+        }
+    }
+    true
+}
+
+/// Wait for `delay_ms` while handling the replay controls. Returns false if
+/// the user asked to quit.
+async fn wait_with_controls(
+    delay_ms: u64,
+    rx: &mut mpsc::UnboundedReceiver<CEvent>,
+    paused: &mut bool,
+    current_speed: &mut f32,
+) -> bool {
+    let start = Instant::now();
+    let deadline = start + Duration::from_millis(delay_ms);
+
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+
+        // Handle interactive input during delay
+        let mut input_event = None;
+
+        if *paused {
+            // If paused, we wait for input or a small timeout to emulate original behavior
+            // Original behavior: sleep 50ms, then check loop condition (which might exit if delay passed)
+            tokio::select! {
+                evt = rx.recv() => input_event = evt,
+                _ = sleep(Duration::from_millis(50)) => {}
+            }
+        } else {
+            // Wait for deadline or input
+            tokio::select! {
+                evt = rx.recv() => input_event = evt,
+                _ = sleep_until(deadline) => {}
+            }
+        }
+
+        if let Some(CEvent::Key(key)) = input_event {
+            match key.code {
+                KeyCode::Char(' ') => {
+                    *paused = !*paused;
+                }
+                KeyCode::Char('+') => {
+                    *current_speed *= 2.0;
+                }
+                KeyCode::Char('-') => {
+                    *current_speed = (*current_speed / 2.0).max(0.25);
+                }
+                KeyCode::Char('q') => return false,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Time of the first event after the command preceding the last `count`
+/// commands, or None if nothing has been run. `events` must be sorted by time.
+fn last_commands_start(
+    events: &[Event],
+    count: usize,
+) -> Option<chrono::DateTime<chrono::Utc>> {
+    let commands: Vec<usize> = events
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| matches!(e.event_type, EventType::Command { .. }))
+        .map(|(i, _)| i)
+        .collect();
+    if commands.is_empty() || count == 0 {
+        return None;
+    }
+    let first = match commands.len().checked_sub(count + 1) {
+        Some(previous) => commands[previous] + 1,
+        None => 0,
+    };
+    Some(events[first].timestamp)
+}
+
 #[derive(Debug)]
 pub struct ReplaySummary {
     pub total_events: usize,
     pub commands: usize,
     pub key_presses: usize,
     pub file_changes: usize,
+    /// Up to five longest-running commands with their duration in milliseconds
+    pub slowest_commands: Vec<(String, u64)>,
+    pub commands_with_stderr: usize,
     pub duration: chrono::Duration,
 }
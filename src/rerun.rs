@@ -0,0 +1,450 @@
+use crate::events::OutputStream;
+use crate::pty::terminal_output_to_text;
+use crate::shell::ShellSession;
+use crate::shell_adapter::ShellKind;
+use crate::workspace::Workspace;
+use crate::{EventType, Storage, TimeLoopError};
+use regex::Regex;
+use serde::Serialize;
+use similar::TextDiff;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How `Rerun::run` runs the recorded commands
+#[derive(Debug, Clone, Default)]
+pub struct RerunOptions {
+    pub shell: ShellKind,
+    /// The directory the session worked in, mapped onto the sandbox. Defaults
+    /// to the root of the session's baseline, else the deepest directory
+    /// containing every command's working directory.
+    pub root: Option<PathBuf>,
+    /// Commands still running after this long are killed, along with the
+    /// shell and what was set in it
+    pub timeout: Option<Duration>,
+}
+
+/// How a re-run command compares to its recording
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RerunStatus {
+    Same,
+    OutputChanged,
+    ExitCodeChanged,
+    TimedOut,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RerunResult {
+    pub command: String,
+    /// Where the command ran, inside the sandbox
+    pub working_directory: PathBuf,
+    pub status: RerunStatus,
+    pub recorded_exit_code: i32,
+    /// None when the command timed out or was killed by a signal
+    pub exit_code: Option<i32>,
+    /// Unified diff from the recorded output to the new one, both normalized
+    pub output_diff: Option<String>,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RerunReport {
+    pub session_id: String,
+    pub sandbox: PathBuf,
+    pub root: Option<PathBuf>,
+    /// Files checked out into the empty sandbox before the first command
+    pub checked_out: usize,
+    pub results: Vec<RerunResult>,
+}
+
+impl RerunReport {
+    /// Number of commands that didn't behave as recorded
+    pub fn changed(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|r| r.status != RerunStatus::Same)
+            .count()
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = format!("Rerun of session {} in {}\n", self.session_id, self.sandbox.display());
+        if self.checked_out > 0 {
+            out.push_str(&format!("Checked out {} recorded files first\n", self.checked_out));
+        }
+        for result in &self.results {
+            let status = match (result.status, result.exit_code) {
+                (RerunStatus::Same, _) => "✅ same".to_string(),
+                (RerunStatus::OutputChanged, _) => "⚠️  output changed".to_string(),
+                (RerunStatus::ExitCodeChanged, Some(code)) => {
+                    format!("❌ exit {} → {}", result.recorded_exit_code, code)
+                }
+                (RerunStatus::ExitCodeChanged, None) => {
+                    format!("❌ exit {} → killed", result.recorded_exit_code)
+                }
+                (RerunStatus::TimedOut, _) => "⏱️  timed out".to_string(),
+            };
+            out.push_str(&format!("{}: {}\n", status, result.command));
+            if let Some(diff) = &result.output_diff {
+                for line in diff.lines() {
+                    out.push_str(&format!("    {}\n", line));
+                }
+            }
+        }
+        out.push_str(&format!(
+            "{} commands, {} changed\n",
+            self.results.len(),
+            self.changed()
+        ));
+        out
+    }
+}
+
+/// Re-runs the commands of a recorded session in a sandbox directory and
+/// compares their exit codes and output to the recording
+pub struct Rerun {
+    storage: Storage,
+    session_id: String,
+}
+
+impl Rerun {
+    pub fn new(session_id: &str) -> crate::Result<Self> {
+        Ok(Self::with_storage(session_id, Storage::new()?))
+    }
+
+    pub fn with_storage(session_id: &str, storage: Storage) -> Self {
+        Self {
+            storage,
+            session_id: session_id.to_string(),
+        }
+    }
+
+    /// Run every recorded command in order, each in its recorded working
+    /// directory moved from the session's root into `sandbox`. An empty
+    /// sandbox is first filled with the files recorded before the first
+    /// command.
+    ///
+    /// The commands share one shell, as they did when recorded, so variables,
+    /// aliases and functions they set carry over. What the session's shell had
+    /// before its first command, such as variables exported by the user's
+    /// startup files, is not recorded and comes from the current environment
+    /// instead. If the shell can't be started, each command runs on its own.
+    pub async fn run(&self, sandbox: &Path, options: &RerunOptions) -> crate::Result<RerunReport> {
+        let mut events = self.storage.get_events_for_session(&self.session_id)?;
+        events.sort_by_key(|e| e.sequence_number);
+        let first_command = events
+            .iter()
+            .position(|e| matches!(e.event_type, EventType::Command { .. }))
+            .ok_or_else(|| {
+                TimeLoopError::Replay(format!("Session {} has no commands to rerun", self.session_id))
+            })?;
+
+        let root = options.root.clone().or_else(|| {
+            events.iter().find_map(|e| match &e.event_type {
+                EventType::Baseline { root, .. } => Some(PathBuf::from(root)),
+                _ => None,
+            })
+        });
+        let root = root.or_else(|| {
+            common_dir(events.iter().filter_map(|e| match &e.event_type {
+                EventType::Command {
+                    working_directory, ..
+                } if !working_directory.is_empty() => Some(Path::new(working_directory)),
+                _ => None,
+            }))
+        });
+
+        let sandbox_is_empty = !sandbox.exists() || std::fs::read_dir(sandbox)?.next().is_none();
+        let checked_out = match (first_command, &root) {
+            (1.., Some(root)) if sandbox_is_empty => {
+                let workspace = Workspace::with_storage(&self.session_id, self.storage.clone());
+                let at = &events[first_command - 1].id;
+                workspace.checkout(at, sandbox, Some(root))?.written.len()
+            }
+            _ => {
+                std::fs::create_dir_all(sandbox)?;
+                0
+            }
+        };
+        let sandbox = sandbox.canonicalize()?;
+        let normalizer = Normalizer::new(root.as_deref(), &sandbox);
+        let mut shell = ShellSession::start(&sandbox.to_string_lossy(), options.shell).await.ok();
+
+        let mut results = Vec::new();
+        for event in &events[first_command..] {
+            let EventType::Command {
+                command,
+                output,
+                stderr,
+                exit_code,
+                working_directory,
+                ..
+            } = &event.event_type
+            else {
+                continue;
+            };
+            let working_directory = match &root {
+                Some(root) => match Path::new(working_directory).strip_prefix(root) {
+                    Ok(rest) if !rest.as_os_str().is_empty() => sandbox.join(rest),
+                    _ => sandbox.clone(),
+                },
+                None => sandbox.clone(),
+            };
+
+            // It existed when the command was recorded; directories without
+            // files in them aren't recorded
+            std::fs::create_dir_all(&working_directory)?;
+            let started = std::time::Instant::now();
+            let finished = match shell.as_mut() {
+                Some(session) => {
+                    let finished = run_in_shell(session, command, &working_directory, options.timeout).await;
+                    if !finished.as_ref().is_some_and(|f| f.is_ok()) || !session.is_alive() {
+                        // Later commands get a fresh shell, without what was set
+                        // in this one
+                        session.kill();
+                        shell = ShellSession::start(&sandbox.to_string_lossy(), options.shell).await.ok();
+                    }
+                    finished
+                }
+                None => run_one_shot(command, &working_directory, options).await,
+            };
+            let duration_ms = started.elapsed().as_millis() as u64;
+
+            let mut result = RerunResult {
+                command: command.clone(),
+                working_directory,
+                status: RerunStatus::TimedOut,
+                recorded_exit_code: *exit_code,
+                exit_code: None,
+                output_diff: None,
+                duration_ms,
+            };
+            if let Some(finished) = finished {
+                // A missing shell fails like a missing command would
+                let (new_stdout, new_stderr, new_code) = match finished {
+                    Ok(output) => output,
+                    Err(e) => (String::new(), e.to_string(), Some(127)),
+                };
+                let recorded = (normalizer.apply(output), normalizer.apply(stderr));
+                let new = (normalizer.apply(&new_stdout), normalizer.apply(&new_stderr));
+                result.exit_code = new_code;
+                result.status = if new_code != Some(*exit_code) {
+                    RerunStatus::ExitCodeChanged
+                } else if outputs_match(&recorded, &new) {
+                    RerunStatus::Same
+                } else {
+                    RerunStatus::OutputChanged
+                };
+                if !outputs_match(&recorded, &new) {
+                    let old = join_output(&recorded.0, &recorded.1);
+                    let new = join_output(&new.0, &new.1);
+                    let diff = TextDiff::from_lines(&old, &new)
+                        .unified_diff()
+                        .header("recorded", "rerun")
+                        .to_string();
+                    result.output_diff = Some(diff);
+                }
+            }
+            results.push(result);
+        }
+
+        if let Some(shell) = shell.as_mut() {
+            shell.kill();
+        }
+        Ok(RerunReport {
+            session_id: self.session_id.clone(),
+            sandbox,
+            root,
+            checked_out,
+            results,
+        })
+    }
+}
+
+/// Stdout, stderr and exit code of a command that finished, or None if it
+/// timed out
+type Finished = Option<crate::Result<(String, String, Option<i32>)>>;
+
+/// Run `command` in the session's shell, in `dir`
+async fn run_in_shell(shell: &mut ShellSession, command: &str, dir: &Path, timeout: Option<Duration>) -> Finished {
+    let dir = dir.to_string_lossy();
+    if shell.working_directory() != dir {
+        let cd = shell.kind().adapter().change_directory_command(&dir);
+        if let Err(e) = shell.execute(&cd, None, &mut |_, _| {}).await {
+            return Some(Err(e));
+        }
+    }
+    let mut ignore = |_: OutputStream, _: &[u8]| {};
+    let run = shell.execute(command, None, &mut ignore);
+    let result = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, run).await.ok()?,
+        None => run.await,
+    };
+    Some(result.map(|r| {
+        (
+            terminal_output_to_text(&r.output),
+            terminal_output_to_text(&r.stderr),
+            Some(r.exit_code),
+        )
+    }))
+}
+
+/// Run `command` in a shell of its own, in `dir`
+async fn run_one_shot(command: &str, dir: &Path, options: &RerunOptions) -> Finished {
+    let (program, args) = options.shell.adapter().one_shot_command(command);
+    let mut child = tokio::process::Command::new(program);
+    child
+        .args(&args)
+        .current_dir(dir)
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true);
+    let finished = match options.timeout {
+        Some(timeout) => tokio::time::timeout(timeout, child.output()).await.ok()?,
+        None => child.output().await,
+    };
+    Some(
+        finished
+            .map(|out| {
+                (
+                    String::from_utf8_lossy(&out.stdout).into_owned(),
+                    String::from_utf8_lossy(&out.stderr).into_owned(),
+                    out.status.code(),
+                )
+            })
+            .map_err(TimeLoopError::from),
+    )
+}
+
+/// Output recorded in a terminal has stdout and stderr interleaved in one
+/// stream, so it also matches the two new streams joined either way
+fn outputs_match(recorded: &(String, String), new: &(String, String)) -> bool {
+    recorded == new
+        || (recorded.1.is_empty()
+            && (recorded.0 == join_output(&new.0, &new.1) || recorded.0 == join_output(&new.1, &new.0)))
+}
+
+fn join_output(first: &str, second: &str) -> String {
+    match (first.is_empty(), second.is_empty()) {
+        (_, true) => first.to_string(),
+        (true, false) => second.to_string(),
+        (false, false) => format!("{}\n{}", first, second),
+    }
+}
+
+/// The deepest directory containing every path
+fn common_dir<'a>(mut paths: impl Iterator<Item = &'a Path>) -> Option<PathBuf> {
+    let mut root = paths.next()?.to_path_buf();
+    for path in paths {
+        while !path.starts_with(&root) {
+            root = root.parent()?.to_path_buf();
+        }
+    }
+    Some(root)
+}
+
+/// Masks what differs between runs of the same command: the directory it ran
+/// in, timestamps and durations, line endings and trailing whitespace
+struct Normalizer {
+    paths: Vec<String>,
+    patterns: Vec<(Regex, &'static str)>,
+}
+
+impl Normalizer {
+    fn new(root: Option<&Path>, sandbox: &Path) -> Self {
+        let mut paths: Vec<String> = root
+            .into_iter()
+            .chain(std::iter::once(sandbox))
+            .map(|p| p.to_string_lossy().to_string())
+            .filter(|p| p.len() > 1)
+            .collect();
+        // Longest first, in case one contains the other
+        paths.sort_by_key(|p| std::cmp::Reverse(p.len()));
+        let patterns = [
+            (
+                r"\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}(\.\d+)?(Z|[+-]\d{2}:?\d{2})?",
+                "<TIMESTAMP>",
+            ),
+            (r"\b\d{1,2}:\d{2}:\d{2}(\.\d+)?\b", "<TIME>"),
+            (r"\b\d+(\.\d+)?\s?(ns|µs|us|ms|s|secs?|seconds?)\b", "<DURATION>"),
+        ]
+        .into_iter()
+        .filter_map(|(p, r)| Regex::new(p).ok().map(|re| (re, r)))
+        .collect();
+        Self { paths, patterns }
+    }
+
+    fn apply(&self, text: &str) -> String {
+        let mut text = text.replace("\r\n", "\n");
+        for path in &self.paths {
+            text = text.replace(path.as_str(), "<ROOT>");
+        }
+        for (re, replacement) in &self.patterns {
+            text = re.replace_all(&text, *replacement).into_owned();
+        }
+        let lines: Vec<&str> = text.lines().map(str::trim_end).collect();
+        lines.join("\n").trim_end().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EventRecorder;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_normalizer_masks_paths_and_times() {
+        let normalizer = Normalizer::new(Some(Path::new("/home/me/project")), Path::new("/tmp/sandbox"));
+        assert_eq!(
+            normalizer.apply("built /home/me/project/app at 2024-05-01T10:00:00Z in 1.52s  \r\n"),
+            normalizer.apply("built /tmp/sandbox/app at 2026-10-16 23:14:51 in 0.3s\n"),
+        );
+        assert_ne!(normalizer.apply("3 passed"), normalizer.apply("2 passed"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_rerun_compares_with_recording() {
+        let tmp_dir = TempDir::new().unwrap();
+        let db_path = tmp_dir.path().join("events_rerun.db");
+        let storage = Storage::with_path(db_path.to_str().unwrap()).unwrap();
+        let mut recorder = EventRecorder::with_storage("rerun-test", storage.clone());
+
+        let work = tmp_dir.path().join("work");
+        std::fs::create_dir_all(work.join("sub")).unwrap();
+        let input = work.join("input.txt");
+        std::fs::write(&input, "hello\n").unwrap();
+        recorder.record_baseline(&work, std::slice::from_ref(&input)).unwrap();
+        let cwd = work.to_str().unwrap();
+        let sub = work.join("sub");
+        recorder.record_command("cat input.txt", "hello\n", 0, cwd).unwrap();
+        recorder.record_command("pwd", &format!("{}\n", sub.display()), 0, sub.to_str().unwrap()).unwrap();
+        recorder.record_command("echo 2", "1\n", 0, cwd).unwrap();
+        recorder.record_command("false", "", 0, cwd).unwrap();
+        // Set by one command, used by the next, as in the recorded shell
+        recorder.record_command("export GREETING=hi", "", 0, cwd).unwrap();
+        recorder.record_command("echo $GREETING", "hi\n", 0, cwd).unwrap();
+
+        let sandbox = tmp_dir.path().join("sandbox");
+        let options = RerunOptions {
+            shell: ShellKind::Sh,
+            ..RerunOptions::default()
+        };
+        let report = Rerun::with_storage("rerun-test", storage).run(&sandbox, &options).await.unwrap();
+        assert_eq!(report.checked_out, 1);
+        let statuses: Vec<_> = report.results.iter().map(|r| r.status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                RerunStatus::Same,
+                RerunStatus::Same,
+                RerunStatus::OutputChanged,
+                RerunStatus::ExitCodeChanged,
+                RerunStatus::Same,
+                RerunStatus::Same
+            ]
+        );
+        assert!(report.results[2].output_diff.as_ref().unwrap().contains("+2"));
+        assert_eq!(report.changed(), 2);
+        assert!(report.to_text().contains("❌ exit 0 → 1: false"));
+    }
+}
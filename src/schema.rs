@@ -0,0 +1,226 @@
+//! Versioning of the data `Storage` keeps on disk.
+//!
+//! Snapshots (`state.json` and its CBOR and encrypted forms) and session
+//! exports carry a `schema_version` field, and append-only event logs start
+//! with a `LogHeader` record. Data without either was written before versions
+//! were recorded and is version 0. Older data is upgraded as it is loaded, one
+//! version at a time, on its untyped form and before it is deserialized.
+
+use crate::TimeLoopError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// The version this build writes
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// The first record of an append-only event log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct LogHeader {
+    pub schema_version: u32,
+}
+
+impl LogHeader {
+    pub(crate) fn current() -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+        }
+    }
+}
+
+/// Upgrades stored data from one version to the next
+pub(crate) struct Migration {
+    /// Applied to every event, wherever it is stored
+    pub event: fn(&mut Value),
+    /// Applied to every session, in snapshots and exports
+    pub session: fn(&mut Value),
+    /// Applied to a whole snapshot after its events and sessions, for
+    /// anything else in it such as branches and bookmarks
+    pub snapshot: fn(&mut Value),
+}
+
+/// `MIGRATIONS[n]` upgrades version `n` to `n + 1`
+const MIGRATIONS: &[Migration] = &[
+    // Version 0 is everything written before versions were recorded. The fields
+    // added since then all have defaults, so nothing needs rewriting.
+    Migration {
+        event: |_| {},
+        session: |_| {},
+        snapshot: |_| {},
+    },
+    // Version 2 adds `EventType::Custom`, which older builds can't read
+    Migration {
+        event: |_| {},
+        session: |_| {},
+        snapshot: |_| {},
+    },
+];
+
+const _: () = assert!(MIGRATIONS.len() == SCHEMA_VERSION as usize);
+
+/// Fail for data written by a newer version, which this build can't read and
+/// mustn't overwrite
+pub(crate) fn check_version(version: u32) -> crate::Result<()> {
+    check_version_against(version, SCHEMA_VERSION)
+}
+
+/// Upgrade one event stored at version `from`
+pub(crate) fn migrate_event(event: &mut Value, from: u32) -> crate::Result<()> {
+    migrate_event_with(event, from, MIGRATIONS)
+}
+
+/// Upgrade a snapshot of the whole store from the version it records
+pub(crate) fn migrate_snapshot(snapshot: &mut Value) -> crate::Result<()> {
+    migrate_snapshot_with(snapshot, MIGRATIONS)
+}
+
+/// Upgrade a session export from the version it records
+pub(crate) fn migrate_export(export: &mut Value) -> crate::Result<()> {
+    migrate_export_with(export, MIGRATIONS)
+}
+
+fn check_version_against(version: u32, supported: u32) -> crate::Result<()> {
+    if version > supported {
+        return Err(TimeLoopError::Schema(format!(
+            "data has schema version {} but this build only reads up to {}; upgrade timeloop-terminal",
+            version, supported
+        )));
+    }
+    Ok(())
+}
+
+/// The `schema_version` of a snapshot or export, 0 when there is none
+fn version_of(value: &Value) -> crate::Result<u32> {
+    match value.get("schema_version") {
+        None | Some(Value::Null) => Ok(0),
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| TimeLoopError::Schema(format!("invalid schema version {}", v))),
+    }
+}
+
+fn set_version(value: &mut Value, version: u32) {
+    if let Value::Object(map) = value {
+        map.insert("schema_version".to_string(), Value::from(version));
+    }
+}
+
+fn migrate_event_with(event: &mut Value, from: u32, migrations: &[Migration]) -> crate::Result<()> {
+    check_version_against(from, migrations.len() as u32)?;
+    for step in &migrations[from as usize..] {
+        (step.event)(event);
+    }
+    Ok(())
+}
+
+fn migrate_snapshot_with(snapshot: &mut Value, migrations: &[Migration]) -> crate::Result<()> {
+    let from = version_of(snapshot)?;
+    check_version_against(from, migrations.len() as u32)?;
+    for step in &migrations[from as usize..] {
+        // session_id -> events
+        if let Some(Value::Object(sessions)) = snapshot.get_mut("events") {
+            for events in sessions.values_mut() {
+                if let Value::Array(events) = events {
+                    events.iter_mut().for_each(step.event);
+                }
+            }
+        }
+        if let Some(Value::Object(sessions)) = snapshot.get_mut("sessions") {
+            sessions.values_mut().for_each(step.session);
+        }
+        (step.snapshot)(snapshot);
+    }
+    set_version(snapshot, migrations.len() as u32);
+    Ok(())
+}
+
+fn migrate_export_with(export: &mut Value, migrations: &[Migration]) -> crate::Result<()> {
+    let from = version_of(export)?;
+    check_version_against(from, migrations.len() as u32)?;
+    for step in &migrations[from as usize..] {
+        if let Some(Value::Array(events)) = export.get_mut("events") {
+            events.iter_mut().for_each(step.event);
+        }
+        if let Some(session) = export.get_mut("session") {
+            (step.session)(session);
+        }
+    }
+    set_version(export, migrations.len() as u32);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // Two made-up versions after the legacy one: version 2 renames a field of
+    // `Command` and gives sessions tags, version 3 turns bookmark notes into lists
+    const TEST_MIGRATIONS: &[Migration] = &[
+        Migration {
+            event: |_| {},
+            session: |_| {},
+            snapshot: |_| {},
+        },
+        Migration {
+            event: |event| {
+                if let Some(command) = event.pointer_mut("/event_type/Command") {
+                    let output = command["output"].take();
+                    command["stdout"] = output;
+                    command.as_object_mut().unwrap().remove("output");
+                }
+            },
+            session: |session| session["tags"] = json!([]),
+            snapshot: |_| {},
+        },
+        Migration {
+            event: |_| {},
+            session: |_| {},
+            snapshot: |snapshot| {
+                for bookmark in snapshot["bookmarks"].as_object_mut().unwrap().values_mut() {
+                    let note = bookmark["note"].take();
+                    bookmark["notes"] = json!([note]);
+                }
+            },
+        },
+    ];
+
+    #[test]
+    fn test_migrations_run_in_order_from_recorded_version() {
+        let mut snapshot = json!({
+            "events": {"s1": [{"event_type": {"Command": {"output": "hi"}}}]},
+            "sessions": {"s1": {"id": "s1"}},
+            "bookmarks": {"b1": {"note": "look"}},
+        });
+        migrate_snapshot_with(&mut snapshot, TEST_MIGRATIONS).unwrap();
+        assert_eq!(snapshot["schema_version"], 3);
+        assert_eq!(snapshot["events"]["s1"][0]["event_type"]["Command"], json!({"stdout": "hi"}));
+        assert_eq!(snapshot["sessions"]["s1"]["tags"], json!([]));
+        assert_eq!(snapshot["bookmarks"]["b1"]["notes"], json!(["look"]));
+
+        // Steps at or below the recorded version are skipped
+        let mut current = json!({"schema_version": 2, "bookmarks": {"b1": {"note": "x"}}});
+        migrate_snapshot_with(&mut current, TEST_MIGRATIONS).unwrap();
+        assert_eq!(current["bookmarks"]["b1"]["notes"], json!(["x"]));
+        let mut event = json!({"event_type": {"Command": {"output": "hi"}}});
+        migrate_event_with(&mut event, 2, TEST_MIGRATIONS).unwrap();
+        assert_eq!(event["event_type"]["Command"]["output"], "hi");
+
+        let mut export = json!({"session": {"id": "s1"}, "events": []});
+        migrate_export_with(&mut export, TEST_MIGRATIONS).unwrap();
+        assert_eq!(export["session"]["tags"], json!([]));
+    }
+
+    #[test]
+    fn test_newer_or_invalid_versions_are_rejected() {
+        let mut newer = json!({"schema_version": SCHEMA_VERSION + 1, "events": {}});
+        assert!(matches!(migrate_snapshot(&mut newer), Err(TimeLoopError::Schema(_))));
+        assert!(migrate_event(&mut json!({}), SCHEMA_VERSION + 1).is_err());
+        assert!(migrate_snapshot(&mut json!({"schema_version": "one"})).is_err());
+
+        let mut legacy = json!({"events": {}, "sessions": {}, "branches": {}});
+        migrate_snapshot(&mut legacy).unwrap();
+        assert_eq!(legacy["schema_version"], SCHEMA_VERSION);
+    }
+}
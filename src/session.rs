@@ -1,19 +1,58 @@
 use crate::{EventType, Storage, TimeLoopError};
 use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use uuid::Uuid;
 use zeroize::Zeroize;
 
-#[derive(Debug, Clone, Serialize, Deserialize, Zeroize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Session {
     pub id: String,
     pub name: String,
-    #[zeroize(skip)]
     pub created_at: DateTime<Utc>,
-    #[zeroize(skip)]
     pub ended_at: Option<DateTime<Utc>>,
     pub parent_session_id: Option<String>,
     pub branch_name: Option<String>,
+    /// Free-form labels such as a ticket or project, in the order added
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// Changes to a session's details; fields left as None or empty are kept
+#[derive(Debug, Clone, Default)]
+pub struct SessionEdit {
+    pub name: Option<String>,
+    /// `Some(None)` removes the description
+    pub description: Option<Option<String>>,
+    pub set_metadata: Vec<(String, String)>,
+    pub unset_metadata: Vec<String>,
+}
+
+impl Session {
+    /// Whether the session has every one of `tags`
+    pub fn has_tags(&self, tags: &[String]) -> bool {
+        tags.iter().all(|tag| self.tags.contains(tag))
+    }
+}
+
+// By hand because zeroize has no impl for maps
+impl Zeroize for Session {
+    fn zeroize(&mut self) {
+        self.id.zeroize();
+        self.name.zeroize();
+        self.parent_session_id.zeroize();
+        self.branch_name.zeroize();
+        self.tags.zeroize();
+        self.description.zeroize();
+        for (mut key, mut value) in std::mem::take(&mut self.metadata) {
+            key.zeroize();
+            value.zeroize();
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,10 +63,46 @@ pub struct SessionSummary {
     pub commands_executed: usize,
     pub files_modified: usize,
     pub last_command: String,
+    /// Up to five longest-running commands with their duration in milliseconds
+    pub slowest_commands: Vec<(String, u64)>,
+    /// Commands that wrote to stderr, with the first line they wrote
+    pub commands_with_stderr: Vec<(String, String)>,
+    /// Commands terminated by a signal, with the signal number
+    pub commands_killed_by_signal: Vec<(String, i32)>,
     pub created_at: DateTime<Utc>,
     pub ended_at: Option<DateTime<Utc>>,
 }
 
+/// The summary's details, one line each, as shown by `timeloop summary`
+impl std::fmt::Display for SessionSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "⏱️  Duration: {}", self.duration)?;
+        writeln!(f, "⌨️  Commands executed: {}", self.commands_executed)?;
+        writeln!(f, "📁 Files modified: {}", self.files_modified)?;
+        writeln!(f, "🎯 Last command: {}", self.last_command)?;
+
+        if !self.slowest_commands.is_empty() {
+            writeln!(f, "🐢 Slowest commands:")?;
+            for (command, duration_ms) in &self.slowest_commands {
+                writeln!(f, "   {:>8.2}s  {}", *duration_ms as f64 / 1000.0, command)?;
+            }
+        }
+        if !self.commands_with_stderr.is_empty() {
+            writeln!(f, "⚠️  Commands with error output: {}", self.commands_with_stderr.len())?;
+            for (command, first_line) in &self.commands_with_stderr {
+                writeln!(f, "   {}  →  {}", command, first_line)?;
+            }
+        }
+        if !self.commands_killed_by_signal.is_empty() {
+            writeln!(f, "🛑 Commands terminated by a signal: {}", self.commands_killed_by_signal.len())?;
+            for (command, signal) in &self.commands_killed_by_signal {
+                writeln!(f, "   {}  →  {}", command, crate::process::signal_name(*signal))?;
+            }
+        }
+        Ok(())
+    }
+}
+
 pub struct SessionManager {
     storage: Storage,
 }
@@ -51,6 +126,9 @@ impl SessionManager {
             ended_at: None,
             parent_session_id: None,
             branch_name: None,
+            tags: Vec::new(),
+            description: None,
+            metadata: BTreeMap::new(),
         };
 
         self.storage.store_session(&session)?;
@@ -75,6 +153,9 @@ impl SessionManager {
             ended_at: None,
             parent_session_id: Some(parent_session_id.to_string()),
             branch_name: Some(branch_name.to_string()),
+            tags: parent_session.tags.clone(),
+            description: None,
+            metadata: BTreeMap::new(),
         };
 
         self.storage.store_session(&branch_session)?;
@@ -97,6 +178,92 @@ impl SessionManager {
         self.storage.list_sessions()
     }
 
+    /// The session with ID `id_or_name`, else the most recent one named so
+    pub fn find_by_id_or_name(&self, id_or_name: &str) -> crate::Result<Option<Session>> {
+        if let Some(session) = self.get_session(id_or_name)? {
+            return Ok(Some(session));
+        }
+        Ok(self
+            .list_sessions()?
+            .into_iter()
+            .filter(|s| s.name == id_or_name)
+            .max_by_key(|s| s.created_at))
+    }
+
+    /// Sessions that have all of `tags`, oldest first
+    pub fn list_sessions_with_tags(&self, tags: &[String]) -> crate::Result<Vec<Session>> {
+        let mut sessions = self.list_sessions()?;
+        sessions.retain(|s| s.has_tags(tags));
+        Ok(sessions)
+    }
+
+    /// Apply `edit` to a session, all of it or, if any of it is invalid, none
+    pub fn edit_session(&mut self, session_id: &str, edit: &SessionEdit) -> crate::Result<Session> {
+        if edit.name.as_deref().is_some_and(|n| n.trim().is_empty()) {
+            return Err(TimeLoopError::InvalidSessionData(
+                "Session name can't be empty".to_string(),
+            ));
+        }
+        let keys = edit.set_metadata.iter().map(|(k, _)| k).chain(&edit.unset_metadata);
+        for key in keys {
+            if key.is_empty() || key.contains(|c: char| c == '=' || c.is_whitespace()) {
+                return Err(TimeLoopError::InvalidSessionData(format!(
+                    "Invalid metadata key '{}': it must be one word without '='",
+                    key
+                )));
+            }
+        }
+        self.update_session(session_id, |session| {
+            if let Some(name) = &edit.name {
+                session.name = name.clone();
+            }
+            if let Some(description) = &edit.description {
+                session.description = description.clone();
+            }
+            for (key, value) in &edit.set_metadata {
+                session.metadata.insert(key.clone(), value.clone());
+            }
+            for key in &edit.unset_metadata {
+                session.metadata.remove(key);
+            }
+        })
+    }
+
+    /// Add tags the session doesn't have yet. Tags are one word each.
+    pub fn add_tags(&mut self, session_id: &str, tags: &[String]) -> crate::Result<Session> {
+        let invalid = |t: &&String| t.is_empty() || t.contains(|c: char| c == ',' || c.is_whitespace());
+        if let Some(tag) = tags.iter().find(invalid) {
+            return Err(TimeLoopError::InvalidSessionData(format!(
+                "Invalid tag '{}': it must be one word without commas",
+                tag
+            )));
+        }
+        self.update_session(session_id, |session| {
+            for tag in tags {
+                if !session.tags.contains(tag) {
+                    session.tags.push(tag.clone());
+                }
+            }
+        })
+    }
+
+    pub fn remove_tags(&mut self, session_id: &str, tags: &[String]) -> crate::Result<Session> {
+        self.update_session(session_id, |session| session.tags.retain(|t| !tags.contains(t)))
+    }
+
+    fn update_session(
+        &mut self,
+        session_id: &str,
+        update: impl FnOnce(&mut Session),
+    ) -> crate::Result<Session> {
+        let mut session = self
+            .get_session(session_id)?
+            .ok_or_else(|| TimeLoopError::SessionNotFound(session_id.to_string()))?;
+        update(&mut session);
+        self.storage.store_session(&session)?;
+        Ok(session)
+    }
+
     pub fn get_session_summary(&self, session_id: &str) -> crate::Result<SessionSummary> {
         let session = self
             .get_session(session_id)?
@@ -107,12 +274,30 @@ impl SessionManager {
         let mut commands_executed = 0;
         let mut files_modified = 0;
         let mut last_command = String::new();
+        let mut slowest_commands = Vec::new();
+        let mut commands_with_stderr = Vec::new();
+        let mut commands_killed_by_signal = Vec::new();
 
         for event in &events {
             match &event.event_type {
-                EventType::Command { command, .. } => {
+                EventType::Command {
+                    command,
+                    stderr,
+                    duration_ms,
+                    signal,
+                    ..
+                } => {
                     commands_executed += 1;
                     last_command = command.clone();
+                    if let Some(signal) = signal {
+                        commands_killed_by_signal.push((command.clone(), *signal));
+                    }
+                    if let Some(duration_ms) = duration_ms {
+                        slowest_commands.push((command.clone(), *duration_ms));
+                    }
+                    if let Some(first_line) = stderr.lines().find(|l| !l.trim().is_empty()) {
+                        commands_with_stderr.push((command.clone(), first_line.to_string()));
+                    }
                 }
                 EventType::FileChange { .. } => {
                     files_modified += 1;
@@ -121,6 +306,9 @@ impl SessionManager {
             }
         }
 
+        slowest_commands.sort_by_key(|(_, duration_ms)| std::cmp::Reverse(*duration_ms));
+        slowest_commands.truncate(5);
+
         let duration = if let Some(ended_at) = session.ended_at {
             ended_at - session.created_at
         } else {
@@ -134,6 +322,9 @@ impl SessionManager {
             commands_executed,
             files_modified,
             last_command,
+            slowest_commands,
+            commands_with_stderr,
+            commands_killed_by_signal,
             created_at: session.created_at,
             ended_at: session.ended_at,
         })
@@ -227,4 +418,125 @@ mod tests {
             assert_eq!(key, "a");
         }
     }
+
+    #[test]
+    fn test_summary_reports_slow_commands_and_stderr() {
+        let tmp_dir = TempDir::new().unwrap();
+        let db_path = tmp_dir.path().join("test_summary.db");
+        let storage = Storage::with_path(db_path.to_str().unwrap()).unwrap();
+        let mut session_manager = SessionManager::with_storage(storage);
+        let session_id = session_manager.create_session("Test Summary").unwrap();
+        let mut recorder = crate::events::EventRecorder::with_storage_and_redaction(
+            &session_id,
+            session_manager.storage.clone(),
+            false,
+            None,
+        );
+
+        let now = Utc::now();
+        for (command, stderr, seconds) in [("make", "", 90), ("ls /nope", "ls: cannot access '/nope'\n", 0)] {
+            recorder
+                .record_command_execution(crate::events::CommandRecord {
+                    command: command.to_string(),
+                    stdout: String::new(),
+                    stderr: stderr.to_string(),
+                    output_chunks: Vec::new(),
+                    exit_code: 0,
+                    signal: None,
+                    core_dumped: false,
+                    resource_usage: None,
+                    working_directory: "/tmp".to_string(),
+                    environment: None,
+                    started_at: now - chrono::Duration::seconds(seconds),
+                    ended_at: now,
+                })
+                .unwrap();
+        }
+
+        let summary = session_manager.get_session_summary(&session_id).unwrap();
+        assert_eq!(summary.commands_executed, 2);
+        assert_eq!(summary.slowest_commands[0], ("make".to_string(), 90_000));
+        assert_eq!(
+            summary.commands_with_stderr,
+            vec![("ls /nope".to_string(), "ls: cannot access '/nope'".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_find_by_id_or_name() {
+        let tmp_dir = TempDir::new().unwrap();
+        let db_path = tmp_dir.path().join("test_find.db");
+        let storage = Storage::with_path(db_path.to_str().unwrap()).unwrap();
+        let mut session_manager = SessionManager::with_storage(storage);
+        let older = session_manager.create_session("ci").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let newer = session_manager.create_session("ci").unwrap();
+
+        let find = |s: &str| session_manager.find_by_id_or_name(s).unwrap().map(|s| s.id);
+        assert_eq!(find(&older), Some(older.clone()));
+        assert_eq!(find("ci"), Some(newer));
+        assert_eq!(find("nightly"), None);
+    }
+
+    #[test]
+    fn test_tags_description_and_metadata() {
+        let tmp_dir = TempDir::new().unwrap();
+        let db_path = tmp_dir.path().join("test_tags.db");
+        let storage = Storage::with_path(db_path.to_str().unwrap()).unwrap();
+        let mut session_manager = SessionManager::with_storage(storage.clone());
+        let first = session_manager.create_session("first").unwrap();
+        let second = session_manager.create_session("second").unwrap();
+
+        let tags = |t: &[&str]| t.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        session_manager.add_tags(&first, &tags(&["PROJ-123", "backend"])).unwrap();
+        session_manager.add_tags(&second, &tags(&["backend", "backend"])).unwrap();
+        assert!(session_manager.add_tags(&second, &tags(&["two words"])).is_err());
+        assert_eq!(session_manager.get_session(&second).unwrap().unwrap().tags, tags(&["backend"]));
+
+        let lister = SessionManager::with_storage(storage.clone());
+        let ids = |t: &[&str]| -> Vec<String> {
+            lister
+                .list_sessions_with_tags(&tags(t))
+                .unwrap()
+                .into_iter()
+                .map(|s| s.id)
+                .collect()
+        };
+        assert_eq!(ids(&["backend"]), vec![first.clone(), second.clone()]);
+        assert_eq!(ids(&["backend", "PROJ-123"]), vec![first.clone()]);
+        assert_eq!(ids(&[]).len(), 2);
+
+        session_manager.remove_tags(&first, &tags(&["PROJ-123"])).unwrap();
+        assert_eq!(ids(&["PROJ-123"]), Vec::<String>::new());
+
+        let edit = SessionEdit {
+            name: Some("renamed".to_string()),
+            description: Some(Some("flaky test hunt".to_string())),
+            set_metadata: vec![("ticket".to_string(), "PROJ-9".to_string())],
+            ..Default::default()
+        };
+        session_manager.edit_session(&first, &edit).unwrap();
+        // An invalid edit changes nothing
+        let bad = SessionEdit {
+            name: Some("again".to_string()),
+            unset_metadata: vec!["a=b".to_string()],
+            ..Default::default()
+        };
+        assert!(session_manager.edit_session(&first, &bad).is_err());
+
+        let reopened = Storage::with_path(db_path.to_str().unwrap()).unwrap();
+        let session = reopened.get_session(&first).unwrap().unwrap();
+        assert_eq!(session.name, "renamed");
+        assert_eq!(session.description.as_deref(), Some("flaky test hunt"));
+        assert_eq!(session.metadata["ticket"], "PROJ-9");
+        assert_eq!(session.tags, tags(&["backend"]));
+
+        let clear = SessionEdit {
+            description: Some(None),
+            unset_metadata: vec!["ticket".to_string()],
+            ..Default::default()
+        };
+        let session = session_manager.edit_session(&first, &clear).unwrap();
+        assert!(session.description.is_none() && session.metadata.is_empty());
+    }
 }
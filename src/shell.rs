@@ -0,0 +1,695 @@
+use crate::events::{OutputChunk, OutputChunkCollector, OutputStream, ResourceUsage};
+use crate::process::{signal_from_shell_status, ChildUsageTracker, ProcessExit};
+#[cfg(unix)]
+use crate::pty::{CapturePty, SignalForwarder};
+use crate::pty::{PtyInput, PtyProcess};
+use crate::shell_adapter::{ShellAdapter, ShellKind};
+use crate::TimeLoopError;
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// How long to wait for a freshly spawned shell to finish its startup files
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for the stderr end marker once the command has finished
+const STDERR_GRACE: Duration = Duration::from_millis(500);
+
+/// Variables the shell keeps up to date itself, which aren't worth recording
+const SHELL_MANAGED_VARIABLES: &[&str] = &["PWD", "OLDPWD", "_", "SHLVL"];
+
+/// Result of running one command in a `ShellSession`
+#[derive(Debug, Clone)]
+pub struct ShellCommandResult {
+    /// Raw terminal output the command wrote to stdout
+    pub output: Vec<u8>,
+    /// Raw terminal output the command wrote to stderr. Empty when stderr
+    /// cannot be captured separately, in which case it is part of `output`.
+    pub stderr: Vec<u8>,
+    /// Both streams split into chunks timed from the start of the command
+    pub output_chunks: Vec<OutputChunk>,
+    pub exit_code: i32,
+    /// Signal that killed the command. Inferred from the shell's exit status
+    /// (128 + N), so a command that explicitly exits with such a status is
+    /// reported the same way.
+    pub signal: Option<i32>,
+    pub core_dumped: bool,
+    /// CPU time and sampled peak memory of the command's processes
+    pub resource_usage: Option<ResourceUsage>,
+    /// The shell's working directory after the command finished
+    pub working_directory: String,
+    /// Exported variables set or changed since the shell started, as
+    /// `NAME=value`, or None if the shell can't report them
+    pub environment: Option<Vec<String>>,
+    /// Interrupt, quit and suspend signals the user sent while the command ran
+    pub interrupts: Vec<(i32, DateTime<Utc>)>,
+    /// True if the shell itself exited while running the command
+    pub shell_exited: bool,
+}
+
+/// A long-lived shell running in a PTY. Every command is sent to the same
+/// process, so `export`, `source`, aliases, functions and `set` options persist
+/// across commands exactly as they do in a normal terminal.
+///
+/// Command boundaries are detected with private OSC sequences that the shell
+/// prints around every command: `ESC ] 7770 ; <token> ; C BEL` right before the
+/// command runs and `ESC ] 7770 ; <token> ; D ; <status> ; <cwd> BEL` from the
+/// prompt hook once it has finished. The token is random per session so program
+/// output cannot fake a boundary by accident.
+///
+/// On Unix each command's stderr is redirected to a second PTY, so it can be
+/// recorded on its own while programs still see a terminal. The prompt hook
+/// writes `ESC ] 7770 ; <token> ; E BEL` there to mark the end of the stream.
+///
+/// The prompt hook also writes the exported variables to a file, if the shell
+/// can, so each command's result says which ones the session has changed.
+///
+/// How the markers are produced depends on the shell; see `ShellAdapter`.
+pub struct ShellSession {
+    adapter: Box<dyn ShellAdapter>,
+    process: PtyProcess,
+    output_rx: mpsc::UnboundedReceiver<Vec<u8>>,
+    #[cfg(unix)]
+    stderr_pty: Option<CapturePty>,
+    stderr_rx: Option<mpsc::UnboundedReceiver<Vec<u8>>>,
+    token: String,
+    pending: Vec<u8>,
+    stderr_pending: Vec<u8>,
+    working_directory: String,
+    /// Where the prompt hook writes the exported variables
+    env_path: PathBuf,
+    /// The exported variables once the shell had started
+    base_environment: Option<BTreeMap<String, String>>,
+    alive: bool,
+}
+
+/// Output of the command currently running, handed on as it arrives
+struct CommandCapture<'a> {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    chunks: OutputChunkCollector,
+    interrupts: Vec<(i32, DateTime<Utc>)>,
+    on_output: &'a mut dyn FnMut(OutputStream, &[u8]),
+}
+
+impl CommandCapture<'_> {
+    fn push(&mut self, stream: OutputStream, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        (self.on_output)(stream, bytes);
+        match stream {
+            OutputStream::Stdout => self.stdout.extend_from_slice(bytes),
+            OutputStream::Stderr => self.stderr.extend_from_slice(bytes),
+        }
+        self.chunks.push(stream, bytes);
+    }
+}
+
+impl ShellSession {
+    /// Spawn `shell` in `cwd` and wait until it is ready for commands
+    pub async fn start(cwd: &str, shell: ShellKind) -> crate::Result<Self> {
+        Self::start_with_environment(cwd, shell, &[]).await
+    }
+
+    /// Like `start`, with the `NAME=value` variables of `environment`, as
+    /// reported in `ShellCommandResult::environment`, exported in the shell.
+    /// They count as changed by this shell, so its results report them too.
+    pub async fn start_with_environment(cwd: &str, shell: ShellKind, environment: &[String]) -> crate::Result<Self> {
+        let adapter = shell.adapter();
+        let (program, args) = adapter.interactive_command();
+        let environment: Vec<(String, String)> = environment
+            .iter()
+            .filter_map(|entry| entry.split_once('='))
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let (process, output_rx) = PtyProcess::spawn_with_env(program, args, cwd, &environment)?;
+
+        // Without a second PTY stderr simply stays merged into stdout
+        #[cfg(unix)]
+        let (stderr_pty, stderr_rx) = match CapturePty::open() {
+            Ok((pty, rx)) => (Some(pty), Some(rx)),
+            Err(_) => (None, None),
+        };
+        #[cfg(not(unix))]
+        let stderr_rx = None;
+
+        let mut token_bytes = [0u8; 8];
+        rand::rngs::OsRng.fill_bytes(&mut token_bytes);
+        let token: String = token_bytes.iter().map(|b| format!("{:02x}", b)).collect();
+
+        let env_path = std::env::temp_dir().join(format!("timeloop-env-{}", token));
+        let mut session = Self {
+            adapter,
+            process,
+            output_rx,
+            #[cfg(unix)]
+            stderr_pty,
+            stderr_rx,
+            token,
+            pending: Vec::new(),
+            stderr_pending: Vec::new(),
+            working_directory: cwd.to_string(),
+            env_path: env_path.clone(),
+            base_environment: None,
+            alive: true,
+        };
+
+        let init = session.adapter.init_script(
+            &session.token,
+            session.stderr_path(),
+            Some(&env_path.to_string_lossy()),
+        );
+        session.process.write_input(init.as_bytes())?;
+
+        // Everything printed before the first end marker (startup banners, the
+        // echoed init line, the user's prompt) is discarded.
+        match tokio::time::timeout(STARTUP_TIMEOUT, session.wait_for_end()).await {
+            Ok(Ok(Some((_, cwd)))) => {
+                session.working_directory = cwd;
+                session.base_environment = read_environment(&session.env_path).map(|mut base| {
+                    for (name, _) in &environment {
+                        match std::env::var(name) {
+                            Ok(value) => base.insert(name.clone(), value),
+                            Err(_) => base.remove(name),
+                        };
+                    }
+                    base
+                });
+                Ok(session)
+            }
+            Ok(Ok(None)) => Err(TimeLoopError::CommandExecution(
+                "Shell exited during startup".to_string(),
+            )),
+            Ok(Err(e)) => Err(e),
+            Err(_) => {
+                let _ = session.process.kill();
+                Err(TimeLoopError::CommandExecution(
+                    "Timed out waiting for the shell to start".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Which shell is running
+    pub fn kind(&self) -> ShellKind {
+        self.adapter.kind()
+    }
+
+    /// The shell's current working directory as reported after the last command
+    pub fn working_directory(&self) -> &str {
+        &self.working_directory
+    }
+
+    /// False once the shell process has exited (e.g. after `exit` or `set -e`)
+    pub fn is_alive(&self) -> bool {
+        self.alive
+    }
+
+    /// Run `command` in the shell. Output between the boundary markers is handed
+    /// to `on_output` as it arrives; forwarded `input` is written to the PTY
+    /// while the command runs.
+    pub async fn execute(
+        &mut self,
+        command: &str,
+        mut input: Option<&mut mpsc::UnboundedReceiver<PtyInput>>,
+        on_output: &mut dyn FnMut(OutputStream, &[u8]),
+    ) -> crate::Result<ShellCommandResult> {
+        if !self.alive {
+            return Err(TimeLoopError::CommandExecution(
+                "Shell is no longer running".to_string(),
+            ));
+        }
+        // Anything background jobs printed since the last command is dropped,
+        // just as it is on the main PTY before the start marker
+        self.pending.clear();
+        self.stderr_pending.clear();
+        if let Some(rx) = self.stderr_rx.as_mut() {
+            while rx.try_recv().is_ok() {}
+        }
+        let wrapped = self
+            .adapter
+            .wrap_command(command, self.stderr_path().is_some());
+        self.process.write_input(wrapped.as_bytes())?;
+
+        let start_marker = format!("\x1b]7770;{};C\x07", self.token).into_bytes();
+        let end_prefix = format!("\x1b]7770;{};D;", self.token).into_bytes();
+        let stderr_marker = format!("\x1b]7770;{};E\x07", self.token).into_bytes();
+
+        let mut capture = CommandCapture {
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            chunks: OutputChunkCollector::new(),
+            interrupts: Vec::new(),
+            on_output,
+        };
+        // Signals sent to TimeLoop itself go to the command, not to us
+        #[cfg(unix)]
+        let signals = self.process.master_fd().map(SignalForwarder::install);
+        let mut started = false;
+        let mut finished: Option<i32> = None;
+        let mut stderr_done = self.stderr_rx.is_none();
+        let mut stderr_deadline = Instant::now() + STARTUP_TIMEOUT;
+        let mut exit_check = tokio::time::interval(Duration::from_millis(50));
+        let mut usage = ChildUsageTracker::start(self.process.process_id());
+
+        loop {
+            if !started {
+                match find(&self.pending, &start_marker) {
+                    Some(pos) => {
+                        self.pending.drain(..pos + start_marker.len());
+                        started = true;
+                    }
+                    // A bare end marker means the command never started, e.g. the
+                    // shell rejected the line before running the pre-command hook
+                    None if find(&self.pending, &end_prefix).is_some() => started = true,
+                    None => {}
+                }
+            }
+
+            if started && finished.is_none() {
+                if let Some((status, cwd, marker_pos)) = self.parse_end_marker(&end_prefix) {
+                    let tail: Vec<u8> = self.pending.drain(..marker_pos).collect();
+                    capture.push(OutputStream::Stdout, &tail);
+                    self.pending.clear();
+                    self.working_directory = cwd;
+                    finished = Some(status);
+                    stderr_deadline = Instant::now() + STDERR_GRACE;
+                } else {
+                    // Pass through everything except a trailing partial marker
+                    let safe = safe_prefix_len(&self.pending, &end_prefix);
+                    let chunk: Vec<u8> = self.pending.drain(..safe).collect();
+                    capture.push(OutputStream::Stdout, &chunk);
+                }
+            }
+
+            if !stderr_done {
+                if let Some(pos) = find(&self.stderr_pending, &stderr_marker) {
+                    let tail: Vec<u8> = self.stderr_pending.drain(..pos).collect();
+                    capture.push(OutputStream::Stderr, &tail);
+                    self.stderr_pending.clear();
+                    stderr_done = true;
+                } else {
+                    let safe = safe_prefix_len(&self.stderr_pending, &stderr_marker);
+                    let chunk: Vec<u8> = self.stderr_pending.drain(..safe).collect();
+                    capture.push(OutputStream::Stderr, &chunk);
+                }
+            }
+
+            if let (Some(status), true) = (finished, stderr_done) {
+                let signal = signal_from_shell_status(status);
+                // Shells with job control report core dumps as e.g.
+                // "Segmentation fault (core dumped)"
+                let core_dumped = signal.is_some()
+                    && (find(&capture.stderr, b"(core dumped)").is_some()
+                        || find(&capture.stdout, b"(core dumped)").is_some());
+                let exit = ProcessExit {
+                    exit_code: status,
+                    signal,
+                    core_dumped,
+                    resource_usage: usage.finish(),
+                };
+                return Ok(self.result(capture, exit, false));
+            }
+
+            tokio::select! {
+                chunk = self.output_rx.recv() => match chunk {
+                    Some(chunk) => self.pending.extend_from_slice(&chunk),
+                    None => return self.finish_exited(capture).await,
+                },
+                Some(chunk) = recv_stderr(&mut self.stderr_rx), if !stderr_done => {
+                    self.stderr_pending.extend_from_slice(&chunk);
+                }
+                Some(event) = recv_input(&mut input) => {
+                    if let PtyInput::Bytes(bytes) = &event {
+                        if let Some(signal) = self.process.signal_for_input(bytes) {
+                            capture.interrupts.push((signal, Utc::now()));
+                        }
+                    }
+                    let _ = self.apply_input(event);
+                }
+                _ = tokio::time::sleep_until(stderr_deadline), if finished.is_some() => {
+                    // The stderr marker never arrived (the command may have
+                    // replaced the hook); keep whatever was captured
+                    stderr_done = true;
+                }
+                _ = exit_check.tick() => {
+                    usage.sample(self.process.foreground_process_group());
+                    #[cfg(unix)]
+                    if let Some(signals) = &signals {
+                        for signal in signals.take_forwarded() {
+                            capture.interrupts.push((signal, Utc::now()));
+                        }
+                    }
+                    if self.process.try_wait()?.is_some() {
+                        return self.finish_exited(capture).await;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Terminate the shell
+    pub fn kill(&mut self) {
+        self.alive = false;
+        let _ = self.process.kill();
+    }
+
+    fn stderr_path(&self) -> Option<&str> {
+        #[cfg(unix)]
+        {
+            self.stderr_pty.as_ref().map(|pty| pty.path())
+        }
+        #[cfg(not(unix))]
+        {
+            None
+        }
+    }
+
+    fn apply_input(&mut self, input: PtyInput) -> crate::Result<()> {
+        #[cfg(unix)]
+        if let (PtyInput::Resize(cols, rows), Some(pty)) = (&input, &self.stderr_pty) {
+            pty.resize(*cols, *rows)?;
+        }
+        self.process.apply_input(input)
+    }
+
+    fn result(&self, capture: CommandCapture<'_>, exit: ProcessExit, shell_exited: bool) -> ShellCommandResult {
+        ShellCommandResult {
+            output: capture.stdout,
+            stderr: capture.stderr,
+            output_chunks: capture.chunks.finish(),
+            exit_code: exit.exit_code,
+            signal: exit.signal,
+            core_dumped: exit.core_dumped,
+            resource_usage: exit.resource_usage,
+            interrupts: capture.interrupts,
+            working_directory: self.working_directory.clone(),
+            environment: if shell_exited { None } else { self.environment_changes() },
+            shell_exited,
+        }
+    }
+
+    /// The exported variables that differ from when the shell started
+    fn environment_changes(&self) -> Option<Vec<String>> {
+        let base = self.base_environment.as_ref()?;
+        let current = read_environment(&self.env_path)?;
+        Some(
+            current
+                .into_iter()
+                .filter(|(name, value)| {
+                    !SHELL_MANAGED_VARIABLES.contains(&name.as_str()) && base.get(name) != Some(value)
+                })
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect(),
+        )
+    }
+
+    async fn finish_exited(&mut self, mut capture: CommandCapture<'_>) -> crate::Result<ShellCommandResult> {
+        self.alive = false;
+        while let Ok(Some(chunk)) =
+            tokio::time::timeout(Duration::from_millis(50), self.output_rx.recv()).await
+        {
+            self.pending.extend_from_slice(&chunk);
+        }
+        if let Some(rx) = self.stderr_rx.as_mut() {
+            while let Ok(chunk) = rx.try_recv() {
+                self.stderr_pending.extend_from_slice(&chunk);
+            }
+        }
+        let rest = std::mem::take(&mut self.pending);
+        capture.push(OutputStream::Stdout, &rest);
+        let rest = std::mem::take(&mut self.stderr_pending);
+        capture.push(OutputStream::Stderr, &rest);
+
+        let exit = self
+            .process
+            .try_wait()?
+            .unwrap_or_else(|| ProcessExit::from_code(-1));
+        Ok(self.result(capture, exit, true))
+    }
+
+    /// Wait for the next end marker (and the matching stderr marker),
+    /// discarding all output. Returns None if the shell exits first.
+    async fn wait_for_end(&mut self) -> crate::Result<Option<(i32, String)>> {
+        let end_prefix = format!("\x1b]7770;{};D;", self.token).into_bytes();
+        let stderr_marker = format!("\x1b]7770;{};E\x07", self.token).into_bytes();
+        let mut finished = None;
+        let mut stderr_done = self.stderr_rx.is_none();
+        let mut stderr_deadline = Instant::now() + STARTUP_TIMEOUT;
+        loop {
+            if finished.is_none() {
+                if let Some((status, cwd, _)) = self.parse_end_marker(&end_prefix) {
+                    self.pending.clear();
+                    finished = Some((status, cwd));
+                    stderr_deadline = Instant::now() + STDERR_GRACE;
+                }
+            }
+            if !stderr_done && find(&self.stderr_pending, &stderr_marker).is_some() {
+                stderr_done = true;
+            }
+            if stderr_done && finished.is_some() {
+                self.stderr_pending.clear();
+                return Ok(finished);
+            }
+            tokio::select! {
+                chunk = self.output_rx.recv() => match chunk {
+                    Some(chunk) => self.pending.extend_from_slice(&chunk),
+                    None => {
+                        self.alive = false;
+                        return Ok(None);
+                    }
+                },
+                Some(chunk) = recv_stderr(&mut self.stderr_rx), if !stderr_done => {
+                    self.stderr_pending.extend_from_slice(&chunk);
+                }
+                _ = tokio::time::sleep_until(stderr_deadline), if finished.is_some() => {
+                    stderr_done = true;
+                }
+            }
+        }
+    }
+
+    /// Look for a complete end marker in the pending buffer and return its exit
+    /// status, reported cwd and start offset.
+    fn parse_end_marker(&self, end_prefix: &[u8]) -> Option<(i32, String, usize)> {
+        let pos = find(&self.pending, end_prefix)?;
+        let payload_start = pos + end_prefix.len();
+        let len = self.pending[payload_start..].iter().position(|&b| b == 0x07)?;
+        let payload = String::from_utf8_lossy(&self.pending[payload_start..payload_start + len]);
+        let (status, cwd) = payload.split_once(';')?;
+        let status = status.trim().parse().unwrap_or(-1);
+        let cwd = if cwd.is_empty() {
+            self.working_directory.clone()
+        } else {
+            cwd.to_string()
+        };
+        Some((status, cwd, pos))
+    }
+}
+
+impl Drop for ShellSession {
+    fn drop(&mut self) {
+        if self.alive {
+            let _ = self.process.kill();
+        }
+        let _ = std::fs::remove_file(&self.env_path);
+    }
+}
+
+/// Variables as written by `env -0`, or None if the file is missing or empty
+fn read_environment(path: &Path) -> Option<BTreeMap<String, String>> {
+    let bytes = std::fs::read(path).ok()?;
+    let environment: BTreeMap<String, String> = bytes
+        .split(|&b| b == 0)
+        .filter_map(|entry| {
+            let entry = String::from_utf8_lossy(entry);
+            let (name, value) = entry.split_once('=')?;
+            Some((name.to_string(), value.to_string()))
+        })
+        .collect();
+    (!environment.is_empty()).then_some(environment)
+}
+
+async fn recv_input(input: &mut Option<&mut mpsc::UnboundedReceiver<PtyInput>>) -> Option<PtyInput> {
+    match input {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn recv_stderr(rx: &mut Option<mpsc::UnboundedReceiver<Vec<u8>>>) -> Option<Vec<u8>> {
+    match rx {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() || haystack.len() < needle.len() {
+        return None;
+    }
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Number of leading bytes of `buf` that cannot be part of `marker`, i.e.
+/// everything except a trailing partial match that needs more data.
+fn safe_prefix_len(buf: &[u8], marker: &[u8]) -> usize {
+    let max = marker.len().saturating_sub(1).min(buf.len());
+    for keep in (1..=max).rev() {
+        if buf[buf.len() - keep..] == marker[..keep] {
+            return buf.len() - keep;
+        }
+    }
+    buf.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_safe_prefix_len() {
+        let marker = b"\x1b]7770;ab;D;";
+        assert_eq!(safe_prefix_len(b"hello", marker), 5);
+        assert_eq!(safe_prefix_len(b"hello\x1b]77", marker), 5);
+        assert_eq!(safe_prefix_len(b"hello\x1b", marker), 5);
+        assert_eq!(safe_prefix_len(b"hello\x1b[0m", marker), 9);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_shell_state_persists_between_commands() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let cwd = tmp_dir.path().to_str().unwrap().to_string();
+        let mut shell = ShellSession::start(&cwd, ShellKind::Bash).await.unwrap();
+        let mut sink = |_: OutputStream, _: &[u8]| {};
+
+        shell.execute("export TL_TEST_VAR=persisted", None, &mut sink).await.unwrap();
+        shell.execute("mkdir sub && cd sub", None, &mut sink).await.unwrap();
+        let result = shell
+            .execute("echo \"$TL_TEST_VAR\"; false", None, &mut sink)
+            .await
+            .unwrap();
+
+        assert_eq!(result.exit_code, 1);
+        assert!(result.working_directory.ends_with("/sub"));
+        let text = crate::pty::terminal_output_to_text(&result.output);
+        assert_eq!(text.trim(), "persisted");
+        // Only what the commands exported is reported, not the directory
+        assert_eq!(result.environment, Some(vec!["TL_TEST_VAR=persisted".to_string()]));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_environment_is_restored() {
+        let cwd = std::env::temp_dir();
+        let environment = vec!["TL_RESTORED=a b\nc".to_string()];
+        let mut shell = ShellSession::start_with_environment(cwd.to_str().unwrap(), ShellKind::Bash, &environment)
+            .await
+            .unwrap();
+        let mut sink = |_: OutputStream, _: &[u8]| {};
+
+        let result = shell
+            .execute("printf '%s' \"$TL_RESTORED\"; export TL_MORE=1", None, &mut sink)
+            .await
+            .unwrap();
+        let text = crate::pty::terminal_output_to_text(&result.output);
+        assert_eq!(text, "a b\nc");
+        assert_eq!(
+            result.environment,
+            Some(vec!["TL_MORE=1".to_string(), "TL_RESTORED=a b\nc".to_string()])
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_stderr_is_captured_separately() {
+        let cwd = std::env::temp_dir();
+        let mut shell = ShellSession::start(cwd.to_str().unwrap(), ShellKind::Bash).await.unwrap();
+        let mut sink = |_: OutputStream, _: &[u8]| {};
+
+        let result = shell
+            .execute("echo out; echo err >&2; [ -t 2 ] && echo tty >&2", None, &mut sink)
+            .await
+            .unwrap();
+
+        let stdout = crate::pty::terminal_output_to_text(&result.output);
+        let stderr = crate::pty::terminal_output_to_text(&result.stderr);
+        assert_eq!(stdout.trim(), "out");
+        assert_eq!(stderr.trim(), "err\ntty");
+        assert!(result
+            .output_chunks
+            .iter()
+            .any(|chunk| chunk.stream == OutputStream::Stderr));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_signal_and_usage_are_reported() {
+        let cwd = std::env::temp_dir();
+        let mut shell = ShellSession::start(cwd.to_str().unwrap(), ShellKind::Bash).await.unwrap();
+        let mut sink = |_: OutputStream, _: &[u8]| {};
+
+        let result = shell
+            .execute("sh -c 'kill -TERM $$'", None, &mut sink)
+            .await
+            .unwrap();
+        assert_eq!(result.exit_code, 128 + libc::SIGTERM);
+        assert_eq!(result.signal, Some(libc::SIGTERM));
+
+        let result = shell.execute("true", None, &mut sink).await.unwrap();
+        assert_eq!(result.signal, None);
+        if cfg!(target_os = "linux") {
+            assert!(result.resource_usage.is_some());
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_ctrl_c_interrupts_the_running_command() {
+        let cwd = std::env::temp_dir();
+        let mut shell = ShellSession::start(cwd.to_str().unwrap(), ShellKind::Bash).await.unwrap();
+        let mut sink = |_: OutputStream, _: &[u8]| {};
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+            let _ = tx.send(PtyInput::Bytes(vec![0x03]));
+        });
+        let started = std::time::Instant::now();
+        let result = shell.execute("sleep 10", Some(&mut rx), &mut sink).await.unwrap();
+
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+        assert_eq!(result.signal, Some(libc::SIGINT));
+        assert_eq!(result.interrupts.len(), 1);
+        assert_eq!(result.interrupts[0].0, libc::SIGINT);
+        assert!(!result.shell_exited);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_posix_sh_reports_status_and_directory() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let cwd = tmp_dir.path().to_str().unwrap().to_string();
+        let mut shell = ShellSession::start(&cwd, ShellKind::Sh).await.unwrap();
+        let mut sink = |_: OutputStream, _: &[u8]| {};
+
+        shell.execute("mkdir sub && cd sub", None, &mut sink).await.unwrap();
+        let result = shell
+            .execute("echo \"it's\"; echo err >&2; false", None, &mut sink)
+            .await
+            .unwrap();
+
+        assert_eq!(result.exit_code, 1);
+        assert!(result.working_directory.ends_with("/sub"));
+        let text = crate::pty::terminal_output_to_text(&result.output);
+        assert_eq!(text.trim(), "it's");
+        let stderr = crate::pty::terminal_output_to_text(&result.stderr);
+        assert_eq!(stderr.trim(), "err");
+    }
+}
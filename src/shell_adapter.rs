@@ -0,0 +1,542 @@
+use crate::TimeLoopError;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Environment variable that selects the shell when no `--shell` flag is given
+pub const SHELL_ENV_VAR: &str = "TIMELOOP_SHELL";
+
+/// How to start a user's interactive shell with the prompt-mark snippet loaded
+#[derive(Debug, Clone, PartialEq)]
+pub struct IntegratedShell {
+    pub program: &'static str,
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    /// Startup files to write into the launch directory, by file name
+    pub files: Vec<(&'static str, String)>,
+}
+
+/// The shells TimeLoop can host
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShellKind {
+    Bash,
+    Zsh,
+    Fish,
+    Sh,
+    PowerShell,
+}
+
+impl ShellKind {
+    /// PowerShell on Windows, bash everywhere else
+    pub fn platform_default() -> Self {
+        if cfg!(target_os = "windows") {
+            ShellKind::PowerShell
+        } else {
+            ShellKind::Bash
+        }
+    }
+
+    /// The shell named by `TIMELOOP_SHELL`, if it is set
+    pub fn from_env() -> crate::Result<Option<Self>> {
+        match std::env::var(SHELL_ENV_VAR) {
+            Ok(name) if !name.trim().is_empty() => name.parse().map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Name shown to users, e.g. in the welcome banner
+    pub fn display_name(self) -> &'static str {
+        match self {
+            ShellKind::Bash => "Bash",
+            ShellKind::Zsh => "Zsh",
+            ShellKind::Fish => "Fish",
+            ShellKind::Sh => "sh",
+            ShellKind::PowerShell => "PowerShell",
+        }
+    }
+
+    pub fn adapter(self) -> Box<dyn ShellAdapter> {
+        match self {
+            ShellKind::Bash => Box::new(Bash),
+            ShellKind::Zsh => Box::new(Zsh),
+            ShellKind::Fish => Box::new(Fish),
+            ShellKind::Sh => Box::new(Sh),
+            ShellKind::PowerShell => Box::new(PowerShell),
+        }
+    }
+}
+
+impl Default for ShellKind {
+    fn default() -> Self {
+        Self::platform_default()
+    }
+}
+
+impl fmt::Display for ShellKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ShellKind::Bash => "bash",
+            ShellKind::Zsh => "zsh",
+            ShellKind::Fish => "fish",
+            ShellKind::Sh => "sh",
+            ShellKind::PowerShell => "powershell",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for ShellKind {
+    type Err = TimeLoopError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Accept paths such as /usr/bin/zsh, as found in $SHELL
+        let name = s.trim().rsplit(['/', '\\']).next().unwrap_or_default();
+        let name = name.strip_suffix(".exe").unwrap_or(name).to_ascii_lowercase();
+        match name.as_str() {
+            "bash" => Ok(ShellKind::Bash),
+            "zsh" => Ok(ShellKind::Zsh),
+            "fish" => Ok(ShellKind::Fish),
+            "sh" | "dash" => Ok(ShellKind::Sh),
+            "powershell" | "pwsh" => Ok(ShellKind::PowerShell),
+            _ => Err(TimeLoopError::Configuration(format!(
+                "Unsupported shell: {} (expected bash, zsh, fish, sh or powershell)",
+                s
+            ))),
+        }
+    }
+}
+
+/// Everything TimeLoop needs to know to drive one kind of shell.
+///
+/// A persistent `ShellSession` types `init_script` into the shell once, then
+/// one `wrap_command` line per command. Between them they must print the
+/// boundary markers described on `ShellSession`: `C` right before the command
+/// runs, and `D` with the exit status and `$PWD` once it has finished. The
+/// reported `$PWD` is how directory changes are detected. Shells that can
+/// also write their exported variables, as `env -0` prints them, to the file
+/// given to `init_script` before `D`.
+pub trait ShellAdapter: Send + Sync {
+    fn kind(&self) -> ShellKind;
+
+    /// Program and arguments for a long-lived interactive shell
+    fn interactive_command(&self) -> (&'static str, &'static [&'static str]);
+
+    /// Program and arguments that run `command` on its own and exit
+    fn one_shot_command(&self, command: &str) -> (&'static str, Vec<String>);
+
+    /// Line typed into the shell right after it starts. It hides the prompt,
+    /// keeps the wrapper lines out of history and installs the boundary hooks.
+    fn init_script(&self, token: &str, stderr_path: Option<&str>, env_path: Option<&str>) -> String;
+
+    /// Line typed into the shell to run `command`
+    fn wrap_command(&self, command: &str, separate_stderr: bool) -> String;
+
+    /// Builtins of the form `<builtin> [dir]` that change the working directory
+    fn directory_builtins(&self) -> &'static [&'static str];
+
+    /// Command that changes the working directory to `dir`
+    fn change_directory_command(&self, dir: &str) -> String {
+        format!("cd {}", posix_quote(dir))
+    }
+
+    /// If `command` is a plain directory change such as `cd src`, the target
+    /// directory, or None for the home directory. Used when there is no
+    /// persistent shell to report its `$PWD`.
+    fn directory_change(&self, command: &str) -> Option<Option<String>> {
+        if command.contains([';', '|', '&', '$', '`', '(', '<', '>']) {
+            return None;
+        }
+        let words = shellwords::split(command).ok()?;
+        let (builtin, args) = words.split_first()?;
+        if !self.directory_builtins().contains(&builtin.as_str()) {
+            return None;
+        }
+        match args {
+            [] => Some(None),
+            [dir] if dir != "-" => Some(Some(dir.clone())),
+            _ => None,
+        }
+    }
+
+    /// How to start an interactive shell that reads the user's own startup
+    /// files and then the snippet in `shell-integration/`, which marks
+    /// prompts and commands with OSC 133. The files are written to `dir`.
+    /// None if there is no snippet for this shell.
+    fn prompt_marks_launch(&self, _dir: &str) -> Option<IntegratedShell> {
+        None
+    }
+}
+
+const BASH_PROMPT_MARKS: &str = include_str!("../shell-integration/timeloop.bash");
+const ZSH_PROMPT_MARKS: &str = include_str!("../shell-integration/timeloop.zsh");
+const FISH_PROMPT_MARKS: &str = include_str!("../shell-integration/timeloop.fish");
+
+/// Quote a word for display in a command line if it needs it
+pub fn quote_word(word: &str) -> String {
+    if !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_alphanumeric() || "-_./=:,+@%".contains(c))
+    {
+        word.to_string()
+    } else {
+        posix_quote(word)
+    }
+}
+
+/// Quote `s` as a single POSIX shell word
+fn posix_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+pub struct Bash;
+
+impl ShellAdapter for Bash {
+    fn kind(&self) -> ShellKind {
+        ShellKind::Bash
+    }
+
+    fn interactive_command(&self) -> (&'static str, &'static [&'static str]) {
+        ("bash", &["-i"])
+    }
+
+    fn one_shot_command(&self, command: &str) -> (&'static str, Vec<String>) {
+        ("bash", vec!["-c".to_string(), command.to_string()])
+    }
+
+    fn init_script(&self, token: &str, stderr_path: Option<&str>, env_path: Option<&str>) -> String {
+        format!(
+            " __tl_token={token}; __tl_err='{}'; __tl_env='{}'; PS1=''; PS2=''; set +H; \
+             bind 'set disable-completion on' 2>/dev/null; \
+             HISTCONTROL=\"ignorespace${{HISTCONTROL:+:$HISTCONTROL}}\"; \
+             __tl_pre() {{ history -s -- \"$__tl_cmd\"; printf '\\033]7770;%s;C\\007' \"$__tl_token\"; }}; \
+             __tl_post() {{ local s=$?; [ -n \"$__tl_env\" ] && command command env -0 >\"$__tl_env\" 2>/dev/null; \
+             printf '\\033]7770;%s;D;%s;%s\\007' \"$__tl_token\" \"$s\" \"$PWD\"; \
+             [ -n \"$__tl_err\" ] && printf '\\033]7770;%s;E\\007' \"$__tl_token\" >\"$__tl_err\"; return $s; }}; \
+             PROMPT_COMMAND=\"__tl_post${{PROMPT_COMMAND:+; $PROMPT_COMMAND}}\"; \
+             history -d -1 2>/dev/null\n",
+            stderr_path.unwrap_or_default(),
+            env_path.unwrap_or_default()
+        )
+    }
+
+    /// A leading Ctrl-U discards any partial line the user typed ahead while
+    /// the previous command ran. The end marker comes from `PROMPT_COMMAND`.
+    fn wrap_command(&self, command: &str, separate_stderr: bool) -> String {
+        format!(
+            "\x15 __tl_cmd={}; __tl_pre; eval -- \"$__tl_cmd\"{}\n",
+            posix_quote(command),
+            if separate_stderr { " 2>\"$__tl_err\"" } else { "" }
+        )
+    }
+
+    fn directory_builtins(&self) -> &'static [&'static str] {
+        &["cd", "pushd"]
+    }
+
+    fn prompt_marks_launch(&self, dir: &str) -> Option<IntegratedShell> {
+        let rcfile = format!("{}/bashrc", dir);
+        Some(IntegratedShell {
+            program: "bash",
+            args: vec!["--rcfile".to_string(), rcfile, "-i".to_string()],
+            env: Vec::new(),
+            files: vec![(
+                "bashrc",
+                format!("[ -f ~/.bashrc ] && . ~/.bashrc\n{}", BASH_PROMPT_MARKS),
+            )],
+        })
+    }
+}
+
+pub struct Zsh;
+
+impl ShellAdapter for Zsh {
+    fn kind(&self) -> ShellKind {
+        ShellKind::Zsh
+    }
+
+    fn interactive_command(&self) -> (&'static str, &'static [&'static str]) {
+        ("zsh", &["-i"])
+    }
+
+    fn one_shot_command(&self, command: &str) -> (&'static str, Vec<String>) {
+        ("zsh", vec!["-c".to_string(), command.to_string()])
+    }
+
+    /// ZLE is switched off so that the typed wrapper lines are read verbatim,
+    /// and the end marker is printed from a `precmd` hook.
+    fn init_script(&self, token: &str, stderr_path: Option<&str>, env_path: Option<&str>) -> String {
+        format!(
+            " __tl_token={token}; __tl_err='{}'; __tl_env='{}'; PS1=''; PS2=''; RPS1=''; PROMPT_EOL_MARK=''; \
+             unsetopt zle prompt_sp prompt_cr bang_hist 2>/dev/null; setopt hist_ignore_space; \
+             __tl_pre() {{ print -s -r -- \"$__tl_cmd\"; printf '\\033]7770;%s;C\\007' \"$__tl_token\"; }}; \
+             __tl_post() {{ local s=$?; [ -n \"$__tl_env\" ] && command command env -0 >\"$__tl_env\" 2>/dev/null; \
+             printf '\\033]7770;%s;D;%s;%s\\007' \"$__tl_token\" \"$s\" \"$PWD\"; \
+             [ -n \"$__tl_err\" ] && printf '\\033]7770;%s;E\\007' \"$__tl_token\" >\"$__tl_err\"; return $s; }}; \
+             precmd_functions=(__tl_post $precmd_functions)\n",
+            stderr_path.unwrap_or_default(),
+            env_path.unwrap_or_default()
+        )
+    }
+
+    fn wrap_command(&self, command: &str, separate_stderr: bool) -> String {
+        format!(
+            "\x15 __tl_cmd={}; __tl_pre; eval \"$__tl_cmd\"{}\n",
+            posix_quote(command),
+            if separate_stderr { " 2>\"$__tl_err\"" } else { "" }
+        )
+    }
+
+    fn directory_builtins(&self) -> &'static [&'static str] {
+        &["cd", "chdir", "pushd"]
+    }
+
+    /// zsh has no option naming an rc file, so `ZDOTDIR` points at `dir`,
+    /// whose startup files source the user's and then restore `ZDOTDIR`.
+    fn prompt_marks_launch(&self, dir: &str) -> Option<IntegratedShell> {
+        let mut env = vec![("ZDOTDIR".to_string(), dir.to_string())];
+        if let Ok(user_dir) = std::env::var("ZDOTDIR") {
+            env.push(("TIMELOOP_USER_ZDOTDIR".to_string(), user_dir));
+        }
+        Some(IntegratedShell {
+            program: "zsh",
+            args: vec!["-i".to_string()],
+            env,
+            files: vec![
+                (
+                    ".zshenv",
+                    "__tl_zdotdir=$ZDOTDIR; ZDOTDIR=${TIMELOOP_USER_ZDOTDIR:-$HOME}\n\
+                     [[ -f $ZDOTDIR/.zshenv ]] && . $ZDOTDIR/.zshenv\n\
+                     TIMELOOP_USER_ZDOTDIR=$ZDOTDIR; ZDOTDIR=$__tl_zdotdir\n"
+                        .to_string(),
+                ),
+                (
+                    ".zshrc",
+                    format!(
+                        "ZDOTDIR=$TIMELOOP_USER_ZDOTDIR; unset TIMELOOP_USER_ZDOTDIR\n\
+                         [[ -f $ZDOTDIR/.zshrc ]] && . $ZDOTDIR/.zshrc\n{}",
+                        ZSH_PROMPT_MARKS
+                    ),
+                ),
+            ],
+        })
+    }
+}
+
+pub struct Fish;
+
+impl Fish {
+    /// Quote `s` as a single fish word
+    fn quote(s: &str) -> String {
+        format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+    }
+}
+
+impl ShellAdapter for Fish {
+    fn kind(&self) -> ShellKind {
+        ShellKind::Fish
+    }
+
+    fn interactive_command(&self) -> (&'static str, &'static [&'static str]) {
+        ("fish", &["-i"])
+    }
+
+    fn one_shot_command(&self, command: &str) -> (&'static str, Vec<String>) {
+        ("fish", vec!["-c".to_string(), command.to_string()])
+    }
+
+    /// Fish has no hook that sees the status of a command run through `eval`
+    /// reliably, so the wrapper line prints the end marker itself. Lines
+    /// starting with a space are kept out of history by fish.
+    fn init_script(&self, token: &str, stderr_path: Option<&str>, env_path: Option<&str>) -> String {
+        format!(
+            " set -g __tl_token {token}; set -g __tl_err {}; set -g __tl_env {}; \
+             function fish_prompt; end; function fish_right_prompt; end; function fish_greeting; end; \
+             bind \\t 'commandline -i \\t' 2>/dev/null; \
+             function __tl_pre; builtin history append -- $__tl_cmd 2>/dev/null; printf '\\e]7770;%s;C\\a' $__tl_token; end; \
+             function __tl_post; test -n \"$__tl_env\"; and command env -0 >$__tl_env 2>/dev/null; \
+             printf '\\e]7770;%s;D;%s;%s\\a' $__tl_token $argv[1] $PWD; \
+             test -n \"$__tl_err\"; and printf '\\e]7770;%s;E\\a' $__tl_token >$__tl_err; end; \
+             __tl_post 0\n",
+            Self::quote(stderr_path.unwrap_or_default()),
+            Self::quote(env_path.unwrap_or_default())
+        )
+    }
+
+    fn wrap_command(&self, command: &str, separate_stderr: bool) -> String {
+        format!(
+            "\x15 set -g __tl_cmd {}; __tl_pre; eval $__tl_cmd{}; __tl_post $status\n",
+            Self::quote(command),
+            if separate_stderr { " 2>$__tl_err" } else { "" }
+        )
+    }
+
+    fn directory_builtins(&self) -> &'static [&'static str] {
+        &["cd", "pushd"]
+    }
+
+    fn change_directory_command(&self, dir: &str) -> String {
+        format!("cd {}", Self::quote(dir))
+    }
+
+    /// `--init-command` runs after the user's config has been read
+    fn prompt_marks_launch(&self, dir: &str) -> Option<IntegratedShell> {
+        let script = format!("{}/timeloop.fish", dir);
+        Some(IntegratedShell {
+            program: "fish",
+            args: vec![
+                "-i".to_string(),
+                "--init-command".to_string(),
+                format!("source {}", Self::quote(&script)),
+            ],
+            env: Vec::new(),
+            files: vec![("timeloop.fish", FISH_PROMPT_MARKS.to_string())],
+        })
+    }
+}
+
+/// Any POSIX shell, e.g. dash. Without prompt hooks the wrapper line prints
+/// the end marker itself.
+pub struct Sh;
+
+impl ShellAdapter for Sh {
+    fn kind(&self) -> ShellKind {
+        ShellKind::Sh
+    }
+
+    fn interactive_command(&self) -> (&'static str, &'static [&'static str]) {
+        ("sh", &["-i"])
+    }
+
+    fn one_shot_command(&self, command: &str) -> (&'static str, Vec<String>) {
+        ("sh", vec!["-c".to_string(), command.to_string()])
+    }
+
+    fn init_script(&self, token: &str, stderr_path: Option<&str>, env_path: Option<&str>) -> String {
+        format!(
+            " __tl_token={token}; __tl_err='{}'; __tl_env='{}'; PS1=''; PS2=''; \
+             __tl_pre() {{ printf '\\033]7770;%s;C\\007' \"$__tl_token\"; }}; \
+             __tl_post() {{ __tl_s=$?; [ -n \"$__tl_env\" ] && command command env -0 >\"$__tl_env\" 2>/dev/null; \
+             printf '\\033]7770;%s;D;%s;%s\\007' \"$__tl_token\" \"$__tl_s\" \"$PWD\"; \
+             [ -n \"$__tl_err\" ] && printf '\\033]7770;%s;E\\007' \"$__tl_token\" >\"$__tl_err\"; return $__tl_s; }}; \
+             __tl_post\n",
+            stderr_path.unwrap_or_default(),
+            env_path.unwrap_or_default()
+        )
+    }
+
+    fn wrap_command(&self, command: &str, separate_stderr: bool) -> String {
+        format!(
+            "\x15 __tl_cmd={}; __tl_pre; eval \"$__tl_cmd\"{}; __tl_post\n",
+            posix_quote(command),
+            if separate_stderr { " 2>\"$__tl_err\"" } else { "" }
+        )
+    }
+
+    fn directory_builtins(&self) -> &'static [&'static str] {
+        &["cd"]
+    }
+}
+
+pub struct PowerShell;
+
+impl ShellAdapter for PowerShell {
+    fn kind(&self) -> ShellKind {
+        ShellKind::PowerShell
+    }
+
+    fn interactive_command(&self) -> (&'static str, &'static [&'static str]) {
+        ("powershell", &["-NoLogo", "-NoExit", "-ExecutionPolicy", "Bypass"])
+    }
+
+    /// -NoProfile starts faster, -ExecutionPolicy Bypass allows scripts
+    fn one_shot_command(&self, command: &str) -> (&'static str, Vec<String>) {
+        (
+            "powershell",
+            ["-NoProfile", "-ExecutionPolicy", "Bypass", "-Command", command]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+        )
+    }
+
+    fn init_script(&self, token: &str, _stderr_path: Option<&str>, _env_path: Option<&str>) -> String {
+        format!(
+            "$global:__tl_token='{token}'; \
+             function global:__tl_pre {{ Write-Host -NoNewline \"$([char]27)]7770;$global:__tl_token;C$([char]7)\" }}; \
+             function global:prompt {{ $ok = $?; $code = if ($ok) {{ 0 }} elseif ($global:LASTEXITCODE) {{ $global:LASTEXITCODE }} else {{ 1 }}; \
+             \"$([char]27)]7770;$global:__tl_token;D;$code;$($PWD.ProviderPath)$([char]7)\" }}\r"
+        )
+    }
+
+    fn wrap_command(&self, command: &str, _separate_stderr: bool) -> String {
+        format!(
+            "$__tl_cmd = '{}'; __tl_pre; Invoke-Expression $__tl_cmd\r",
+            command.replace('\'', "''")
+        )
+    }
+
+    fn directory_builtins(&self) -> &'static [&'static str] {
+        &["cd", "chdir", "sl", "Set-Location", "pushd", "Push-Location"]
+    }
+
+    fn change_directory_command(&self, dir: &str) -> String {
+        format!("Set-Location -LiteralPath '{}'", dir.replace('\'', "''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_shell_kind() {
+        assert_eq!("zsh".parse::<ShellKind>().unwrap(), ShellKind::Zsh);
+        assert_eq!("/usr/bin/fish".parse::<ShellKind>().unwrap(), ShellKind::Fish);
+        assert_eq!("dash".parse::<ShellKind>().unwrap(), ShellKind::Sh);
+        assert_eq!("pwsh.exe".parse::<ShellKind>().unwrap(), ShellKind::PowerShell);
+        assert!("tcsh".parse::<ShellKind>().is_err());
+        assert_eq!(ShellKind::Bash.to_string().parse::<ShellKind>().unwrap(), ShellKind::Bash);
+    }
+
+    #[test]
+    fn test_directory_change_detection() {
+        let bash = ShellKind::Bash.adapter();
+        assert_eq!(bash.directory_change("cd src"), Some(Some("src".to_string())));
+        assert_eq!(bash.directory_change("cd"), Some(None));
+        assert_eq!(bash.directory_change("cd 'my dir'"), Some(Some("my dir".to_string())));
+        assert_eq!(bash.directory_change("cd src && make"), None);
+        assert_eq!(bash.directory_change("cd -"), None);
+        assert_eq!(bash.directory_change("Set-Location src"), None);
+
+        let powershell = ShellKind::PowerShell.adapter();
+        assert_eq!(powershell.directory_change("Set-Location src"), Some(Some("src".to_string())));
+        assert_eq!(ShellKind::Zsh.adapter().directory_change("chdir /"), Some(Some("/".to_string())));
+    }
+
+    #[test]
+    fn test_prompt_marks_launch() {
+        let bash = ShellKind::Bash.adapter().prompt_marks_launch("/tmp/tl").unwrap();
+        assert_eq!(bash.args, vec!["--rcfile", "/tmp/tl/bashrc", "-i"]);
+        assert!(bash.files[0].1.contains("133;C"));
+
+        let zsh = ShellKind::Zsh.adapter().prompt_marks_launch("/tmp/tl").unwrap();
+        assert!(zsh.env.contains(&("ZDOTDIR".to_string(), "/tmp/tl".to_string())));
+        assert_eq!(zsh.files.len(), 2);
+
+        let fish = ShellKind::Fish.adapter().prompt_marks_launch("/tmp/tl").unwrap();
+        assert_eq!(fish.args[2], "source '/tmp/tl/timeloop.fish'");
+
+        assert!(ShellKind::Sh.adapter().prompt_marks_launch("/tmp/tl").is_none());
+        assert!(ShellKind::PowerShell.adapter().prompt_marks_launch("/tmp/tl").is_none());
+    }
+
+    #[test]
+    fn test_command_quoting() {
+        let line = ShellKind::Sh.adapter().wrap_command("echo 'hi'", false);
+        assert!(line.contains("__tl_cmd='echo '\\''hi'\\'''"));
+        let line = ShellKind::Fish.adapter().wrap_command("echo 'hi' \\n", true);
+        assert!(line.contains("set -g __tl_cmd 'echo \\'hi\\' \\\\n'"));
+        assert!(line.contains("2>$__tl_err"));
+    }
+}
@@ -18,19 +18,24 @@ use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use zeroize::Zeroize;
 
+use crate::blob_store::{BlobPolicy, BlobStore};
+use crate::bookmark::Bookmark;
 use crate::branch::TimelineBranch;
+use crate::schema::{self, LogHeader, SCHEMA_VERSION};
 use crate::session::Session;
 use crate::Event;
 
-const KEY_LEN: usize = 32;
+pub(crate) const KEY_LEN: usize = 32;
 const SALT_LEN: usize = 16;
-const NONCE_LEN: usize = 24;
+pub(crate) const NONCE_LEN: usize = 24;
 
 #[derive(Default, Clone, Serialize, Deserialize)]
 struct StorageInner {
     events: HashMap<String, Vec<Event>>,       // session_id -> events
     sessions: HashMap<String, Session>,        // session_id -> session
     branches: HashMap<String, TimelineBranch>, // branch_id -> branch
+    #[serde(default)]
+    bookmarks: HashMap<String, Bookmark>, // bookmark_id -> bookmark
 }
 
 impl Drop for StorageInner {
@@ -52,6 +57,11 @@ impl Drop for StorageInner {
             branch.zeroize();
         }
         self.branches.clear();
+
+        for bookmark in self.bookmarks.values_mut() {
+            bookmark.zeroize();
+        }
+        self.bookmarks.clear();
     }
 }
 
@@ -191,6 +201,8 @@ pub struct Storage {
     background_handle: Option<thread::JoinHandle<()>>,
     // Pending writes counter for this instance (when not using global storage)
     pending_writes: Option<Arc<AtomicU32>>,
+    // Which file contents are captured into the blob store
+    blob_policy: BlobPolicy,
 }
 
 impl Clone for Storage {
@@ -198,7 +210,7 @@ impl Clone for Storage {
         Self {
             inner: self.inner.clone(),
             persistence_path: self.persistence_path.clone(),
-            encryption_key: self.encryption_key.clone(),
+            encryption_key: self.encryption_key,
             encryption_salt: self.encryption_salt.clone(),
             argon2_config: self.argon2_config.clone(),
             persistence_format: self.persistence_format,
@@ -211,6 +223,7 @@ impl Clone for Storage {
             background_running: self.background_running.clone(),
             background_handle: None, // Cannot clone the background thread handle
             pending_writes: self.pending_writes.clone(),
+            blob_policy: self.blob_policy.clone(),
         }
     }
 }
@@ -272,14 +285,27 @@ impl Storage {
         self.compaction_interval_secs = p.compaction_interval_secs;
     }
 
+    /// Set which file contents `store_file_blob` keeps
+    pub fn set_blob_policy(&mut self, policy: BlobPolicy) {
+        self.blob_policy = policy;
+    }
+
+    /// Get the per-instance blob policy
+    pub fn blob_policy(&self) -> &BlobPolicy {
+        &self.blob_policy
+    }
+
     /// Get the per-instance retention_count
     pub fn retention_count(&self) -> usize {
         self.retention_count
     }
 
     pub fn new() -> crate::Result<Self> {
-        // Best-effort load persisted state for the global storage
-        let _ = Self::load_from_disk();
+        // Best-effort load persisted state for the global storage, except that
+        // data from a newer version must not be overwritten
+        if let Err(e @ crate::error::TimeLoopError::Schema(_)) = Self::load_from_disk() {
+            return Err(e);
+        }
         // adopt global config
         let fmt = global_persistence_format();
         let append = global_append_only();
@@ -301,13 +327,16 @@ impl Storage {
             background_running: None,
             background_handle: None,
             pending_writes: None,
+            blob_policy: BlobPolicy::default(),
         };
         if append {
             // compute events log path for default global persistence file
             let p = Self::persistence_file();
             s.events_log_path = Some(Self::events_log_for(&p, fmt));
             // try to load events from log
-            let _ = s.load_events_from_log();
+            if let Err(e @ crate::error::TimeLoopError::Schema(_)) = s.load_events_from_log() {
+                return Err(e);
+            }
         }
         Ok(s)
     }
@@ -357,28 +386,14 @@ impl Storage {
             background_running: None,
             background_handle: None,
             pending_writes: Some(pending_writes),
+            blob_policy: BlobPolicy::default(),
         };
 
         // If the file exists, load it into the per-instance inner store
         if pb.exists() {
             let bytes = std::fs::read(&pb).ok();
             if let Some(b) = bytes {
-                match format {
-                    PersistenceFormat::Json => {
-                        if let Ok(inner_data) = serde_json::from_slice::<StorageInner>(&b) {
-                            if let Ok(mut guard) = inner.write() {
-                                *guard = inner_data;
-                            }
-                        }
-                    }
-                    PersistenceFormat::Cbor => {
-                        if let Ok(inner_data) = serde_cbor::from_slice::<StorageInner>(&b) {
-                            if let Ok(mut guard) = inner.write() {
-                                *guard = inner_data;
-                            }
-                        }
-                    }
-                }
+                Self::load_snapshot(&inner, &b, format)?;
             }
         }
 
@@ -387,7 +402,9 @@ impl Storage {
             let events_path = Self::events_log_for(&pb, format);
             storage.events_log_path = Some(events_path);
             storage.append_only = true;
-            let _ = storage.load_events_from_log();
+            if let Err(e @ crate::error::TimeLoopError::Schema(_)) = storage.load_events_from_log() {
+                return Err(e);
+            }
         }
         Ok(storage)
     }
@@ -435,12 +452,11 @@ impl Storage {
                                     if let Ok(plain) =
                                         Self::try_decrypt(&key, &nonce_bytes, &ciphertext)
                                     {
-                                        if let Ok(inner_data) =
-                                            serde_json::from_slice::<StorageInner>(&plain)
-                                        {
-                                            if let Ok(mut guard) = inner.write() {
-                                                *guard = inner_data;
-                                            }
+                                        if Self::load_snapshot(
+                                            &inner,
+                                            &plain,
+                                            PersistenceFormat::Json,
+                                        )? {
                                             encryption_key = Some(key);
                                             encryption_salt = Some(salt_bytes);
                                         }
@@ -465,10 +481,7 @@ impl Storage {
                         if let Ok(plain) =
                             Self::try_decrypt(&key, &wrapper_cbor.nonce, &wrapper_cbor.ciphertext)
                         {
-                            if let Ok(inner_data) = serde_cbor::from_slice::<StorageInner>(&plain) {
-                                if let Ok(mut guard) = inner.write() {
-                                    *guard = inner_data;
-                                }
+                            if Self::load_snapshot(&inner, &plain, PersistenceFormat::Cbor)? {
                                 encryption_key = Some(key);
                                 encryption_salt = Some(salt_bytes);
                             }
@@ -492,7 +505,7 @@ impl Storage {
 
         let gp = global_compaction_policy();
         let pending_writes = Arc::new(AtomicU32::new(0));
-        Ok(Self { inner: Some(inner), persistence_path: Some(pb), encryption_key, encryption_salt, argon2_config: Some(params.clone()), persistence_format: format, append_only: false, events_log_path: None, max_log_size_bytes: gp.max_log_size_bytes, max_events: gp.max_events, retention_count: gp.retention_count, compaction_interval_secs: gp.compaction_interval_secs, background_running: None, background_handle: None, pending_writes: Some(pending_writes) })
+        Ok(Self { inner: Some(inner), persistence_path: Some(pb), encryption_key, encryption_salt, argon2_config: Some(params.clone()), persistence_format: format, append_only: false, events_log_path: None, max_log_size_bytes: gp.max_log_size_bytes, max_events: gp.max_events, retention_count: gp.retention_count, compaction_interval_secs: gp.compaction_interval_secs, background_running: None, background_handle: None, pending_writes: Some(pending_writes), blob_policy: BlobPolicy::default() })
     }
 
     // Helper to run read-only closures against the correct storage instance
@@ -593,6 +606,26 @@ impl Storage {
         })
     }
 
+    /// The `limit` most recent commands across all sessions, oldest first
+    pub fn recent_commands(&self, limit: usize) -> crate::Result<Vec<String>> {
+        self.with_read(|guard| {
+            let mut commands: Vec<(DateTime<Utc>, String)> = guard
+                .events
+                .values()
+                .flatten()
+                .filter_map(|e| match &e.event_type {
+                    crate::EventType::Command { command, .. } => {
+                        Some((e.timestamp, command.clone()))
+                    }
+                    _ => None,
+                })
+                .collect();
+            commands.sort_by_key(|(timestamp, _)| *timestamp);
+            let skip = commands.len().saturating_sub(limit);
+            commands.into_iter().skip(skip).map(|(_, c)| c).collect()
+        })
+    }
+
     pub fn clear_session_events(&self, session_id: &str) -> crate::Result<()> {
         self.with_write(|guard| {
             guard.events.remove(session_id);
@@ -655,10 +688,95 @@ impl Storage {
         })
     }
 
+    // Bookmarks
+    pub fn store_bookmark(&self, bookmark: &Bookmark) -> crate::Result<()> {
+        self.with_write(|guard| {
+            guard.bookmarks.insert(bookmark.id.clone(), bookmark.clone());
+        })?;
+        if let Some(path) = &self.persistence_path {
+            let _ = Self::save_to_path(path, self, true);
+        } else if self.inner.is_none() {
+            let _ = Self::save_to_disk(true);
+        }
+        Ok(())
+    }
+
+    pub fn get_bookmarks_for_session(&self, session_id: &str) -> crate::Result<Vec<Bookmark>> {
+        self.with_read(|guard| {
+            let mut bookmarks: Vec<Bookmark> = guard
+                .bookmarks
+                .values()
+                .filter(|b| b.session_id == session_id)
+                .cloned()
+                .collect();
+            bookmarks.sort_by_key(|b| b.created_at);
+            bookmarks
+        })
+    }
+
+    pub fn delete_bookmark(&self, bookmark_id: &str) -> crate::Result<()> {
+        self.with_write(|guard| {
+            guard.bookmarks.remove(bookmark_id);
+        })?;
+        if let Some(path) = &self.persistence_path {
+            let _ = Self::save_to_path(path, self, true);
+        } else if self.inner.is_none() {
+            let _ = Self::save_to_disk(true);
+        }
+        Ok(())
+    }
+
+    /// Store `content` in the blob store and return its SHA-256. Identical
+    /// contents are stored once.
+    pub fn store_blob(&self, content: &[u8]) -> crate::Result<String> {
+        self.blob_store().put(content)
+    }
+
+    /// Store the contents of the file at `path`, unless the blob policy
+    /// excludes it for its size or name. Returns the SHA-256 if it was stored.
+    pub fn store_file_blob(&self, path: &std::path::Path) -> crate::Result<Option<String>> {
+        self.blob_store().put_file(path, &self.blob_policy)
+    }
+
+    /// The contents stored under `hash`, if any
+    pub fn get_blob(&self, hash: &str) -> crate::Result<Option<Vec<u8>>> {
+        self.blob_store().get(hash)
+    }
+
+    pub fn has_blob(&self, hash: &str) -> bool {
+        self.blob_store().contains(hash)
+    }
+
+    /// Whether `path` is one of this storage's own files, such as the state
+    /// file or a blob. Changes to them aren't part of anyone's workspace.
+    pub fn is_storage_path(&self, path: &std::path::Path) -> bool {
+        let Some(state_file) = &self.persistence_path else {
+            return path.starts_with(Self::data_dir());
+        };
+        let temp_file = path.parent() == state_file.parent()
+            && path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with(".tmp_timeloop"));
+        path == state_file
+            || temp_file
+            || path.starts_with(self.blob_store().dir())
+            || self.events_log_path.as_deref() == Some(path)
+    }
+
+    // Blobs live next to the state file: `state.json` keeps them in `state.blobs/`
+    fn blob_store(&self) -> BlobStore {
+        let dir = match &self.persistence_path {
+            Some(path) => path.with_extension("blobs"),
+            None => Self::data_dir().join("blobs"),
+        };
+        BlobStore::new(dir, self.encryption_key)
+    }
+
     pub fn delete_session(&self, session_id: &str) -> crate::Result<()> {
         self.with_write(|guard| {
             guard.events.remove(session_id);
             guard.sessions.remove(session_id);
+            guard.bookmarks.retain(|_, b| b.session_id != session_id);
         })?;
         if let Some(path) = &self.persistence_path {
             let _ = Self::save_to_path(path, self, true);
@@ -689,7 +807,13 @@ impl Storage {
             .get_session(session_id)?
             .ok_or_else(|| crate::error::TimeLoopError::SessionNotFound(session_id.to_string()))?;
         let events = self.get_events_for_session(session_id)?;
-        let bundle = SessionExport { session, events };
+        let bookmarks = self.get_bookmarks_for_session(session_id)?;
+        let bundle = SessionExport {
+            schema_version: SCHEMA_VERSION,
+            session,
+            events,
+            bookmarks,
+        };
 
         // Serialize the data
         let json = serde_json::to_string_pretty(&bundle)?;
@@ -773,12 +897,17 @@ impl Storage {
 
         // Parse as JSON
         let json_str = String::from_utf8(data).map_err(|e| crate::error::TimeLoopError::Storage(format!("Invalid UTF-8 in import file: {}", e)))?;
-        let bundle: SessionExport = serde_json::from_str(&json_str)?;
+        let mut bundle: serde_json::Value = serde_json::from_str(&json_str)?;
+        schema::migrate_export(&mut bundle)?;
+        let bundle: SessionExport = serde_json::from_value(bundle)?;
         let id = bundle.session.id.clone();
         self.store_session(&bundle.session)?;
         for event in &bundle.events {
             self.store_event(event)?;
         }
+        for bookmark in &bundle.bookmarks {
+            self.store_bookmark(bookmark)?;
+        }
         Ok(id)
     }
 
@@ -793,7 +922,7 @@ impl Storage {
 
     // Helper to atomically write bytes to a file path. Writes to a temporary file in
     // the same directory and then renames into place.
-    fn atomic_write(path: &std::path::Path, content: Vec<u8>, sync: bool) -> crate::Result<()> {
+    pub(crate) fn atomic_write(path: &std::path::Path, content: Vec<u8>, sync: bool) -> crate::Result<()> {
         let (tx, rx) = if sync {
             let (tx, rx) = channel();
             (Some(tx), Some(rx))
@@ -835,9 +964,9 @@ impl Storage {
         let guard = GLOBAL_STORAGE
             .read()
             .map_err(|e| crate::error::TimeLoopError::Storage(e.to_string()))?;
-        let data = serde_json::to_string_pretty(&*guard)?;
+        let data = Self::encode_snapshot(&guard, PersistenceFormat::Json)?;
         // atomic write
-        Self::atomic_write(&path, data.into_bytes(), sync)?;
+        Self::atomic_write(&path, data, sync)?;
         Ok(())
     }
 
@@ -1111,8 +1240,12 @@ impl Storage {
 
 #[derive(Serialize, Deserialize)]
 struct SessionExport {
+    #[serde(default)]
+    schema_version: u32,
     session: Session,
     events: Vec<Event>,
+    #[serde(default)]
+    bookmarks: Vec<Bookmark>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -1130,6 +1263,14 @@ struct EncryptedFileCbor {
     ciphertext: Vec<u8>,
 }
 
+// A snapshot as written to disk: the store, preceded by its schema version
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    schema_version: u32,
+    #[serde(flatten)]
+    inner: &'a StorageInner,
+}
+
 impl Storage {
     fn data_dir() -> std::path::PathBuf {
         if cfg!(target_os = "windows") {
@@ -1160,9 +1301,9 @@ impl Storage {
         if !path.exists() {
             return Ok(());
         }
-        let data = fs::read_to_string(&path)
+        let data = fs::read(&path)
             .map_err(|e| crate::error::TimeLoopError::FileSystem(e.to_string()))?;
-        let inner: StorageInner = serde_json::from_str(&data)?;
+        let inner = Self::decode_snapshot(&data, PersistenceFormat::Json)?;
         let mut guard = GLOBAL_STORAGE
             .write()
             .map_err(|e| crate::error::TimeLoopError::Storage(e.to_string()))?;
@@ -1187,10 +1328,7 @@ impl Storage {
         };
 
         // Serialize according to the chosen persistence format
-        let mut data_bytes = match storage.persistence_format {
-            PersistenceFormat::Json => serde_json::to_vec_pretty(&data_inner)?,
-            PersistenceFormat::Cbor => serde_cbor::to_vec(&data_inner)?,
-        };
+        let mut data_bytes = Self::encode_snapshot(&data_inner, storage.persistence_format)?;
 
         // If encryption is enabled on this storage, encrypt the blob and write a wrapper
         if let Some(key) = &storage.encryption_key {
@@ -1233,8 +1371,50 @@ impl Storage {
         Ok(())
     }
 
+    // Serialize a snapshot of `inner` with the current schema version
+    fn encode_snapshot(inner: &StorageInner, format: PersistenceFormat) -> crate::Result<Vec<u8>> {
+        let snapshot = SnapshotRef {
+            schema_version: SCHEMA_VERSION,
+            inner,
+        };
+        Ok(match format {
+            PersistenceFormat::Json => serde_json::to_vec_pretty(&snapshot)?,
+            PersistenceFormat::Cbor => serde_cbor::to_vec(&snapshot)?,
+        })
+    }
+
+    // Parse a snapshot, upgrading it first if it was written by an older version
+    fn decode_snapshot(bytes: &[u8], format: PersistenceFormat) -> crate::Result<StorageInner> {
+        let mut value: serde_json::Value = match format {
+            PersistenceFormat::Json => serde_json::from_slice(bytes)?,
+            PersistenceFormat::Cbor => serde_cbor::from_slice(bytes)?,
+        };
+        schema::migrate_snapshot(&mut value)?;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    // Replace the contents of `inner` with a snapshot and return whether it could
+    // be read. Unreadable data is skipped, but data from a newer version is an
+    // error so that it doesn't get overwritten.
+    fn load_snapshot(
+        inner: &RwLock<StorageInner>,
+        bytes: &[u8],
+        format: PersistenceFormat,
+    ) -> crate::Result<bool> {
+        match Self::decode_snapshot(bytes, format) {
+            Ok(data) => {
+                if let Ok(mut guard) = inner.write() {
+                    *guard = data;
+                }
+                Ok(true)
+            }
+            Err(e @ crate::error::TimeLoopError::Schema(_)) => Err(e),
+            Err(_) => Ok(false),
+        }
+    }
+
     // Encrypt given plaintext with the given key using XChaCha20-Poly1305.
-    fn encrypt_bytes(key: &[u8; KEY_LEN], plaintext: &[u8]) -> crate::Result<(Vec<u8>, Vec<u8>)> {
+    pub(crate) fn encrypt_bytes(key: &[u8; KEY_LEN], plaintext: &[u8]) -> crate::Result<(Vec<u8>, Vec<u8>)> {
         use chacha20poly1305::aead::{Aead, KeyInit};
         use chacha20poly1305::XChaCha20Poly1305;
         use chacha20poly1305::XNonce;
@@ -1247,7 +1427,7 @@ impl Storage {
         Ok((nonce, ciphertext))
     }
 
-    fn try_decrypt(key: &[u8; KEY_LEN], nonce: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, ()> {
+    pub(crate) fn try_decrypt(key: &[u8; KEY_LEN], nonce: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, ()> {
         use chacha20poly1305::aead::{Aead, KeyInit};
         use chacha20poly1305::XChaCha20Poly1305;
         use chacha20poly1305::XNonce;
@@ -1312,7 +1492,7 @@ impl Storage {
         };
 
         // Serialize into bytes then encrypt with a newly-derived key
-        let mut data_bytes = serde_json::to_vec_pretty(&data_inner)?;
+        let mut data_bytes = Self::encode_snapshot(&data_inner, PersistenceFormat::Json)?;
 
         // Generate new salt and derive new key
         let salt = Self::generate_random_bytes(SALT_LEN)?;
@@ -1333,6 +1513,7 @@ impl Storage {
         };
         let wrapper_json = serde_json::to_string_pretty(&wrapper)?;
         Self::atomic_write(path, wrapper_json.into_bytes(), true)?;
+        self.blob_store().rekey(&new_key)?;
 
         // Zeroize and replace old key material
         if let Some(mut old_key) = self.encryption_key.take() {
@@ -1389,12 +1570,24 @@ impl Storage {
             return Ok(());
         }
 
+        // Logs written before versions were recorded have no header
+        let mut version = 0;
+        let mut values: Vec<serde_json::Value> = Vec::new();
+        // Encrypted entries that can't be read without a key
+        let mut skipped = false;
         if self.persistence_format == PersistenceFormat::Json {
             let file = std::fs::File::open(&path)
                 .map_err(|e| crate::error::TimeLoopError::FileSystem(e.to_string()))?;
             let reader = std::io::BufReader::new(file);
-            for line in reader.lines() {
+            for (i, line) in reader.lines().enumerate() {
                 let l = line.map_err(|e| crate::error::TimeLoopError::FileSystem(e.to_string()))?;
+                if i == 0 {
+                    if let Ok(header) = serde_json::from_str::<LogHeader>(&l) {
+                        schema::check_version(header.schema_version)?;
+                        version = header.schema_version;
+                        continue;
+                    }
+                }
                 // Check if encrypted entry (JSON object with nonce/ciphertext) or plain event
                 if let Ok(wrapper) = serde_json::from_str::<EncryptedEventJson>(&l) {
                     // encrypted
@@ -1408,20 +1601,19 @@ impl Storage {
                         let plain = Self::try_decrypt(key, &nonce, &ciphertext).map_err(|_| {
                             crate::error::TimeLoopError::Storage("decryption failed".to_string())
                         })?;
-                        let event: Event = serde_json::from_slice(&plain)?;
-                        // insert event
-                        self.with_write(|g| { g.events.entry(event.session_id.clone()).or_default().push(event); })?;
+                        values.push(serde_json::from_slice(&plain)?);
+                    } else {
+                        skipped = true;
                     }
                 } else {
-                    let event: Event = serde_json::from_str(&l)?;
-                    // insert event
-                    self.with_write(|g| { g.events.entry(event.session_id.clone()).or_default().push(event); })?;
+                    values.push(serde_json::from_str(&l)?);
                 }
             }
         } else {
             // CBOR log: length-prefixed records: u32 LE length followed by bytes. Or encrypted CBOR wrapper entries.
             let mut file = std::fs::File::open(&path)
                 .map_err(|e| crate::error::TimeLoopError::FileSystem(e.to_string()))?;
+            let mut first = true;
             loop {
                 let mut len_buf = [0u8; 4];
                 if file.read_exact(&mut len_buf).is_err() { break; }
@@ -1429,6 +1621,13 @@ impl Storage {
                 let mut buf = vec![0u8; len];
                 file.read_exact(&mut buf)
                     .map_err(|e| crate::error::TimeLoopError::FileSystem(e.to_string()))?;
+                if std::mem::take(&mut first) {
+                    if let Ok(header) = serde_cbor::from_slice::<LogHeader>(&buf) {
+                        schema::check_version(header.schema_version)?;
+                        version = header.schema_version;
+                        continue;
+                    }
+                }
                 // attempt to deserialize as EncryptedEventCbor first
                 if let Ok(wrapper) = serde_cbor::from_slice::<EncryptedEventCbor>(&buf) {
                     if let Some(key) = &self.encryption_key {
@@ -1438,17 +1637,36 @@ impl Storage {
                                     "decryption failed".to_string(),
                                 )
                             })?;
-                        let event: Event = serde_cbor::from_slice(&plain)?;
-                        self.with_write(|g| { g.events.entry(event.session_id.clone()).or_default().push(event); })?;
+                        values.push(serde_cbor::from_slice(&plain)?);
+                    } else {
+                        skipped = true;
                     }
                 } else {
                     // treat as raw CBOR Event
-                    let event: Event = serde_cbor::from_slice(&buf)?;
-                    self.with_write(|g| { g.events.entry(event.session_id.clone()).or_default().push(event); })?;
+                    values.push(serde_cbor::from_slice(&buf)?);
                 }
             }
         }
 
+        let mut events = Vec::with_capacity(values.len());
+        for mut value in values {
+            schema::migrate_event(&mut value, version)?;
+            let event: Event = serde_json::from_value(value)?;
+            self.with_write(|g| { g.events.entry(event.session_id.clone()).or_default().push(event.clone()); })?;
+            events.push(event);
+        }
+
+        // Upgrade an older log in place, so that events appended from now on
+        // aren't mixed with ones in the old format. Entries that couldn't be
+        // read would be lost, so such a log is left as it is.
+        if version < SCHEMA_VERSION && !skipped && !events.is_empty() {
+            let mut content = self.encode_log_header()?;
+            for event in &events {
+                content.extend(self.encode_log_event(event)?);
+            }
+            Self::atomic_write(&path, content, true)?;
+        }
+
         Ok(())
     }
 
@@ -1458,13 +1676,46 @@ impl Storage {
             None => return Ok(()),
         };
 
-        if self.persistence_format == PersistenceFormat::Json {
-            let mut file = std::fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(&path)
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| crate::error::TimeLoopError::FileSystem(e.to_string()))?;
+        // A new (or freshly rotated) log starts with its schema version
+        let empty = file
+            .metadata()
+            .map_err(|e| crate::error::TimeLoopError::FileSystem(e.to_string()))?
+            .len()
+            == 0;
+        if empty {
+            file.write_all(&self.encode_log_header()?)
                 .map_err(|e| crate::error::TimeLoopError::FileSystem(e.to_string()))?;
-            if let Some(key) = &self.encryption_key {
+        }
+        file.write_all(&self.encode_log_event(event)?)
+            .map_err(|e| crate::error::TimeLoopError::FileSystem(e.to_string()))?;
+        file.flush()
+            .map_err(|e| crate::error::TimeLoopError::FileSystem(e.to_string()))?;
+        Ok(())
+    }
+
+    // The header record that starts an events log
+    fn encode_log_header(&self) -> crate::Result<Vec<u8>> {
+        let header = LogHeader::current();
+        match self.persistence_format {
+            PersistenceFormat::Json => {
+                let mut line = serde_json::to_vec(&header)?;
+                line.push(b'\n');
+                Ok(line)
+            }
+            PersistenceFormat::Cbor => Ok(Self::length_prefixed(serde_cbor::to_vec(&header)?)),
+        }
+    }
+
+    // One event as an entry of the events log: a line of JSON or a length-prefixed
+    // CBOR record, wrapped in an encrypted envelope when the storage is encrypted
+    fn encode_log_event(&self, event: &Event) -> crate::Result<Vec<u8>> {
+        if self.persistence_format == PersistenceFormat::Json {
+            let mut line = if let Some(key) = &self.encryption_key {
                 // encrypt event JSON bytes
                 let plain = serde_json::to_vec(event)?;
                 let (nonce, ciphertext) = Self::encrypt_bytes(key, &plain)?;
@@ -1472,48 +1723,26 @@ impl Storage {
                     nonce: general_purpose::STANDARD.encode(&nonce),
                     ciphertext: general_purpose::STANDARD.encode(&ciphertext),
                 };
-                let line = serde_json::to_string(&wrapper)?;
-                file.write_all(line.as_bytes())
-                    .map_err(|e| crate::error::TimeLoopError::FileSystem(e.to_string()))?;
-                file.write_all(b"\n")
-                    .map_err(|e| crate::error::TimeLoopError::FileSystem(e.to_string()))?;
+                serde_json::to_vec(&wrapper)?
             } else {
-                let line = serde_json::to_string(event)?;
-                file.write_all(line.as_bytes())
-                    .map_err(|e| crate::error::TimeLoopError::FileSystem(e.to_string()))?;
-                file.write_all(b"\n")
-                    .map_err(|e| crate::error::TimeLoopError::FileSystem(e.to_string()))?;
-            }
-            file.flush()
-                .map_err(|e| crate::error::TimeLoopError::FileSystem(e.to_string()))?;
+                serde_json::to_vec(event)?
+            };
+            line.push(b'\n');
+            Ok(line)
+        } else if let Some(key) = &self.encryption_key {
+            let plain = serde_cbor::to_vec(event)?;
+            let (nonce, ciphertext) = Self::encrypt_bytes(key, &plain)?;
+            let wrapper = EncryptedEventCbor { nonce, ciphertext };
+            Ok(Self::length_prefixed(serde_cbor::to_vec(&wrapper)?))
         } else {
-            let mut file = std::fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(&path)
-                .map_err(|e| crate::error::TimeLoopError::FileSystem(e.to_string()))?;
-            if let Some(key) = &self.encryption_key {
-                let plain = serde_cbor::to_vec(event)?;
-                let (nonce, ciphertext) = Self::encrypt_bytes(key, &plain)?;
-                let wrapper = EncryptedEventCbor { nonce, ciphertext };
-                let buf = serde_cbor::to_vec(&wrapper)?;
-                let len = (buf.len() as u32).to_le_bytes();
-                file.write_all(&len)
-                    .map_err(|e| crate::error::TimeLoopError::FileSystem(e.to_string()))?;
-                file.write_all(&buf)
-                    .map_err(|e| crate::error::TimeLoopError::FileSystem(e.to_string()))?;
-            } else {
-                let buf = serde_cbor::to_vec(event)?;
-                let len = (buf.len() as u32).to_le_bytes();
-                file.write_all(&len)
-                    .map_err(|e| crate::error::TimeLoopError::FileSystem(e.to_string()))?;
-                file.write_all(&buf)
-                    .map_err(|e| crate::error::TimeLoopError::FileSystem(e.to_string()))?;
-            }
-            file.flush()
-                .map_err(|e| crate::error::TimeLoopError::FileSystem(e.to_string()))?;
+            Ok(Self::length_prefixed(serde_cbor::to_vec(event)?))
         }
-        Ok(())
+    }
+
+    fn length_prefixed(buf: Vec<u8>) -> Vec<u8> {
+        let mut record = (buf.len() as u32).to_le_bytes().to_vec();
+        record.extend(buf);
+        record
     }
 }
 
@@ -1543,11 +1772,10 @@ impl Default for CompactionPolicy {
 }
 
 fn global_persistence_format() -> PersistenceFormat {
-    GLOBAL_PERSISTENCE_FORMAT
+    *GLOBAL_PERSISTENCE_FORMAT
         .get_or_init(|| RwLock::new(PersistenceFormat::Json))
         .read()
         .unwrap()
-        .clone()
 }
 
 fn global_append_only() -> bool {
@@ -1558,11 +1786,10 @@ fn global_append_only() -> bool {
 }
 
 fn global_compaction_policy() -> CompactionPolicy {
-    GLOBAL_COMPACTION_POLICY
+    *GLOBAL_COMPACTION_POLICY
         .get_or_init(|| RwLock::new(CompactionPolicy::default()))
         .read()
         .unwrap()
-        .clone()
 }
 
 #[allow(dead_code)]
@@ -1624,6 +1851,7 @@ mod tests {
             ended_at: None,
             parent_session_id: None,
             branch_name: None,
+            ..Default::default()
         };
 
         storage.store_session(&session).unwrap();
@@ -1647,6 +1875,22 @@ mod tests {
         assert_eq!(events.len(), 1);
     }
 
+    #[test]
+    fn test_recent_commands_across_sessions() {
+        let tmp_dir = TempDir::new().unwrap();
+        let state_file = tmp_dir.path().join("state.json");
+        let storage = Storage::with_path(state_file.to_str().unwrap()).unwrap();
+
+        for (session, command) in [("a", "ls"), ("b", "make"), ("a", "git status")] {
+            let mut recorder =
+                crate::EventRecorder::with_storage(session, storage.clone());
+            recorder.record_command(command, "", 0, "/tmp").unwrap();
+        }
+
+        assert_eq!(storage.recent_commands(10).unwrap(), vec!["ls", "make", "git status"]);
+        assert_eq!(storage.recent_commands(2).unwrap(), vec!["make", "git status"]);
+    }
+
     #[test]
     fn test_persistence_roundtrip() {
         let tmp_dir = TempDir::new().unwrap();
@@ -1665,6 +1909,7 @@ mod tests {
             ended_at: None,
             parent_session_id: None,
             branch_name: None,
+            ..Default::default()
         };
         storage.store_session(&session).unwrap();
 
@@ -1761,6 +2006,7 @@ mod tests {
             ended_at: None,
             parent_session_id: None,
             branch_name: None,
+            ..Default::default()
         };
         storage1.store_session(&session).unwrap();
         storage1.flush().unwrap();
@@ -1787,6 +2033,7 @@ mod tests {
             ended_at: None,
             parent_session_id: None,
             branch_name: None,
+            ..Default::default()
         };
         storage.store_session(&session).unwrap();
         storage.flush().unwrap();
@@ -1804,6 +2051,44 @@ mod tests {
         assert!(err.is_err());
     }
 
+    #[test]
+    fn test_encrypted_blobs_survive_passphrase_change() {
+        let tmp_dir = TempDir::new().unwrap();
+        let state_file = tmp_dir.path().join("state.json");
+        let mut storage =
+            Storage::with_encryption(state_file.to_str().unwrap(), "oldpass").unwrap();
+
+        let hash = storage.store_blob(b"fn main() {}").unwrap();
+        assert!(storage.has_blob(&hash));
+        // Neither the contents nor their hash appear on disk
+        let blob_dir = tmp_dir.path().join("state.blobs");
+        let files: Vec<_> = walkdir(&blob_dir);
+        assert_eq!(files.len(), 1);
+        assert!(!files[0].to_string_lossy().contains(&hash[2..]));
+        assert!(!std::fs::read(&files[0]).unwrap().windows(7).any(|w| w == b"fn main"));
+
+        storage.change_passphrase("newpass").unwrap();
+        assert_eq!(storage.get_blob(&hash).unwrap().unwrap(), b"fn main() {}");
+        assert_eq!(walkdir(&blob_dir).len(), 1);
+
+        let plain = Storage::with_path(tmp_dir.path().join("plain.json").to_str().unwrap()).unwrap();
+        assert!(plain.get_blob(&hash).unwrap().is_none());
+        assert!(plain.get_blob("../state.json").unwrap().is_none());
+    }
+
+    fn walkdir(dir: &std::path::Path) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                files.extend(walkdir(&path));
+            } else {
+                files.push(path);
+            }
+        }
+        files
+    }
+
     #[test]
     fn test_cbor_roundtrip() {
         let tmp_dir = TempDir::new().unwrap();
@@ -1818,6 +2103,7 @@ mod tests {
             ended_at: None,
             parent_session_id: None,
             branch_name: None,
+            ..Default::default()
         };
         storage.store_session(&session).unwrap();
 
@@ -1905,6 +2191,7 @@ mod tests {
             ended_at: None,
             parent_session_id: None,
             branch_name: None,
+            ..Default::default()
         };
         storage.store_session(&session).unwrap();
         storage.flush().unwrap();
@@ -1939,6 +2226,7 @@ mod tests {
             ended_at: None,
             parent_session_id: None,
             branch_name: None,
+            ..Default::default()
         };
         storage.store_session(&session).unwrap();
 
@@ -1979,6 +2267,7 @@ mod tests {
             ended_at: None,
             parent_session_id: None,
             branch_name: None,
+            ..Default::default()
         };
         storage.store_session(&session).unwrap();
 
@@ -2072,7 +2361,7 @@ mod tests {
                 rots.push(p);
             }
         }
-        assert!(rots.len() <= storage.retention_count as usize + 1); // +1 tolerant
+        assert!(rots.len() <= storage.retention_count + 1); // +1 tolerant
     }
 
     #[test]
@@ -2092,6 +2381,7 @@ mod tests {
             ended_at: None,
             parent_session_id: None,
             branch_name: None,
+            ..Default::default()
         };
         storage.store_session(&session).unwrap();
 
@@ -2130,6 +2420,7 @@ mod tests {
             ended_at: None,
             parent_session_id: None,
             branch_name: None,
+            ..Default::default()
         };
         storage.store_session(&session).unwrap();
 
@@ -2199,4 +2490,221 @@ mod tests {
         let result = storage.get_last_n_events(session_id, 0).unwrap();
         assert!(result.is_empty());
     }
+
+    const STATE_V0: &str = include_str!("../tests/fixtures/state-v0.json");
+    const STATE_V1: &str = include_str!("../tests/fixtures/state-v1.json");
+    const EVENTS_V0: &str = include_str!("../tests/fixtures/events-v0.jsonl");
+    const EVENTS_V1: &str = include_str!("../tests/fixtures/events-v1.jsonl");
+    const EVENTS_V2: &str = include_str!("../tests/fixtures/events-v2.jsonl");
+
+    fn fast_argon2() -> Argon2Config {
+        Argon2Config {
+            memory_kib: 1024,
+            iterations: 1,
+            parallelism: 1,
+        }
+    }
+
+    fn file_schema_version(path: &std::path::Path) -> serde_json::Value {
+        let bytes = std::fs::read(path).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&bytes)
+            .unwrap_or_else(|_| serde_cbor::from_slice(&bytes).unwrap());
+        value["schema_version"].clone()
+    }
+
+    #[test]
+    fn test_loads_snapshot_fixtures() {
+        let tmp_dir = TempDir::new().unwrap();
+        for (name, fixture, count) in [("v0", STATE_V0, 4), ("v1", STATE_V1, 5)] {
+            let value: serde_json::Value = serde_json::from_str(fixture).unwrap();
+            let json_file = tmp_dir.path().join(format!("state-{}.json", name));
+            std::fs::write(&json_file, fixture).unwrap();
+            let cbor_file = tmp_dir.path().join(format!("state-{}.cbor", name));
+            std::fs::write(&cbor_file, serde_cbor::to_vec(&value).unwrap()).unwrap();
+
+            for file in [&json_file, &cbor_file] {
+                let path = file.to_str().unwrap();
+                let storage = Storage::with_path(path).unwrap();
+                let events = storage.get_events_for_session("fixture-session").unwrap();
+                assert_eq!(events.len(), count, "{}", path);
+                assert_eq!(storage.get_session("fixture-session").unwrap().unwrap().name, "fixture");
+
+                // Writing stores the current version
+                storage.flush().unwrap();
+                assert_eq!(file_schema_version(file), SCHEMA_VERSION);
+                let reopened = Storage::with_path(path).unwrap();
+                assert_eq!(reopened.get_events_for_session("fixture-session").unwrap(), events);
+            }
+        }
+
+        // Fields added since version 0 take their defaults
+        let v0 = tmp_dir.path().join("state-v0.json");
+        let storage = Storage::with_path(v0.to_str().unwrap()).unwrap();
+        let events = storage.get_events_for_session("fixture-session").unwrap();
+        let EventType::Command { stderr, output_chunks, duration_ms, .. } = &events[2].event_type else {
+            panic!("expected a Command, got {:?}", events[2].event_type);
+        };
+        assert!(stderr.is_empty() && output_chunks.is_empty() && duration_ms.is_none());
+        assert!(storage.get_bookmarks_for_session("fixture-session").unwrap().is_empty());
+        assert!(storage.get_session("fixture-session").unwrap().unwrap().tags.is_empty());
+
+        let v1 = tmp_dir.path().join("state-v1.json");
+        let storage = Storage::with_path(v1.to_str().unwrap()).unwrap();
+        assert_eq!(storage.get_bookmarks_for_session("fixture-session").unwrap().len(), 1);
+        assert_eq!(storage.list_branches().unwrap().len(), 1);
+        let events = storage.get_events_for_session("fixture-session").unwrap();
+        assert!(matches!(&events[2].event_type, EventType::FileChange { diff: Some(_), .. }));
+
+        // Data from a newer version is refused rather than dropped and overwritten
+        let mut newer: serde_json::Value = serde_json::from_str(STATE_V1).unwrap();
+        newer["schema_version"] = (SCHEMA_VERSION + 1).into();
+        let newer_file = tmp_dir.path().join("state-newer.json");
+        std::fs::write(&newer_file, newer.to_string()).unwrap();
+        let result = Storage::with_path(newer_file.to_str().unwrap());
+        assert!(matches!(result, Err(crate::error::TimeLoopError::Schema(_))));
+        assert_eq!(std::fs::read_to_string(&newer_file).unwrap(), newer.to_string());
+    }
+
+    #[test]
+    fn test_loads_encrypted_snapshot_fixtures() {
+        let tmp_dir = TempDir::new().unwrap();
+        let params = fast_argon2();
+        let salt = Storage::generate_random_bytes(SALT_LEN).unwrap();
+        let key = Storage::derive_key_with_params("fixture-pass", &salt, Some(&params));
+        let json_wrapper = |plain: &[u8]| {
+            let (nonce, ciphertext) = Storage::encrypt_bytes(&key, plain).unwrap();
+            let wrapper = EncryptedFile {
+                salt: general_purpose::STANDARD.encode(&salt),
+                nonce: general_purpose::STANDARD.encode(&nonce),
+                ciphertext: general_purpose::STANDARD.encode(&ciphertext),
+            };
+            serde_json::to_vec(&wrapper).unwrap()
+        };
+
+        for (name, fixture, count) in [("v0", STATE_V0, 4), ("v1", STATE_V1, 5)] {
+            let json_file = tmp_dir.path().join(format!("enc-{}.json", name));
+            std::fs::write(&json_file, json_wrapper(fixture.as_bytes())).unwrap();
+
+            let value: serde_json::Value = serde_json::from_str(fixture).unwrap();
+            let (nonce, ciphertext) =
+                Storage::encrypt_bytes(&key, &serde_cbor::to_vec(&value).unwrap()).unwrap();
+            let wrapper = EncryptedFileCbor {
+                salt: salt.clone(),
+                nonce,
+                ciphertext,
+            };
+            let cbor_file = tmp_dir.path().join(format!("enc-{}.cbor", name));
+            std::fs::write(&cbor_file, serde_cbor::to_vec(&wrapper).unwrap()).unwrap();
+
+            for (file, format) in [(&json_file, PersistenceFormat::Json), (&cbor_file, PersistenceFormat::Cbor)] {
+                let path = file.to_str().unwrap();
+                let open = || {
+                    Storage::with_encryption_with_params_and_format(path, "fixture-pass", &params, format)
+                        .unwrap()
+                };
+                let storage = open();
+                assert_eq!(storage.get_events_for_session("fixture-session").unwrap().len(), count, "{}", path);
+                storage.flush().unwrap();
+                assert_eq!(open().get_events_for_session("fixture-session").unwrap().len(), count);
+            }
+        }
+
+        let mut newer: serde_json::Value = serde_json::from_str(STATE_V1).unwrap();
+        newer["schema_version"] = (SCHEMA_VERSION + 1).into();
+        let newer_file = tmp_dir.path().join("enc-newer.json");
+        std::fs::write(&newer_file, json_wrapper(newer.to_string().as_bytes())).unwrap();
+        let result = Storage::with_encryption_with_params_and_format(
+            newer_file.to_str().unwrap(),
+            "fixture-pass",
+            &params,
+            PersistenceFormat::Json,
+        );
+        assert!(matches!(result, Err(crate::error::TimeLoopError::Schema(_))));
+    }
+
+    #[test]
+    fn test_loads_event_log_fixtures() {
+        let tmp_dir = TempDir::new().unwrap();
+        let header = format!("{{\"schema_version\":{}}}", SCHEMA_VERSION);
+
+        for (name, fixture, count) in [("v0", EVENTS_V0, 2), ("v1", EVENTS_V1, 2), ("v2", EVENTS_V2, 3)] {
+            let state_file = tmp_dir.path().join(format!("log-{}.json", name));
+            let mut storage = Storage::with_path(state_file.to_str().unwrap()).unwrap();
+            storage.enable_append_only();
+            let log = storage.events_log_path.clone().unwrap();
+            std::fs::write(&log, fixture).unwrap();
+            storage.load_events_from_log().unwrap();
+            assert_eq!(storage.get_events_for_session("log-session").unwrap().len(), count, "{}", name);
+
+            // An old log is upgraded in place, and new events follow it
+            let event = Event::new(
+                "log-session",
+                EventType::KeyPress {
+                    key: "q".to_string(),
+                    timestamp: Utc::now(),
+                },
+                count as u64 + 1,
+            );
+            storage.store_event(&event).unwrap();
+            let content = std::fs::read_to_string(&log).unwrap();
+            assert_eq!(content.lines().next(), Some(header.as_str()), "{}", name);
+            assert_eq!(content.lines().count(), count + 2);
+        }
+
+        // A legacy CBOR log, and an encrypted legacy JSON log
+        let params = fast_argon2();
+        for format in [PersistenceFormat::Cbor, PersistenceFormat::Json] {
+            let encrypted = format == PersistenceFormat::Json;
+            let state_file = tmp_dir.path().join(if encrypted { "log-v0-enc.json" } else { "log-v0.cbor" });
+            let path = state_file.to_str().unwrap();
+            let mut storage = if encrypted {
+                Storage::with_encryption_with_params_and_format(path, "fixture-pass", &params, format).unwrap()
+            } else {
+                Storage::with_path(path).unwrap()
+            };
+            storage.enable_append_only();
+            let log = storage.events_log_path.clone().unwrap();
+            let mut content = Vec::new();
+            for line in EVENTS_V0.lines() {
+                if let Some(key) = &storage.encryption_key {
+                    let (nonce, ciphertext) = Storage::encrypt_bytes(key, line.as_bytes()).unwrap();
+                    let wrapper = EncryptedEventJson {
+                        nonce: general_purpose::STANDARD.encode(&nonce),
+                        ciphertext: general_purpose::STANDARD.encode(&ciphertext),
+                    };
+                    content.extend(serde_json::to_vec(&wrapper).unwrap());
+                    content.push(b'\n');
+                } else {
+                    let value: serde_json::Value = serde_json::from_str(line).unwrap();
+                    content.extend(Storage::length_prefixed(serde_cbor::to_vec(&value).unwrap()));
+                }
+            }
+            std::fs::write(&log, content).unwrap();
+            storage.load_events_from_log().unwrap();
+            assert_eq!(storage.get_events_for_session("log-session").unwrap().len(), 2);
+
+            // The upgraded log stays encrypted and reads back the same
+            let upgraded = std::fs::read(&log).unwrap();
+            if encrypted {
+                assert!(upgraded.starts_with(header.as_bytes()));
+                assert!(!upgraded.windows(4).any(|w| w == b"make"));
+            }
+            storage.with_write(|g| g.events.clear()).unwrap();
+            storage.load_events_from_log().unwrap();
+            assert_eq!(storage.get_events_for_session("log-session").unwrap().len(), 2);
+            assert_eq!(std::fs::read(&log).unwrap(), upgraded);
+        }
+
+        // A log from a newer version is refused
+        let state_file = tmp_dir.path().join("log-newer.json");
+        let mut storage = Storage::with_path(state_file.to_str().unwrap()).unwrap();
+        storage.enable_append_only();
+        let events = EVENTS_V2.lines().skip(1).collect::<Vec<_>>().join("\n");
+        let newer = format!("{{\"schema_version\":{}}}\n{}\n", SCHEMA_VERSION + 1, events);
+        std::fs::write(storage.events_log_path.as_ref().unwrap(), newer).unwrap();
+        assert!(matches!(
+            storage.load_events_from_log(),
+            Err(crate::error::TimeLoopError::Schema(_))
+        ));
+    }
 }
@@ -1,74 +1,122 @@
+use crate::bookmark::BookmarkManager;
+use crate::completion::Completer;
 use crate::file_watcher::FileWatcher;
-use crate::{EventRecorder, FileChangeType, TimeLoopError};
+use crate::line_editor::{self, ReadLine};
+use crate::meta_command::{MetaCommand, META_COMMANDS};
+#[cfg(unix)]
+use crate::pty::SignalForwarder;
+use crate::process::ProcessExit;
+use crate::pty::{spawn_reader, terminal_output_to_text, InputForwarder, PtyInput, PtyProcess};
+use crate::shell::ShellSession;
+use crate::shell_adapter::{quote_word, ShellKind};
+use crate::events::{CommandRecord, OutputChunkCollector, OutputStream, FILE_WATCH_SETTLE};
+use crate::{
+    BranchManager, EventRecorder, EventType, FileChangeType, ReplayEngine, SessionManager,
+    TimeLoopError,
+};
+use chrono::{DateTime, Utc};
 use crossterm::{
     style::{Color, ResetColor, SetForegroundColor},
     terminal::{disable_raw_mode, enable_raw_mode},
     ExecutableCommand,
 };
 use std::collections::VecDeque;
-use std::io::{self, Write};
+use std::io::{self, IsTerminal, Write};
 use std::path::PathBuf;
-use std::process::Stdio;
 use std::sync::{Arc, Mutex};
-use tokio::process::Command;
+use std::time::Duration;
+use tokio::sync::mpsc;
 use tokio::task::JoinHandle;
 
+/// Number of commands kept for the prompt's history
+const HISTORY_SIZE: usize = 100;
+
+
+/// Commands handled by TimeLoop itself rather than the shell
+const BUILTIN_COMMANDS: &[&str] = &["exit", "quit", "incognito"];
+
 pub struct TerminalEmulator {
     pub(crate) event_recorder: Arc<Mutex<EventRecorder>>,
     working_directory: String,
     file_watcher_handle: Option<JoinHandle<()>>,
     // Command history with a maximum size
     command_history: VecDeque<String>,
+    // Which shell runs the commands
+    shell_kind: ShellKind,
+    // Long-lived shell that runs every command, started on first use
+    shell: Option<ShellSession>,
+    // Variables the session's commands exported, set again in a new shell
+    environment: Vec<String>,
+    // Whether starting file watching records a baseline of the watched files
+    baseline_snapshot: bool,
 }
 
 impl TerminalEmulator {
     pub fn new(event_recorder: EventRecorder) -> crate::Result<Self> {
+        Self::with_shell(event_recorder, ShellKind::platform_default())
+    }
+
+    pub fn with_shell(event_recorder: EventRecorder, shell_kind: ShellKind) -> crate::Result<Self> {
         let working_directory = std::env::current_dir()?.to_string_lossy().to_string();
 
         Ok(Self {
             event_recorder: Arc::new(Mutex::new(event_recorder)),
             working_directory,
             file_watcher_handle: None,
-            command_history: VecDeque::with_capacity(100), // Store up to 100 commands
+            command_history: VecDeque::with_capacity(HISTORY_SIZE),
+            shell_kind,
+            shell: None,
+            environment: Vec::new(),
+            baseline_snapshot: true,
         })
     }
 
-    /// Start file watching for the current directory
+    /// Run commands in `dir` instead of the directory TimeLoop started in
+    pub fn set_working_directory(&mut self, dir: &str) {
+        self.working_directory = dir.to_string();
+    }
+
+    /// Export `environment`, `NAME=value` variables as recorded with a
+    /// command, in the shell the commands run in
+    pub fn set_environment(&mut self, environment: Vec<String>) {
+        self.environment = environment;
+    }
+
+    /// Record the watched files as a baseline when file watching starts (on
+    /// by default)
+    pub fn set_baseline_snapshot(&mut self, enabled: bool) {
+        self.baseline_snapshot = enabled;
+    }
+
+    /// Start file watching for the current directory, after recording the
+    /// files already there as the baseline
     pub(crate) async fn start_file_watching(&mut self) -> crate::Result<()> {
         let watch_path = PathBuf::from(&self.working_directory);
         let recorder = self.event_recorder.clone();
-        println!("📁 File watching started for: {}", self.working_directory);
 
-        let handle = tokio::spawn(async move {
-            // Create callback closure to record file changes
-            let cb: crate::file_watcher::FileChangeCallback = {
-                let recorder = recorder.clone();
-                Arc::new(tokio::sync::Mutex::new(
-                    move |path: &str, change: FileChangeType| {
-                        // Synchronous closure: use std::sync::Mutex to mutate recorder
-                        if let Ok(mut guard) = recorder.lock() {
-                            if let Err(e) = guard.record_file_change(path, change) {
-                                eprintln!("Error recording file change: {}", e);
-                            }
-                        }
-                        Ok(())
-                    },
-                ))
-            };
-
-            let mut watcher = match FileWatcher::new(cb) {
-                Ok(w) => w,
-                Err(e) => {
-                    eprintln!("Failed to init file watcher: {}", e);
-                    return;
+        // Create callback closure to record file changes
+        let cb: crate::file_watcher::FileChangeCallback = Arc::new(tokio::sync::Mutex::new(
+            move |path: &str, change: FileChangeType| {
+                // Synchronous closure: use std::sync::Mutex to mutate recorder
+                if let Ok(mut guard) = recorder.lock() {
+                    if let Err(e) = guard.record_file_change(path, change) {
+                        eprintln!("Error recording file change: {}", e);
+                    }
                 }
-            };
-
-            if let Err(e) = watcher.add_watch_path(watch_path.clone(), true) {
-                eprintln!("Failed to add watch path: {}", e);
-                return;
+                Ok(())
+            },
+        ));
+        let mut watcher = FileWatcher::new(cb)?;
+        watcher.add_watch_path(watch_path.clone(), true)?;
+
+        if self.baseline_snapshot {
+            let files = watcher.scan();
+            if let Ok(mut guard) = self.event_recorder.lock() {
+                guard.record_baseline(&watch_path, &files)?;
             }
+        }
 
+        let handle = tokio::spawn(async move {
             if let Err(e) = watcher.start_watching().await {
                 eprintln!("File watching stopped with error: {}", e);
             }
@@ -91,18 +139,22 @@ impl TerminalEmulator {
     }
 
     pub async fn run(&mut self) -> crate::Result<()> {
-        // Enable raw mode to capture keystrokes and resize events
-        enable_raw_mode()?;
-
         // Record initial terminal state
         let (cols, rows) = crossterm::terminal::size()?;
         if let Ok(mut guard) = self.event_recorder.lock() {
+            let session = guard.storage().get_session(guard.session_id())?;
+            let (name, created_at) = match session {
+                Some(session) => (session.name, session.created_at),
+                None => (guard.session_id().to_string(), Utc::now()),
+            };
+            guard.record_session_metadata(&name, created_at, Some(self.shell_kind))?;
             guard.record_terminal_state((0, 0), (cols, rows))?;
         }
 
         // Start file watching
-        if let Err(e) = self.start_file_watching().await {
-            eprintln!("Warning: Could not start file watching: {}", e);
+        match self.start_file_watching().await {
+            Ok(()) => println!("📁 File watching started for: {}", self.working_directory),
+            Err(e) => eprintln!("Warning: Could not start file watching: {}", e),
         }
 
         // Print welcome message with styling
@@ -111,12 +163,8 @@ impl TerminalEmulator {
         println!("╔════════════════════════════════════════════════════╗");
         println!("║                                                    ║");
         stdout.execute(SetForegroundColor(Color::Blue))?;
-
-        if cfg!(target_os = "windows") {
-            println!("║            TimeLoop Terminal - PowerShell          ║");
-        } else {
-            println!("║              TimeLoop Terminal - Bash              ║");
-        }
+        let title = format!("TimeLoop Terminal - {}", self.shell_kind.display_name());
+        println!("║{:^52}║", title);
 
         stdout.execute(SetForegroundColor(Color::Cyan))?;
         println!("║                                                    ║");
@@ -125,12 +173,17 @@ impl TerminalEmulator {
 
         // Print help info
         stdout.execute(SetForegroundColor(Color::Yellow))?;
-        println!("Type 'exit' to quit | All shell commands are supported");
+        println!("Type 'exit' to quit | ':help' for TimeLoop commands | All shell commands are supported");
         stdout.execute(ResetColor)?;
         println!("─────────────────────────────────────────────────────");
 
-        // Main loop using standard input
-        let stdin = io::stdin();
+        // Seed the prompt's history with commands from earlier sessions
+        if let Ok(guard) = self.event_recorder.lock() {
+            if let Ok(commands) = guard.storage().recent_commands(HISTORY_SIZE) {
+                self.command_history.extend(commands);
+            }
+        }
+
         let mut stdout = io::stdout();
 
         let result = loop {
@@ -141,35 +194,31 @@ impl TerminalEmulator {
                 false
             };
 
-            // Display styled prompt
-            if is_incognito {
-                stdout.execute(SetForegroundColor(Color::Magenta))?;
-                print!("🕵️ ");
-            } else {
-                stdout.execute(SetForegroundColor(Color::Green))?;
-                print!("⚡ ");
-            }
-            stdout.execute(SetForegroundColor(Color::Blue))?;
-            print!("[{}]", self.working_directory);
-            stdout.execute(SetForegroundColor(Color::Yellow))?;
-            print!(" > ");
-            stdout.execute(ResetColor)?;
-            stdout.flush()?;
-
-            // Read a line of input
-            let mut input = String::new();
-            stdin.read_line(&mut input)?;
+            // Styled prompt
+            let prompt = format!(
+                "{}{}{}[{}]{} > {}",
+                SetForegroundColor(if is_incognito { Color::Magenta } else { Color::Green }),
+                if is_incognito { "🕵️ " } else { "⚡ " },
+                SetForegroundColor(Color::Blue),
+                self.working_directory,
+                SetForegroundColor(Color::Yellow),
+                ResetColor,
+            );
+
+            let input = match self.read_input(&prompt)? {
+                ReadLine::Line(line) => line,
+                ReadLine::Interrupted => continue,
+                ReadLine::Eof => {
+                    stdout.execute(SetForegroundColor(Color::Green))?;
+                    println!("👋 Goodbye!");
+                    stdout.execute(ResetColor)?;
+                    break Ok(());
+                }
+            };
 
             // Trim the input
             let input = input.trim();
 
-            // Record the command
-            if let Ok(mut guard) = self.event_recorder.lock() {
-                for c in input.chars() {
-                    guard.record_key_press(&c.to_string())?;
-                }
-            }
-
             // Skip empty input
             if input.is_empty() {
                 continue;
@@ -178,7 +227,7 @@ impl TerminalEmulator {
             // Add command to history if not empty
             if !input.is_empty() {
                 // Add to history, removing oldest if at capacity
-                if self.command_history.len() >= 100 {
+                if self.command_history.len() >= HISTORY_SIZE {
                     self.command_history.pop_front();
                 }
                 self.command_history.push_back(input.to_string());
@@ -212,155 +261,530 @@ impl TerminalEmulator {
                 stdout.execute(ResetColor)?;
                 continue;
             } else {
-                // Special handling for directory change commands
-                if input == "cd" || input == "cd ~" {
-                    // Change to home directory
-                    let home = std::env::var("USERPROFILE")
-                        .or_else(|_| std::env::var("HOME"))
-                        .unwrap_or_else(|_| ".".to_string());
-
-                    if let Err(e) = std::env::set_current_dir(&home) {
-                        println!("Error changing to home directory: {}", e);
-                    } else {
-                        // Update working directory if successful
-                        if let Ok(new_dir) = std::env::current_dir() {
-                            self.working_directory = new_dir.to_string_lossy().to_string();
-                        }
+                self.run_line(input).await?;
+            }
+        };
+
+        // Cleanup: stop file watching and the shell
+        self.stop_file_watching().await;
+        if let Some(mut shell) = self.shell.take() {
+            shell.kill();
+        }
+
+        result
+    }
+
+    /// Run one command without the prompt, as `timeloop exec` does for CI and
+    /// scripts. A single argument is handed to the shell, so it may be a
+    /// pipeline or a whole script; several are run as a program and its
+    /// arguments. Output passes straight through with stdout and stderr kept
+    /// apart, and the command and the file changes it makes are recorded.
+    pub async fn exec(&mut self, argv: &[String]) -> crate::Result<CommandRecord> {
+        let (program, args) = match argv {
+            [] => {
+                return Err(TimeLoopError::CommandExecution("No command given".to_string()));
+            }
+            [command] => self.shell_kind.adapter().one_shot_command(command),
+            [program, args @ ..] => (program.as_str(), args.to_vec()),
+        };
+        let command = match argv {
+            [command] => command.clone(),
+            _ => argv.iter().map(|word| quote_word(word)).collect::<Vec<_>>().join(" "),
+        };
+
+        if let Err(e) = self.start_file_watching().await {
+            eprintln!("Warning: Could not start file watching: {}", e);
+        }
+        // The watcher registers its paths on a thread of its own
+        tokio::time::sleep(FILE_WATCH_SETTLE).await;
+
+        let result = self.execute_piped(&command, program, &args).await;
+
+        tokio::time::sleep(FILE_WATCH_SETTLE).await;
+        self.stop_file_watching().await;
+
+        let record = result?;
+        if let Ok(mut guard) = self.event_recorder.lock() {
+            guard.record_command_execution(record.clone())?;
+            // `exec` usually exits right after recording
+            guard.storage().flush()?;
+        }
+        Ok(record)
+    }
+
+    /// Read a command at the prompt. On a terminal this uses the line editor,
+    /// recording every key press; otherwise a plain line is read from stdin.
+    fn read_input(&mut self, prompt: &str) -> crate::Result<ReadLine> {
+        if !io::stdin().is_terminal() {
+            print!("{}", prompt);
+            io::stdout().flush()?;
+            let mut input = String::new();
+            if io::stdin().read_line(&mut input)? == 0 {
+                return Ok(ReadLine::Eof);
+            }
+            if let Ok(mut guard) = self.event_recorder.lock() {
+                for c in input.trim().chars() {
+                    guard.record_key_press(&c.to_string())?;
+                }
+            }
+            return Ok(ReadLine::Line(input));
+        }
+
+        let _raw_mode = RawModeGuard::enable();
+        let recorder = self.event_recorder.clone();
+        let builtins: Vec<&str> = BUILTIN_COMMANDS.iter().chain(META_COMMANDS).copied().collect();
+        let completer = Completer::new(&self.working_directory, &builtins);
+        line_editor::read_line(prompt, &self.command_history, &completer, &mut |key| {
+            if let Ok(mut guard) = recorder.lock() {
+                guard.record_key_press(&line_editor::key_name(key))?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Run a line typed at the prompt: a meta-command, or else a command for
+    /// the session's shell, including `cd` and lines like `: > file`
+    async fn run_line(&mut self, input: &str) -> crate::Result<()> {
+        if MetaCommand::is_meta_command(input) {
+            // A failed meta-command shouldn't end the session
+            let result = match MetaCommand::parse(input) {
+                Ok(command) => self.run_meta_command(command).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                let mut stdout = io::stdout();
+                stdout.execute(SetForegroundColor(Color::Red))?;
+                println!("❌ {}", e);
+                stdout.execute(ResetColor)?;
+            }
+        } else {
+            let record = self.execute_external_command(input).await?;
+            if let Ok(mut guard) = self.event_recorder.lock() {
+                guard.record_command_execution(record)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Run a meta-command against the session being recorded
+    async fn run_meta_command(&mut self, command: MetaCommand) -> crate::Result<()> {
+        let (session_id, storage) = {
+            let guard = self
+                .event_recorder
+                .lock()
+                .map_err(|_| TimeLoopError::EventRecording("Event recorder lock poisoned".to_string()))?;
+            (guard.session_id().to_string(), guard.storage().clone())
+        };
+
+        match command {
+            MetaCommand::Mark { name, note } => {
+                let event = storage.get_last_event(&session_id)?.ok_or_else(|| {
+                    TimeLoopError::CommandExecution("Nothing has been recorded yet".to_string())
+                })?;
+                let bookmark = BookmarkManager::with_storage(storage).add(
+                    &session_id,
+                    &event.id,
+                    name.as_deref(),
+                    &note,
+                )?;
+                println!("🔖 Bookmarked: {}", bookmark.label());
+            }
+            MetaCommand::Branch { name } => {
+                let branch_point_id = storage
+                    .get_last_event(&session_id)?
+                    .map(|e| e.id)
+                    .unwrap_or_else(|| "0".to_string());
+                let new_session_id =
+                    SessionManager::with_storage(storage.clone()).create_branch(&session_id, &name)?;
+                let timeline_branch_id = BranchManager::with_storage(storage)
+                    .create_branch(&session_id, &name, &branch_point_id, None)?;
+                println!(
+                    "✅ Created branch '{}' with session ID: {} and timeline ID: {}",
+                    name, new_session_id, timeline_branch_id
+                );
+            }
+            MetaCommand::Timeline => {
+                let mut events = storage.get_events_for_session(&session_id)?;
+                events.sort_by_key(|e| e.sequence_number);
+                let bookmarks = storage.get_bookmarks_for_session(&session_id)?;
+                let mut key_presses = 0;
+                for e in events {
+                    // Key presses are only shown when bookmarked
+                    let marks: Vec<_> = bookmarks.iter().filter(|b| b.event_id == e.id).collect();
+                    if matches!(e.event_type, EventType::KeyPress { .. }) && marks.is_empty() {
+                        key_presses += 1;
+                        continue;
                     }
-                } else if input == "cd .." {
-                    // Go up one directory
-                    if let Err(e) = std::env::set_current_dir("..") {
-                        println!("Error going up directory: {}", e);
-                    } else {
-                        // Update working directory if successful
-                        if let Ok(new_dir) = std::env::current_dir() {
-                            self.working_directory = new_dir.to_string_lossy().to_string();
-                        }
+                    println!("{} {}", e.timestamp.format("%H:%M:%S"), e.event_type.describe());
+                    for bookmark in marks {
+                        println!("         🔖 {}", bookmark.label());
                     }
-                } else if input.starts_with("cd ") {
-                    // Extract the directory path
-                    let path = input.trim_start_matches("cd ").trim();
+                }
+                println!("({} key presses not shown)", key_presses);
+            }
+            MetaCommand::Summary => {
+                let summary = SessionManager::with_storage(storage).get_session_summary(&session_id)?;
+                println!("📈 Session Summary for: {}", summary.name);
+                print!("{}", summary);
+            }
+            MetaCommand::Replay { commands, speed } => {
+                ReplayEngine::with_storage(&session_id, storage)
+                    .replay_last_commands(commands, speed)
+                    .await?;
+            }
+            MetaCommand::Help => println!("{}", MetaCommand::help()),
+        }
+        Ok(())
+    }
 
-                    // Try to change directory
-                    if let Err(e) = std::env::set_current_dir(path) {
-                        println!("Error changing directory: {}", e);
-                    } else {
-                        // Update working directory if successful
-                        if let Ok(new_dir) = std::env::current_dir() {
-                            self.working_directory = new_dir.to_string_lossy().to_string();
+    /// Run a command in the session's persistent shell. The shell lives in a
+    /// pseudo-terminal so that interactive programs (editors, pagers, `ssh`,
+    /// anything checking `isatty`) behave as they would in a normal terminal,
+    /// and state such as the working directory, exported variables, aliases and
+    /// functions carries over between commands. Output is passed through to the
+    /// screen as it arrives and captured for the command event.
+    async fn execute_external_command(&mut self, command: &str) -> crate::Result<CommandRecord> {
+        let working_directory = self.working_directory.clone();
+        let started_at = Utc::now();
+        if self.shell.is_none() {
+            match ShellSession::start_with_environment(&self.working_directory, self.shell_kind, &self.environment).await {
+                Ok(shell) => self.shell = Some(shell),
+                Err(e) => {
+                    eprintln!("Warning: Could not start a persistent shell ({}); running the command on its own", e);
+                    return self.execute_one_shot(command, started_at).await;
+                }
+            }
+        }
+        let shell = self.shell.as_mut().expect("shell was just started");
+
+        // While the command owns the screen, keystrokes go straight to it
+        let raw_mode = RawModeGuard::enable();
+        let (input_tx, mut input_rx) = mpsc::unbounded_channel();
+        let forwarder = InputForwarder::start(input_tx);
+
+        let mut stdout = io::stdout();
+        let mut stderr = io::stderr();
+        let mut last_byte = None;
+        let result = shell
+            .execute(command, Some(&mut input_rx), &mut |stream, chunk: &[u8]| {
+                let _ = match stream {
+                    OutputStream::Stdout => stdout.write_all(chunk).and_then(|_| stdout.flush()),
+                    OutputStream::Stderr => stderr.write_all(chunk).and_then(|_| stderr.flush()),
+                };
+                last_byte = chunk.last().copied();
+            })
+            .await;
+        forwarder.stop();
+        drop(raw_mode);
+        let result = result?;
+        let ended_at = Utc::now();
+
+        // Keep the prompt on its own line after output like `printf foo`
+        if last_byte.is_some_and(|b| b != b'\n') {
+            println!();
+        }
+
+        self.record_interrupts(command, &result.interrupts)?;
+        self.working_directory = result.working_directory.clone();
+        if let Some(environment) = &result.environment {
+            self.environment = environment.clone();
+        }
+        if result.shell_exited {
+            self.shell = None;
+            stdout.execute(SetForegroundColor(Color::Yellow))?;
+            println!(
+                "⚠️ The shell exited with code {}. A new shell will be started for the next command.",
+                result.exit_code
+            );
+            stdout.execute(ResetColor)?;
+        }
+
+        Ok(CommandRecord {
+            command: command.to_string(),
+            stdout: terminal_output_to_text(&result.output),
+            stderr: terminal_output_to_text(&result.stderr),
+            output_chunks: result.output_chunks,
+            exit_code: result.exit_code,
+            signal: result.signal,
+            core_dumped: result.core_dumped,
+            resource_usage: result.resource_usage,
+            working_directory,
+            environment: result.environment,
+            started_at,
+            ended_at,
+        })
+    }
+
+    /// Run a single command in its own shell process inside a pseudo-terminal.
+    /// Used when the persistent shell cannot be started. Stdout and stderr
+    /// share the terminal and are recorded together.
+    async fn execute_one_shot(
+        &mut self,
+        command: &str,
+        started_at: DateTime<Utc>,
+    ) -> crate::Result<CommandRecord> {
+        let adapter = self.shell_kind.adapter();
+        // Without a shell to report its directory, follow plain `cd`s ourselves
+        if let Some(target) = adapter.directory_change(command) {
+            return Ok(self.change_directory(command, target, started_at));
+        }
+
+        let (program, args) = adapter.one_shot_command(command);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let (mut process, mut output_rx) =
+            PtyProcess::spawn(program, &args, &self.working_directory)?;
+
+        // While the child owns the screen, keystrokes go straight to it
+        let _raw_mode = RawModeGuard::enable();
+        let (input_tx, mut input_rx) = mpsc::unbounded_channel();
+        let forwarder = InputForwarder::start(input_tx);
+
+        let mut stdout = io::stdout();
+        let mut captured = Vec::new();
+        let mut chunks = OutputChunkCollector::new();
+        let mut interrupts = Vec::new();
+        #[cfg(unix)]
+        let signals = process.master_fd().map(SignalForwarder::install);
+        let mut exit_check = tokio::time::interval(Duration::from_millis(20));
+        let exit = loop {
+            tokio::select! {
+                Some(chunk) = output_rx.recv() => {
+                    stdout.write_all(&chunk)?;
+                    stdout.flush()?;
+                    captured.extend_from_slice(&chunk);
+                    chunks.push(OutputStream::Stdout, &chunk);
+                }
+                Some(input) = input_rx.recv() => {
+                    if let PtyInput::Bytes(bytes) = &input {
+                        if let Some(signal) = process.signal_for_input(bytes) {
+                            interrupts.push((signal, Utc::now()));
                         }
                     }
-                } else if input.starts_with("Set-Location ")
-                    || input.starts_with("sl ")
-                    || input.starts_with("chdir ")
-                {
-                    // Extract the directory path from the PowerShell command
-                    let path_start = input.find(' ').map(|i| i + 1).unwrap_or(input.len());
-                    let path = input[path_start..].trim();
-
-                    // Remove quotes if present
-                    let path = path.trim_start_matches('"').trim_end_matches('"');
-
-                    // Try to change directory directly
-                    if let Err(e) = std::env::set_current_dir(path) {
-                        // If direct change fails, execute via PowerShell and show output
-                        let output = self.execute_external_command(input).await?;
-                        if let Ok(mut guard) = self.event_recorder.lock() {
-                            guard.record_command(
-                                input,
-                                &output.output,
-                                output.exit_code,
-                                &self.working_directory,
-                            )?;
-                        }
-                        println!("Error changing directory: {}", e);
-                    } else {
-                        // Record the command but don't execute it again
-                        if let Ok(mut guard) = self.event_recorder.lock() {
-                            guard.record_command(input, "", 0, &self.working_directory)?;
-                        }
-
-                        // Update working directory
-                        if let Ok(new_dir) = std::env::current_dir() {
-                            self.working_directory = new_dir.to_string_lossy().to_string();
+                    // The child may already have exited; its exit is picked up below
+                    let _ = process.apply_input(input);
+                }
+                _ = exit_check.tick() => {
+                    #[cfg(unix)]
+                    if let Some(signals) = &signals {
+                        for signal in signals.take_forwarded() {
+                            interrupts.push((signal, Utc::now()));
                         }
                     }
-                } else {
-                    // For all other commands, just execute them normally
-                    let output = self.execute_external_command(input).await?;
-                    if let Ok(mut guard) = self.event_recorder.lock() {
-                        guard.record_command(
-                            input,
-                            &output.output,
-                            output.exit_code,
-                            &self.working_directory,
-                        )?;
+                    if let Some(exit) = process.try_wait()? {
+                        break exit;
                     }
                 }
             }
         };
+        forwarder.stop();
+        #[cfg(unix)]
+        drop(signals);
+        self.record_interrupts(command, &interrupts)?;
+
+        // Drain whatever the child wrote right before exiting. Background jobs may
+        // keep the PTY open, so stop once the output goes quiet.
+        while let Ok(Some(chunk)) =
+            tokio::time::timeout(Duration::from_millis(50), output_rx.recv()).await
+        {
+            stdout.write_all(&chunk)?;
+            captured.extend_from_slice(&chunk);
+            chunks.push(OutputStream::Stdout, &chunk);
+        }
+        stdout.flush()?;
+
+        Ok(CommandRecord {
+            command: command.to_string(),
+            stdout: terminal_output_to_text(&captured),
+            stderr: String::new(),
+            output_chunks: chunks.finish(),
+            exit_code: exit.exit_code,
+            signal: exit.signal,
+            core_dumped: exit.core_dumped,
+            resource_usage: exit.resource_usage,
+            working_directory: self.working_directory.clone(),
+            environment: None,
+            started_at,
+            ended_at: Utc::now(),
+        })
+    }
 
-        // Cleanup: stop file watching
-        self.stop_file_watching().await;
+    /// Run `program` with its output on pipes rather than a terminal, passing
+    /// stdout and stderr through as they arrive
+    async fn execute_piped(
+        &self,
+        command: &str,
+        program: &str,
+        args: &[String],
+    ) -> crate::Result<CommandRecord> {
+        let started_at = Utc::now();
+        let mut child = std::process::Command::new(program)
+            .args(args)
+            .current_dir(&self.working_directory)
+            .stdin(std::process::Stdio::inherit())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .map_err(|e| TimeLoopError::CommandExecution(format!("Failed to run {}: {}", program, e)))?;
+        let mut stdout_rx = child.stdout.take().map(|out| spawn_reader(Box::new(out)));
+        let mut stderr_rx = child.stderr.take().map(|err| spawn_reader(Box::new(err)));
 
-        disable_raw_mode()?;
-        result
+        let mut stdout = io::stdout();
+        let mut stderr = io::stderr();
+        let mut captured_stdout = Vec::new();
+        let mut captured_stderr = Vec::new();
+        let mut chunks = OutputChunkCollector::new();
+        let mut handle = |stream: OutputStream, chunk: Vec<u8>| -> io::Result<()> {
+            match stream {
+                OutputStream::Stdout => {
+                    stdout.write_all(&chunk)?;
+                    stdout.flush()?;
+                    captured_stdout.extend_from_slice(&chunk);
+                }
+                OutputStream::Stderr => {
+                    stderr.write_all(&chunk)?;
+                    stderr.flush()?;
+                    captured_stderr.extend_from_slice(&chunk);
+                }
+            }
+            chunks.push(stream, &chunk);
+            Ok(())
+        };
+
+        let mut exit_check = tokio::time::interval(Duration::from_millis(20));
+        let mut exit: Option<ProcessExit> = None;
+        // Done once the child has exited and both pipes are closed. Background
+        // jobs may keep a pipe open, so after the exit stop when output goes quiet.
+        while exit.is_none() || stdout_rx.is_some() || stderr_rx.is_some() {
+            let quiet = async {
+                match exit {
+                    Some(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                chunk = recv_pipe(&mut stdout_rx) => match chunk {
+                    Some(chunk) => handle(OutputStream::Stdout, chunk)?,
+                    None => stdout_rx = None,
+                },
+                chunk = recv_pipe(&mut stderr_rx) => match chunk {
+                    Some(chunk) => handle(OutputStream::Stderr, chunk)?,
+                    None => stderr_rx = None,
+                },
+                _ = exit_check.tick(), if exit.is_none() => {
+                    #[cfg(unix)]
+                    {
+                        exit = crate::process::try_wait4(child.id() as i32)?;
+                    }
+                    #[cfg(not(unix))]
+                    {
+                        exit = child
+                            .try_wait()?
+                            .map(|status| ProcessExit::from_code(status.code().unwrap_or(-1)));
+                    }
+                }
+                _ = quiet => break,
+            }
+        }
+        let exit = exit.unwrap_or_else(|| ProcessExit::from_code(-1));
+
+        Ok(CommandRecord {
+            command: command.to_string(),
+            stdout: terminal_output_to_text(&captured_stdout),
+            stderr: terminal_output_to_text(&captured_stderr),
+            output_chunks: chunks.finish(),
+            exit_code: exit.exit_code,
+            signal: exit.signal,
+            core_dumped: exit.core_dumped,
+            resource_usage: exit.resource_usage,
+            working_directory: self.working_directory.clone(),
+            environment: None,
+            started_at,
+            ended_at: Utc::now(),
+        })
     }
 
-    async fn execute_external_command(&self, command: &str) -> crate::Result<CommandOutput> {
-        // Use the appropriate shell based on the platform
-        let mut cmd = if cfg!(target_os = "windows") {
-            // On Windows, use PowerShell with proper arguments to execute commands
-            let mut cmd = Command::new("powershell");
-            // Use -NoProfile to start faster, -ExecutionPolicy Bypass to allow script execution
-            cmd.args([
-                "-NoProfile",
-                "-ExecutionPolicy",
-                "Bypass",
-                "-Command",
-                command,
-            ]);
-            cmd
-        } else {
-            // On Unix systems, use bash with -c to execute commands
-            let mut cmd = Command::new("bash");
-            cmd.args(["-c", command]);
-            cmd
+    /// Change the tracked working directory for a `cd` run without a persistent
+    /// shell. `target` is relative to the current directory; None means home.
+    fn change_directory(
+        &mut self,
+        command: &str,
+        target: Option<String>,
+        started_at: DateTime<Utc>,
+    ) -> CommandRecord {
+        let working_directory = self.working_directory.clone();
+        let home = std::env::var("HOME").or_else(|_| std::env::var("USERPROFILE"));
+        let path = match (target, home) {
+            (Some(dir), _) => PathBuf::from(&self.working_directory).join(dir),
+            (None, Ok(home)) => PathBuf::from(home),
+            (None, Err(_)) => PathBuf::from(&self.working_directory),
+        };
+        let (exit_code, stderr) = match path.canonicalize() {
+            Ok(path) if path.is_dir() => {
+                self.working_directory = path.to_string_lossy().to_string();
+                (0, String::new())
+            }
+            _ => {
+                let message = format!("cd: {}: No such directory", path.display());
+                eprintln!("{}", message);
+                (1, message)
+            }
         };
 
-        cmd.current_dir(&self.working_directory);
-        cmd.stdout(Stdio::piped());
-        cmd.stderr(Stdio::piped());
-
-        let output = cmd
-            .output()
-            .await
-            .map_err(|e| TimeLoopError::CommandExecution(e.to_string()))?;
+        CommandRecord {
+            command: command.to_string(),
+            stdout: String::new(),
+            stderr,
+            output_chunks: Vec::new(),
+            exit_code,
+            signal: None,
+            core_dumped: false,
+            resource_usage: None,
+            working_directory,
+            environment: None,
+            started_at,
+            ended_at: Utc::now(),
+        }
+    }
 
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let stderr = String::from_utf8_lossy(&output.stderr);
+    /// Record the interrupt, quit and suspend signals the user sent to a
+    /// command. They are recorded before the command itself, which is only
+    /// recorded once it has finished.
+    fn record_interrupts(&self, command: &str, interrupts: &[(i32, DateTime<Utc>)]) -> crate::Result<()> {
+        if let Ok(mut guard) = self.event_recorder.lock() {
+            for (signal, at) in interrupts {
+                guard.record_interrupt(command, *signal, *at)?;
+            }
+        }
+        Ok(())
+    }
+}
 
-        let combined_output = if !stderr.is_empty() {
-            format!("{}\n{}", stdout, stderr)
-        } else {
-            stdout.to_string()
-        };
+/// Next chunk from a pipe reader, or never once the pipe has closed
+async fn recv_pipe(rx: &mut Option<mpsc::UnboundedReceiver<Vec<u8>>>) -> Option<Vec<u8>> {
+    match rx {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
 
-        if !combined_output.is_empty() {
-            println!("{}", combined_output);
-        }
+/// Enables raw mode for as long as it is alive when stdin is a terminal
+pub(crate) struct RawModeGuard {
+    enabled: bool,
+}
 
-        Ok(CommandOutput {
-            output: combined_output,
-            exit_code: output.status.code().unwrap_or(-1),
-        })
+impl RawModeGuard {
+    pub(crate) fn enable() -> Self {
+        let enabled = io::stdin().is_terminal() && enable_raw_mode().is_ok();
+        Self { enabled }
     }
 }
 
-#[derive(Debug)]
-struct CommandOutput {
-    output: String,
-    exit_code: i32,
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        if self.enabled {
+            let _ = disable_raw_mode();
+        }
+    }
 }
 
 #[cfg(test)]
@@ -402,9 +826,142 @@ mod tests {
 
         // Test that file watching stops without error
         terminal.stop_file_watching().await;
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_external_command_runs_in_pty() {
+        let tmp_dir = TempDir::new().unwrap();
+        let db_path = tmp_dir.path().join("events_pty.db");
+        let storage = crate::storage::Storage::with_path(db_path.to_str().unwrap()).unwrap();
+        let event_recorder = crate::events::EventRecorder::with_storage("pty-test", storage);
+        let mut terminal = TerminalEmulator::new(event_recorder).unwrap();
+
+        let output = terminal
+            .execute_external_command("[ -t 1 ] && echo interactive; (exit 3)")
+            .await
+            .unwrap();
+        assert_eq!(output.exit_code, 3);
+        assert_eq!(output.stdout.trim(), "interactive");
+        assert!(output.ended_at >= output.started_at);
+
+        // The working directory follows the shell
+        terminal.execute_external_command("cd /").await.unwrap();
+        assert_eq!(terminal.working_directory, "/");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_colon_builtin_reaches_the_shell() {
+        let tmp_dir = TempDir::new().unwrap();
+        let db_path = tmp_dir.path().join("events_colon.db");
+        let storage = crate::storage::Storage::with_path(db_path.to_str().unwrap()).unwrap();
+        let event_recorder = crate::events::EventRecorder::with_storage("colon-test", storage.clone());
+        let mut terminal = TerminalEmulator::new(event_recorder).unwrap();
+        terminal.working_directory = tmp_dir.path().to_string_lossy().to_string();
+
+        terminal.run_line(": > x").await.unwrap();
+        assert!(tmp_dir.path().join("x").exists());
+        let last = storage.get_last_event("colon-test").unwrap().unwrap();
+        assert!(matches!(&last.event_type, EventType::Command { command, .. } if command == ": > x"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_one_shot_fallback_follows_cd() {
+        let tmp_dir = TempDir::new().unwrap();
+        std::fs::create_dir(tmp_dir.path().join("sub")).unwrap();
+        let db_path = tmp_dir.path().join("events_cd.db");
+        let storage = crate::storage::Storage::with_path(db_path.to_str().unwrap()).unwrap();
+        let event_recorder = crate::events::EventRecorder::with_storage("cd-test", storage);
+        let mut terminal = TerminalEmulator::with_shell(event_recorder, ShellKind::Sh).unwrap();
+        terminal.working_directory = tmp_dir.path().canonicalize().unwrap().to_string_lossy().to_string();
+
+        let record = terminal.execute_one_shot("cd sub", Utc::now()).await.unwrap();
+        assert_eq!(record.exit_code, 0);
+        assert!(terminal.working_directory.ends_with("/sub"));
+
+        let record = terminal.execute_one_shot("cd missing", Utc::now()).await.unwrap();
+        assert_eq!(record.exit_code, 1);
+        assert!(terminal.working_directory.ends_with("/sub"));
+
+        let record = terminal.execute_one_shot("pwd", Utc::now()).await.unwrap();
+        assert!(record.stdout.trim().ends_with("/sub"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_exec_records_command_and_file_changes() {
+        let tmp_dir = TempDir::new().unwrap();
+        let work_dir = tmp_dir.path().join("work");
+        std::fs::create_dir(&work_dir).unwrap();
+        let db_path = tmp_dir.path().join("events_exec.db");
+        let storage = crate::storage::Storage::with_path(db_path.to_str().unwrap()).unwrap();
+        let event_recorder = crate::events::EventRecorder::with_storage("exec-test", storage.clone());
+        let mut terminal = TerminalEmulator::new(event_recorder).unwrap();
+        terminal.working_directory = work_dir.to_string_lossy().to_string();
+
+        let record = terminal
+            .exec(&["echo out; echo err >&2; echo data > made.txt; exit 2".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(record.exit_code, 2);
+        assert_eq!(record.stdout, "out\n");
+        assert_eq!(record.stderr, "err\n");
+
+        let events = storage.get_events_for_session("exec-test").unwrap();
+        assert!(events
+            .iter()
+            .any(|e| matches!(&e.event_type, EventType::Command { exit_code: 2, .. })));
+        assert!(events.iter().any(|e| matches!(
+            &e.event_type,
+            EventType::FileChange { path, .. } if path.ends_with("made.txt")
+        )));
+
+        // Several arguments run the program directly, without a shell
+        let record = terminal
+            .exec(&["echo".to_string(), "$HOME".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(record.stdout, "$HOME\n");
+        // and are recorded quoted, so the command line reruns the same way
+        assert_eq!(record.command, "echo '$HOME'");
+        let record = terminal
+            .exec(&["echo".to_string(), "a b".to_string(), "it's".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(record.command, "echo 'a b' 'it'\\''s'");
+    }
 
-        // If we get here, the test passes
-        assert!(true);
+    #[tokio::test]
+    async fn test_meta_commands_mark_and_branch() {
+        let tmp_dir = TempDir::new().unwrap();
+        let db_path = tmp_dir.path().join("events_meta.db");
+        let storage = crate::storage::Storage::with_path(db_path.to_str().unwrap()).unwrap();
+        let mut session_manager = crate::session::SessionManager::with_storage(storage.clone());
+        let session_id = session_manager.create_session("meta-test").unwrap();
+        let mut event_recorder = crate::events::EventRecorder::with_storage(&session_id, storage.clone());
+        event_recorder.record_command("make", "", 0, "/tmp").unwrap();
+        let mut terminal = TerminalEmulator::new(event_recorder).unwrap();
+
+        let mark = MetaCommand::parse(":mark build works").unwrap();
+        terminal.run_meta_command(mark).await.unwrap();
+        let bookmarks = storage.get_bookmarks_for_session(&session_id).unwrap();
+        assert_eq!(bookmarks.len(), 1);
+        assert_eq!(bookmarks[0].note, "build works");
+        let last_event = storage.get_last_event(&session_id).unwrap().unwrap();
+        assert_eq!(bookmarks[0].event_id, last_event.id);
+        let named = MetaCommand::parse(":mark --name green").unwrap();
+        terminal.run_meta_command(named.clone()).await.unwrap();
+        assert!(terminal.run_meta_command(named).await.is_err());
+
+        let branch = MetaCommand::parse(":branch experiment").unwrap();
+        terminal.run_meta_command(branch).await.unwrap();
+        let branches = crate::BranchManager::with_storage(storage.clone())
+            .get_branches_for_session(&session_id)
+            .unwrap();
+        assert_eq!(branches.len(), 1);
+        assert_eq!(branches[0].branch_point_event_id, last_event.id);
     }
 
     #[tokio::test(flavor = "current_thread")]
@@ -419,7 +976,7 @@ mod tests {
         let event_db_path = tmp_dir.path().join("events_bench_rec.db");
         let event_storage = crate::storage::Storage::with_path(event_db_path.to_str().unwrap()).unwrap();
         let event_recorder = crate::events::EventRecorder::with_storage(&session_id, event_storage);
-        let terminal = TerminalEmulator::new(event_recorder).unwrap();
+        let mut terminal = TerminalEmulator::new(event_recorder).unwrap();
 
         // Shared counter
         let counter = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
@@ -0,0 +1,393 @@
+use crate::events::sort_by_command;
+use crate::workspace::WorkspaceState;
+use crate::{Event, EventRecorder, EventType, FileChangeType, Storage, TimeLoopError};
+use similar::TextDiff;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+/// One change `Undo::apply` makes to put a file back
+#[derive(Debug, Clone, PartialEq)]
+pub enum UndoAction {
+    /// Write the contents the file had before the commands, recreating it if
+    /// it was deleted or renamed away
+    Restore { path: PathBuf, content: Vec<u8> },
+    /// Remove a file the commands created
+    Remove { path: PathBuf },
+    /// Remove a directory the commands created, if nothing else is left in it
+    RemoveDir { path: PathBuf },
+}
+
+impl UndoAction {
+    pub fn path(&self) -> &Path {
+        match self {
+            UndoAction::Restore { path, .. }
+            | UndoAction::Remove { path }
+            | UndoAction::RemoveDir { path } => path,
+        }
+    }
+}
+
+/// What undoing the last commands of a session would do
+#[derive(Debug, Default)]
+pub struct UndoPlan {
+    /// The commands being undone, oldest first
+    pub commands: Vec<String>,
+    pub actions: Vec<UndoAction>,
+    /// Files the commands touched whose previous contents weren't recorded,
+    /// e.g. because they were too large; these are left alone
+    pub unknown: Vec<PathBuf>,
+}
+
+impl UndoPlan {
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    /// A unified diff from the files as they are now to how the plan leaves them
+    pub fn diff(&self) -> String {
+        let mut out = String::new();
+        for action in &self.actions {
+            let path = action.path();
+            let (new, new_name) = match action {
+                UndoAction::Restore { content, .. } => (content.clone(), path.display().to_string()),
+                UndoAction::Remove { .. } => (Vec::new(), "/dev/null".to_string()),
+                UndoAction::RemoveDir { .. } => {
+                    out.push_str(&format!("Remove directory {} if empty\n", path.display()));
+                    continue;
+                }
+            };
+            let (old, old_name) = match std::fs::read(path) {
+                Ok(old) => (old, path.display().to_string()),
+                Err(_) => (Vec::new(), "/dev/null".to_string()),
+            };
+            match (std::str::from_utf8(&old), std::str::from_utf8(&new)) {
+                (Ok(old), Ok(new)) => {
+                    let diff = TextDiff::from_lines(old, new);
+                    out.push_str(&diff.unified_diff().header(&old_name, &new_name).to_string());
+                }
+                _ => out.push_str(&format!("Binary file {} differs\n", path.display())),
+            }
+        }
+        out
+    }
+}
+
+/// Puts back the files touched by the last commands of a session, using the
+/// `FileChange` events recorded while they ran
+pub struct Undo {
+    storage: Storage,
+    session_id: String,
+}
+
+impl Undo {
+    pub fn new(session_id: &str) -> crate::Result<Self> {
+        Ok(Self::with_storage(session_id, Storage::new()?))
+    }
+
+    pub fn with_storage(session_id: &str, storage: Storage) -> Self {
+        Self {
+            storage,
+            session_id: session_id.to_string(),
+        }
+    }
+
+    /// Work out how to undo the file effects of the last `count` commands,
+    /// with file changes attributed to commands as by `sort_by_command`
+    pub fn plan(&self, count: usize) -> crate::Result<UndoPlan> {
+        let mut events = self.storage.get_events_for_session(&self.session_id)?;
+        sort_by_command(&mut events);
+
+        let commands: Vec<usize> = events
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| matches!(e.event_type, EventType::Command { .. }))
+            .map(|(i, _)| i)
+            .collect();
+        if count == 0 || count > commands.len() {
+            return Err(TimeLoopError::Configuration(format!(
+                "Cannot undo {} commands; session {} has {}",
+                count,
+                self.session_id,
+                commands.len()
+            )));
+        }
+        let first = commands.len() - count;
+        let cut = match first {
+            0 => 0,
+            _ => commands[first - 1] + 1,
+        };
+        let mut plan = UndoPlan {
+            commands: commands[first..]
+                .iter()
+                .filter_map(|&i| match &events[i].event_type {
+                    EventType::Command { command, .. } => Some(command.clone()),
+                    _ => None,
+                })
+                .collect(),
+            ..UndoPlan::default()
+        };
+
+        let before = WorkspaceState::replay(&events[..cut]);
+        let created = created_paths(&events[cut..]);
+        let touched: Vec<PathBuf> = touched_paths(&events[cut..]);
+        let mut candidates: BTreeSet<PathBuf> = touched.iter().cloned().collect();
+        // Files in a directory that was deleted or moved away
+        candidates.extend(
+            before
+                .files()
+                .keys()
+                .filter(|p| touched.iter().any(|t| p.starts_with(t)))
+                .cloned(),
+        );
+
+        let mut dirs = Vec::new();
+        for path in candidates {
+            match before.files().get(&path) {
+                Some(Some(hash)) => match self.storage.get_blob(hash)? {
+                    Some(content) => {
+                        if std::fs::read(&path).ok().as_ref() != Some(&content) {
+                            plan.actions.push(UndoAction::Restore { path, content });
+                        }
+                    }
+                    None => plan.unknown.push(path),
+                },
+                Some(None) => plan.unknown.push(path),
+                None => match std::fs::symlink_metadata(&path) {
+                    Ok(meta) if meta.is_dir() => {
+                        if created.contains(&path) {
+                            dirs.push(path);
+                        }
+                    }
+                    Ok(_) if created.contains(&path) => plan.actions.push(UndoAction::Remove { path }),
+                    Ok(_) => plan.unknown.push(path),
+                    // Gone, and either created by the commands or never recorded
+                    Err(_) => {}
+                },
+            }
+        }
+        // Deepest first, so nested directories are emptied before their parents
+        dirs.sort_by_key(|d| std::cmp::Reverse(d.components().count()));
+        plan.actions
+            .extend(dirs.into_iter().map(|path| UndoAction::RemoveDir { path }));
+        Ok(plan)
+    }
+
+    /// Carry out `plan` and record it: a `FileChange` event for every file put
+    /// back or removed, then an `Undo` event
+    pub fn apply(&self, plan: &UndoPlan) -> crate::Result<()> {
+        let mut recorder = EventRecorder::with_storage(&self.session_id, self.storage.clone());
+        let mut restored = Vec::new();
+        let mut deleted = Vec::new();
+        for action in &plan.actions {
+            let path = action.path();
+            let name = path.to_string_lossy().to_string();
+            match action {
+                UndoAction::Restore { content, .. } => {
+                    let existed = path.exists();
+                    if let Some(parent) = path.parent() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+                    std::fs::write(path, content)?;
+                    let change = if existed {
+                        FileChangeType::Modified
+                    } else {
+                        FileChangeType::Created
+                    };
+                    recorder.record_file_change(&name, change)?;
+                    restored.push(name);
+                }
+                UndoAction::Remove { .. } => {
+                    std::fs::remove_file(path)?;
+                    recorder.record_file_change(&name, FileChangeType::Deleted)?;
+                    deleted.push(name);
+                }
+                UndoAction::RemoveDir { .. } => {
+                    // Kept when it holds files that weren't recorded
+                    if std::fs::remove_dir(path).is_ok() {
+                        recorder.record_file_change(&name, FileChangeType::Deleted)?;
+                        deleted.push(name);
+                    }
+                }
+            }
+        }
+        recorder.record_undo(plan.commands.clone(), restored, deleted)?;
+        self.storage.flush()
+    }
+}
+
+/// Every path `events` changed, including the old side of renames
+fn touched_paths(events: &[Event]) -> Vec<PathBuf> {
+    let mut paths = BTreeSet::new();
+    for event in events {
+        if let EventType::FileChange {
+            path, change_type, ..
+        } = &event.event_type
+        {
+            paths.insert(PathBuf::from(path));
+            if let FileChangeType::Renamed { old_path } = change_type {
+                if !old_path.is_empty() {
+                    paths.insert(PathBuf::from(old_path));
+                }
+            }
+        }
+    }
+    paths.into_iter().collect()
+}
+
+/// Paths that first appear in `events` by being created or renamed into place,
+/// which are safe to remove again
+fn created_paths(events: &[Event]) -> BTreeSet<PathBuf> {
+    let mut first: BTreeMap<PathBuf, bool> = BTreeMap::new();
+    for event in events {
+        let EventType::FileChange {
+            path, change_type, ..
+        } = &event.event_type
+        else {
+            continue;
+        };
+        if let FileChangeType::Renamed { old_path } = change_type {
+            if !old_path.is_empty() {
+                first.entry(PathBuf::from(old_path)).or_insert(false);
+            }
+        }
+        let created = match change_type {
+            FileChangeType::Created => true,
+            FileChangeType::Renamed { old_path } => !old_path.is_empty(),
+            _ => false,
+        };
+        first.entry(PathBuf::from(path)).or_insert(created);
+    }
+    first
+        .into_iter()
+        .filter(|(_, created)| *created)
+        .map(|(path, _)| path)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CommandRecord;
+    use chrono::{DateTime, Utc};
+    use tempfile::TempDir;
+
+    /// A command that started at `started_at` and has just finished
+    fn ran(command: &str, cwd: &str, started_at: DateTime<Utc>) -> CommandRecord {
+        CommandRecord {
+            command: command.to_string(),
+            stdout: String::new(),
+            stderr: String::new(),
+            output_chunks: Vec::new(),
+            exit_code: 0,
+            signal: None,
+            core_dumped: false,
+            resource_usage: None,
+            working_directory: cwd.to_string(),
+            environment: None,
+            started_at,
+            ended_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_undo_restores_files_touched_by_last_commands() {
+        let tmp_dir = TempDir::new().unwrap();
+        let db_path = tmp_dir.path().join("events_undo.db");
+        let storage = Storage::with_path(db_path.to_str().unwrap()).unwrap();
+        let mut recorder = EventRecorder::with_storage("undo-test", storage.clone());
+        let work = tmp_dir.path().join("work");
+        std::fs::create_dir_all(&work).unwrap();
+        let path = |p: &Path| p.to_str().unwrap().to_string();
+        let cwd = path(&work);
+
+        let config = work.join("config.toml");
+        let notes = work.join("notes.txt");
+        let started = Utc::now();
+        std::fs::write(&config, "debug = false\n").unwrap();
+        recorder.record_file_change(&path(&config), FileChangeType::Created).unwrap();
+        std::fs::write(&notes, "keep me\n").unwrap();
+        recorder.record_file_change(&path(&notes), FileChangeType::Created).unwrap();
+        recorder.record_command_execution(ran("setup", &cwd, started)).unwrap();
+
+        // The command to undo edits one file, moves another and creates a directory
+        let started = Utc::now();
+        std::fs::write(&config, "debug = true\n").unwrap();
+        recorder.record_file_change(&path(&config), FileChangeType::Modified).unwrap();
+        let moved = work.join("notes.md");
+        std::fs::rename(&notes, &moved).unwrap();
+        recorder
+            .record_file_change(&path(&moved), FileChangeType::Renamed { old_path: path(&notes) })
+            .unwrap();
+        let build = work.join("build");
+        std::fs::create_dir(&build).unwrap();
+        recorder.record_file_change(&path(&build), FileChangeType::Created).unwrap();
+        std::fs::write(build.join("out.o"), "obj").unwrap();
+        recorder.record_file_change(&path(&build.join("out.o")), FileChangeType::Created).unwrap();
+        recorder.record_command_execution(ran("make", &cwd, started)).unwrap();
+
+        let undo = Undo::with_storage("undo-test", storage.clone());
+        assert!(undo.plan(3).is_err());
+        let plan = undo.plan(1).unwrap();
+        assert_eq!(plan.commands, vec!["make".to_string()]);
+        assert!(plan.unknown.is_empty());
+        let diff = plan.diff();
+        assert!(diff.contains("-debug = true") && diff.contains("+debug = false"));
+
+        undo.apply(&plan).unwrap();
+        assert_eq!(std::fs::read_to_string(&config).unwrap(), "debug = false\n");
+        assert_eq!(std::fs::read_to_string(&notes).unwrap(), "keep me\n");
+        assert!(!moved.exists() && !build.exists());
+
+        let events = storage.get_events_for_session("undo-test").unwrap();
+        let last = events.iter().max_by_key(|e| e.sequence_number).unwrap();
+        let EventType::Undo { commands, restored, deleted, .. } = &last.event_type else {
+            panic!("expected an Undo event, got {:?}", last.event_type);
+        };
+        assert_eq!(commands, &vec!["make".to_string()]);
+        assert_eq!(restored.len(), 2);
+        assert_eq!(deleted.len(), 3);
+
+        // The timeline now agrees with the files on disk
+        assert!(undo.plan(1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_undo_leaves_late_changes_of_earlier_commands() {
+        let tmp_dir = TempDir::new().unwrap();
+        let db_path = tmp_dir.path().join("events_undo_late.db");
+        let storage = Storage::with_path(db_path.to_str().unwrap()).unwrap();
+        let mut recorder = EventRecorder::with_storage("undo-late", storage.clone());
+        let work = tmp_dir.path().join("work");
+        std::fs::create_dir_all(&work).unwrap();
+        let cwd = work.to_str().unwrap().to_string();
+        let out = work.join("out.txt");
+        let out_path = out.to_str().unwrap();
+
+        std::fs::write(&out, "first\n").unwrap();
+        recorder.record_file_change(out_path, FileChangeType::Created).unwrap();
+        recorder.record_command_execution(ran("write first", &cwd, Utc::now())).unwrap();
+
+        // The watcher delivers the second command's write after it was stored
+        let started = Utc::now();
+        std::fs::write(&out, "second\n").unwrap();
+        recorder.record_command_execution(ran("write second", &cwd, started)).unwrap();
+        recorder.record_file_change(out_path, FileChangeType::Modified).unwrap();
+
+        // Typed well after the settle time, and changing nothing
+        let later = Utc::now() + chrono::Duration::seconds(5);
+        let mut ls = ran("ls", &cwd, later);
+        ls.ended_at = later;
+        recorder.record_command_execution(ls).unwrap();
+
+        let undo = Undo::with_storage("undo-late", storage);
+        assert!(undo.plan(1).unwrap().is_empty());
+        let plan = undo.plan(2).unwrap();
+        assert_eq!(
+            plan.actions,
+            vec![UndoAction::Restore {
+                path: out.clone(),
+                content: b"first\n".to_vec()
+            }]
+        );
+    }
+}
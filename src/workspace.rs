@@ -0,0 +1,293 @@
+use crate::{Event, EventType, FileChangeType, Storage, TimeLoopError};
+use chrono::Utc;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// The watched files of a session as recorded up to some event: each path with
+/// the hash of its contents, or None when the contents weren't recorded
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WorkspaceState {
+    files: BTreeMap<PathBuf, Option<String>>,
+    /// The root of the latest baseline
+    root: Option<PathBuf>,
+}
+
+impl WorkspaceState {
+    /// Replay the `Baseline` and `FileChange` events among `events`, in order
+    pub fn replay<'a>(events: impl IntoIterator<Item = &'a Event>) -> Self {
+        let mut state = Self::default();
+        for event in events {
+            state.apply(event);
+        }
+        state
+    }
+
+    /// Apply one event; anything but a `Baseline` or `FileChange` is ignored.
+    ///
+    /// Some platforms report a rename as two events without the old path, one
+    /// for each side. The contents hash tells them apart: it is only recorded
+    /// for a path that still exists.
+    pub fn apply(&mut self, event: &Event) {
+        let (path, change_type, content_hash) = match &event.event_type {
+            EventType::FileChange {
+                path,
+                change_type,
+                content_hash,
+                ..
+            } => (path, change_type, content_hash),
+            EventType::Baseline { root, files, .. } => {
+                // A baseline lists everything under its root
+                let root = PathBuf::from(root);
+                self.remove(&root);
+                for entry in files {
+                    self.files.insert(root.join(&entry.path), entry.content_hash.clone());
+                }
+                self.root = Some(root);
+                return;
+            }
+            _ => return,
+        };
+        let path = PathBuf::from(path);
+        match change_type {
+            FileChangeType::Deleted => {
+                self.remove(&path);
+            }
+            FileChangeType::Renamed { old_path } => {
+                if !old_path.is_empty() {
+                    self.rename(Path::new(old_path), &path);
+                }
+                match content_hash {
+                    Some(hash) => {
+                        self.files.insert(path, Some(hash.clone()));
+                    }
+                    None if old_path.is_empty() => self.remove(&path),
+                    None => {}
+                }
+            }
+            FileChangeType::Created | FileChangeType::Modified => {
+                // Directories are created implicitly by the files in them
+                if content_hash.is_some() {
+                    self.files.insert(path, content_hash.clone());
+                }
+            }
+        }
+    }
+
+    pub fn files(&self) -> &BTreeMap<PathBuf, Option<String>> {
+        &self.files
+    }
+
+    /// The workspace root used when none is given: the root of the latest
+    /// baseline if every file is under it, else the deepest directory
+    /// containing every file
+    pub fn common_root(&self) -> Option<PathBuf> {
+        if let Some(root) = &self.root {
+            if self.files.keys().all(|p| p.starts_with(root)) {
+                return Some(root.clone());
+            }
+        }
+        let mut paths = self.files.keys();
+        let mut root = paths.next()?.parent()?.to_path_buf();
+        for path in paths {
+            while !path.starts_with(&root) {
+                root = root.parent()?.to_path_buf();
+            }
+        }
+        Some(root)
+    }
+
+    /// Remove `path`, or everything under it if it was a directory
+    fn remove(&mut self, path: &Path) {
+        self.files.retain(|p, _| !p.starts_with(path));
+    }
+
+    fn rename(&mut self, from: &Path, to: &Path) {
+        let moved: Vec<_> = self
+            .files
+            .keys()
+            .filter(|p| p.starts_with(from))
+            .cloned()
+            .collect();
+        for old in moved {
+            let hash = self.files.remove(&old).flatten();
+            let new = match old.strip_prefix(from) {
+                Ok(rest) if !rest.as_os_str().is_empty() => to.join(rest),
+                _ => to.to_path_buf(),
+            };
+            self.files.insert(new, hash);
+        }
+    }
+}
+
+/// What `Workspace::checkout` wrote
+#[derive(Debug, Default)]
+pub struct CheckoutReport {
+    /// Files written, relative to the target directory
+    pub written: Vec<PathBuf>,
+    /// Files whose contents weren't kept, e.g. because they were too large
+    pub missing: Vec<PathBuf>,
+    /// Files outside the workspace root, which were skipped
+    pub outside_root: Vec<PathBuf>,
+}
+
+/// Rebuilds a session's watched files as they were at any event
+pub struct Workspace {
+    storage: Storage,
+    session_id: String,
+}
+
+impl Workspace {
+    pub fn new(session_id: &str) -> crate::Result<Self> {
+        Ok(Self::with_storage(session_id, Storage::new()?))
+    }
+
+    pub fn with_storage(session_id: &str, storage: Storage) -> Self {
+        Self {
+            storage,
+            session_id: session_id.to_string(),
+        }
+    }
+
+    /// The session's events in order, up to and including the one `at` names:
+    /// an event ID, or an RFC 3339 timestamp meaning the last event at or
+    /// before that time
+    pub fn events_until(&self, at: &str) -> crate::Result<Vec<Event>> {
+        let mut events = self.storage.get_events_for_session(&self.session_id)?;
+        events.sort_by_key(|e| e.sequence_number);
+
+        let end = match events.iter().position(|e| e.id == at) {
+            Some(index) => index + 1,
+            None => {
+                let at = chrono::DateTime::parse_from_rfc3339(at)
+                    .map_err(|_| {
+                        TimeLoopError::Configuration(format!(
+                            "{} is neither an event of session {} nor an RFC 3339 timestamp",
+                            at, self.session_id
+                        ))
+                    })?
+                    .with_timezone(&Utc);
+                events.iter().take_while(|e| e.timestamp <= at).count()
+            }
+        };
+        events.truncate(end);
+        Ok(events)
+    }
+
+    pub fn state_at(&self, at: &str) -> crate::Result<WorkspaceState> {
+        Ok(WorkspaceState::replay(&self.events_until(at)?))
+    }
+
+    /// Write the watched files as they were `at` an event into `into`, which
+    /// must be empty or not exist yet. Paths are made relative to `root`, by
+    /// default `WorkspaceState::common_root`.
+    pub fn checkout(&self, at: &str, into: &Path, root: Option<&Path>) -> crate::Result<CheckoutReport> {
+        self.write_state(&self.state_at(at)?, into, root)
+    }
+
+    /// Write the files of `state` into `into`, as `checkout` does
+    pub fn write_state(
+        &self,
+        state: &WorkspaceState,
+        into: &Path,
+        root: Option<&Path>,
+    ) -> crate::Result<CheckoutReport> {
+        if into.exists() && std::fs::read_dir(into)?.next().is_some() {
+            return Err(TimeLoopError::Configuration(format!(
+                "{} is not empty; check out into a new directory",
+                into.display()
+            )));
+        }
+        std::fs::create_dir_all(into)?;
+
+        let root = match root {
+            Some(root) => Some(root.to_path_buf()),
+            None => state.common_root(),
+        };
+        let mut report = CheckoutReport::default();
+        for (path, hash) in state.files() {
+            let Some(relative) = root.as_deref().and_then(|root| path.strip_prefix(root).ok()) else {
+                report.outside_root.push(path.clone());
+                continue;
+            };
+            let content = match hash {
+                Some(hash) => self.storage.get_blob(hash)?,
+                None => None,
+            };
+            let Some(content) = content else {
+                report.missing.push(relative.to_path_buf());
+                continue;
+            };
+            let target = into.join(relative);
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&target, content)?;
+            report.written.push(relative.to_path_buf());
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EventRecorder;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_checkout_replays_file_changes() {
+        let tmp_dir = TempDir::new().unwrap();
+        let db_path = tmp_dir.path().join("events_checkout.db");
+        let storage = Storage::with_path(db_path.to_str().unwrap()).unwrap();
+        let mut recorder = EventRecorder::with_storage("checkout-test", storage.clone());
+
+        let work = tmp_dir.path().join("work");
+        std::fs::create_dir_all(work.join("src")).unwrap();
+        let main_rs = work.join("src/main.rs");
+        let notes = work.join("notes.txt");
+        let path = |p: &Path| p.to_str().unwrap().to_string();
+
+        std::fs::write(&main_rs, "v1").unwrap();
+        recorder.record_file_change(&path(&main_rs), FileChangeType::Created).unwrap();
+        std::fs::write(&notes, "todo").unwrap();
+        recorder.record_file_change(&path(&notes), FileChangeType::Created).unwrap();
+        let checkpoint = storage.get_last_event("checkout-test").unwrap().unwrap().id;
+
+        std::fs::write(&main_rs, "v2").unwrap();
+        recorder.record_file_change(&path(&main_rs), FileChangeType::Modified).unwrap();
+        let renamed = work.join("done.txt");
+        std::fs::rename(&notes, &renamed).unwrap();
+        recorder
+            .record_file_change(&path(&renamed), FileChangeType::Renamed { old_path: path(&notes) })
+            .unwrap();
+
+        let workspace = Workspace::with_storage("checkout-test", storage);
+        let into = tmp_dir.path().join("then");
+        let report = workspace.checkout(&checkpoint, &into, Some(&work)).unwrap();
+        assert_eq!(report.written.len(), 2);
+        assert_eq!(std::fs::read_to_string(into.join("src/main.rs")).unwrap(), "v1");
+        assert_eq!(std::fs::read_to_string(into.join("notes.txt")).unwrap(), "todo");
+
+        let now = tmp_dir.path().join("now");
+        let report = workspace.checkout(&Utc::now().to_rfc3339(), &now, None).unwrap();
+        assert!(report.missing.is_empty() && report.outside_root.is_empty());
+        assert_eq!(std::fs::read_to_string(now.join("src/main.rs")).unwrap(), "v2");
+        assert_eq!(std::fs::read_to_string(now.join("done.txt")).unwrap(), "todo");
+        assert!(!now.join("notes.txt").exists());
+
+        // Files that were there before watching started come from the baseline
+        let untouched = work.join("README.md");
+        std::fs::write(&untouched, "readme").unwrap();
+        recorder.record_baseline(&work, &[untouched.clone(), main_rs.clone()]).unwrap();
+        let state = workspace.state_at(&Utc::now().to_rfc3339()).unwrap();
+        assert_eq!(state.files().len(), 2);
+        assert_eq!(state.common_root(), Some(work.clone()));
+        let based = tmp_dir.path().join("based");
+        workspace.checkout(&Utc::now().to_rfc3339(), &based, None).unwrap();
+        assert_eq!(std::fs::read_to_string(based.join("README.md")).unwrap(), "readme");
+
+        // Never writes over existing files
+        assert!(workspace.checkout(&checkpoint, &now, None).is_err());
+        assert!(workspace.checkout("no-such-event", &tmp_dir.path().join("x"), None).is_err());
+    }
+}